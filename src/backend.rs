@@ -0,0 +1,544 @@
+//! PDF rendering backends.
+//!
+//! Every backend takes the generated HTML (staged in the run's private temp
+//! directory, with a `<base>` pointing back at the source document so relative
+//! resources resolve) plus `PrintOptions` and hands back the PDF bytes.
+
+use crate::cdp::{Browser, CdpError};
+use crate::child::output_with_timeout;
+use crate::config::{length_to_inches, Config};
+use crate::cover::Cover;
+use crate::images::ImageResolver;
+use crate::markdown::Document;
+use crate::native::NativeBackend;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+const BROWSER_PATHS: [&str; 4] = [
+    r"C:\Program Files (x86)\Microsoft\Edge\Application\msedge.exe",
+    r"C:\Program Files\Microsoft\Edge\Application\msedge.exe",
+    r"C:\Program Files\Google\Chrome\Application\chrome.exe",
+    r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe",
+];
+
+const BROWSER_COMMANDS: [&str; 5] = [
+    "msedge",
+    "chrome",
+    "google-chrome",
+    "chromium",
+    "chromium-browser",
+];
+
+pub const BACKEND_NAMES: [&str; 5] = ["auto", "chromium", "weasyprint", "wkhtmltopdf", "native"];
+
+/// Page geometry and print settings shared by all backends.
+#[derive(Debug, Clone)]
+pub struct PrintOptions {
+    pub paper_width: f64,
+    pub paper_height: f64,
+    pub margin: f64,
+    pub print_background: bool,
+    pub outline: bool,
+    pub header_template: String,
+    pub footer_template: String,
+    pub settle_ms: u64,
+    pub timeout: Duration,
+}
+
+impl PrintOptions {
+    pub fn from_config(config: &Config) -> Self {
+        let (paper_width, paper_height) = config.print.paper_inches();
+
+        PrintOptions {
+            paper_width,
+            paper_height,
+            margin: length_to_inches(&config.page.margin).unwrap_or(1.0),
+            print_background: config.print.print_background,
+            outline: config.print.outline,
+            header_template: config.print.header_template.clone(),
+            footer_template: config.print.footer_template.clone(),
+            settle_ms: config.print.settle_ms,
+            timeout: Duration::from_secs(config.print.timeout_secs.max(1)),
+        }
+    }
+}
+
+/// What a backend is asked to render. HTML backends print the page staged at
+/// `html_path`; the native backend lays out `document` itself. `base_dir` is the source
+/// document's directory, `images` resolves image paths against it, and
+/// `work_dir` is scratch space owned by this run.
+pub struct RenderJob<'a> {
+    pub html_path: &'a Path,
+    pub document: &'a Document<'a>,
+    pub header_text: &'a str,
+    pub cover: Option<&'a Cover>,
+    pub base_dir: &'a Path,
+    pub images: &'a ImageResolver,
+    pub work_dir: &'a Path,
+    pub options: &'a PrintOptions,
+}
+
+pub trait PdfBackend {
+    fn name(&self) -> &'static str;
+
+    fn render(&self, job: &RenderJob) -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+/// A failure that may not happen again: a renderer that crashed or timed out.
+/// Missing programs, unreadable files and bad settings fail the same way
+/// every time and are not retried.
+#[derive(Debug)]
+pub struct TransientError(String);
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for TransientError {}
+
+fn is_transient(e: &(dyn Error + 'static)) -> bool {
+    if e.is::<TransientError>() {
+        return true;
+    }
+    match e.downcast_ref::<CdpError>() {
+        Some(CdpError::Io(e)) => !matches!(
+            e.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+        ),
+        Some(CdpError::Handshake(_) | CdpError::Protocol(_) | CdpError::Timeout(_)) => true,
+        // A page that failed to navigate or print will fail again.
+        Some(CdpError::Command { .. }) | None => false,
+    }
+}
+
+/// Renders `job`, retrying up to `retries` more times after a transient
+/// failure. The wait between attempts starts at `backoff` and doubles each
+/// time.
+pub fn render_with_retries(
+    backend: &dyn PdfBackend,
+    job: &RenderJob,
+    retries: u32,
+    backoff: Duration,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut delay = backoff;
+
+    for attempt in 1.. {
+        match backend.render(job) {
+            Ok(pdf) => return Ok(pdf),
+            Err(e) if attempt <= retries && is_transient(e.as_ref()) => {
+                eprintln!(
+                    "Warning: attempt {} of {} failed: {}",
+                    attempt,
+                    retries + 1,
+                    e
+                );
+                eprintln!("Retrying in {:.1}s...", delay.as_secs_f64());
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!("the retry loop only exits by returning")
+}
+
+/// Picks a backend by name. `auto` prefers Chromium, then the command-line
+/// renderers found on `PATH`, and finally the built-in native renderer.
+pub fn select_backend(name: &str, config: &Config) -> Result<Box<dyn PdfBackend>, String> {
+    match name {
+        "auto" => {
+            if let Some(path) = find_browser() {
+                return Ok(Box::new(ChromiumBackend::new(path)));
+            }
+            if let Some(path) = find_on_path("weasyprint") {
+                return Ok(Box::new(WeasyPrintBackend { path }));
+            }
+            if let Some(path) = find_on_path("wkhtmltopdf") {
+                return Ok(Box::new(WkhtmltopdfBackend { path }));
+            }
+            eprintln!("Warning: no browser or HTML renderer found, using the native backend");
+            Ok(Box::new(NativeBackend::new(config.clone())))
+        }
+        "chromium" => find_browser()
+            .map(|path| Box::new(ChromiumBackend::new(path)) as Box<dyn PdfBackend>)
+            .ok_or_else(|| {
+                "Could not find Edge or Chrome. Please install Microsoft Edge or Chrome."
+                    .to_string()
+            }),
+        "weasyprint" => find_on_path("weasyprint")
+            .map(|path| Box::new(WeasyPrintBackend { path }) as Box<dyn PdfBackend>)
+            .ok_or_else(|| "Could not find weasyprint on PATH".to_string()),
+        "wkhtmltopdf" => find_on_path("wkhtmltopdf")
+            .map(|path| Box::new(WkhtmltopdfBackend { path }) as Box<dyn PdfBackend>)
+            .ok_or_else(|| "Could not find wkhtmltopdf on PATH".to_string()),
+        "native" => Ok(Box::new(NativeBackend::new(config.clone()))),
+        other => Err(format!(
+            "Unknown backend '{}'. Expected one of: {}",
+            other,
+            BACKEND_NAMES.join(", ")
+        )),
+    }
+}
+
+fn find_browser() -> Option<PathBuf> {
+    BROWSER_PATHS
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+        .or_else(|| BROWSER_COMMANDS.iter().find_map(|name| find_on_path(name)))
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    let file_name = if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    };
+
+    env::split_paths(&paths)
+        .map(|dir| dir.join(&file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// An absolute `file://` URL, percent-encoding spaces and non-ASCII names.
+pub fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().to_string();
+    let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+    let path = path.replace('\\', "/");
+
+    let mut url = String::from("file:///");
+    for byte in path.trim_start_matches('/').bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+fn run_renderer(command: &mut Command, timeout: Duration) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = output_with_timeout(command, timeout).map_err(|e| -> Box<dyn Error> {
+        if e.kind() == io::ErrorKind::TimedOut {
+            Box::new(TransientError(format!(
+                "{} (raise print.timeout_secs for slow documents)",
+                e
+            )))
+        } else {
+            e.into()
+        }
+    })?;
+
+    if output.status.success() && output.stdout.starts_with(b"%PDF") {
+        Ok(output.stdout)
+    } else {
+        Err(Box::new(TransientError(format!(
+            "renderer exited with {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))))
+    }
+}
+
+/// Headless Edge or Chrome driven over the DevTools Protocol. Each render
+/// gets its own browser profile inside the job's work directory.
+pub struct ChromiumBackend {
+    browser_path: PathBuf,
+}
+
+impl ChromiumBackend {
+    pub fn new(browser_path: PathBuf) -> Self {
+        ChromiumBackend { browser_path }
+    }
+}
+
+impl PdfBackend for ChromiumBackend {
+    fn name(&self) -> &'static str {
+        "chromium"
+    }
+
+    fn render(&self, job: &RenderJob) -> Result<Vec<u8>, Box<dyn Error>> {
+        eprintln!("Using browser: {}", self.browser_path.display());
+
+        let user_data_dir = job.work_dir.join("browser-profile");
+        let mut browser = Browser::launch(&self.browser_path, &user_data_dir, job.options.timeout)
+            .map_err(timeout_hint)?;
+        let printed = browser.print_to_pdf(&file_url(job.html_path), job.options);
+        let stderr = browser.close();
+
+        match printed {
+            Ok((pdf, diagnostics)) => {
+                diagnostics.report();
+                Ok(pdf)
+            }
+            Err(e) => {
+                if !stderr.trim().is_empty() {
+                    eprintln!("Browser output:\n{}", stderr.trim());
+                }
+                Err(timeout_hint(e))
+            }
+        }
+    }
+}
+
+/// Points users at the setting to raise when a slow document times out.
+fn timeout_hint(e: CdpError) -> Box<dyn Error> {
+    match e {
+        CdpError::Timeout(_) => Box::new(TransientError(format!(
+            "{} (raise print.timeout_secs for slow documents)",
+            e
+        ))),
+        e => e.into(),
+    }
+}
+
+/// WeasyPrint. Page geometry comes from the stylesheet's `@page` rules and
+/// JavaScript (syntax highlighting, captions) is not executed.
+pub struct WeasyPrintBackend {
+    path: PathBuf,
+}
+
+impl PdfBackend for WeasyPrintBackend {
+    fn name(&self) -> &'static str {
+        "weasyprint"
+    }
+
+    fn render(&self, job: &RenderJob) -> Result<Vec<u8>, Box<dyn Error>> {
+        run_renderer(
+            Command::new(&self.path)
+                .arg("--base-url")
+                .arg(job.base_dir)
+                .arg(job.html_path)
+                .arg("-"),
+            job.options.timeout,
+        )
+    }
+}
+
+/// wkhtmltopdf (legacy QtWebKit). CSS paged media support is limited.
+pub struct WkhtmltopdfBackend {
+    path: PathBuf,
+}
+
+impl PdfBackend for WkhtmltopdfBackend {
+    fn name(&self) -> &'static str {
+        "wkhtmltopdf"
+    }
+
+    fn render(&self, job: &RenderJob) -> Result<Vec<u8>, Box<dyn Error>> {
+        let options = job.options;
+        let margin = format!("{}in", options.margin);
+
+        let mut command = Command::new(&self.path);
+        command
+            .arg("--quiet")
+            .arg("--enable-local-file-access")
+            .arg("--print-media-type")
+            .arg("--page-width")
+            .arg(format!("{}in", options.paper_width))
+            .arg("--page-height")
+            .arg(format!("{}in", options.paper_height))
+            .args(["-T", &margin, "-B", &margin, "-L", &margin, "-R", &margin])
+            .arg(if options.outline {
+                "--outline"
+            } else {
+                "--no-outline"
+            })
+            .arg(if options.print_background {
+                "--background"
+            } else {
+                "--no-background"
+            })
+            .arg(job.html_path)
+            .arg("-");
+
+        run_renderer(&mut command, options.timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use crate::markdown::{markdown_to_html, parse_document};
+    use crate::staging::StagingDir;
+    use std::cell::{Cell, RefCell};
+    use std::fs;
+
+    /// Renders nothing: fails with the scripted errors first, then returns a
+    /// placeholder PDF, recording the HTML it was given.
+    #[derive(Default)]
+    struct FakeBackend {
+        failures: RefCell<Vec<Box<dyn Error>>>,
+        attempts: Cell<u32>,
+        html: RefCell<String>,
+    }
+
+    impl FakeBackend {
+        fn failing(failures: Vec<Box<dyn Error>>) -> Self {
+            FakeBackend {
+                failures: RefCell::new(failures),
+                ..FakeBackend::default()
+            }
+        }
+    }
+
+    impl PdfBackend for FakeBackend {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn render(&self, job: &RenderJob) -> Result<Vec<u8>, Box<dyn Error>> {
+            self.attempts.set(self.attempts.get() + 1);
+            let mut failures = self.failures.borrow_mut();
+            if !failures.is_empty() {
+                return Err(failures.remove(0));
+            }
+            let html = fs::read_to_string(job.html_path)?;
+            let pdf = format!("%PDF-1.4\n% {} bytes of HTML\n", html.len());
+            *self.html.borrow_mut() = html;
+            Ok(pdf.into_bytes())
+        }
+    }
+
+    fn transient() -> Box<dyn Error> {
+        Box::new(TransientError("renderer exited with 1".to_string()))
+    }
+
+    /// Runs `markdown` through the HTML pipeline into `backend`.
+    fn render(
+        markdown: &str,
+        backend: &FakeBackend,
+        retries: u32,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let config = get_default_config();
+        let staging = StagingDir::create()?;
+        let base_dir = env::temp_dir();
+        let html_path = staging.path().join("document.html");
+        let document = parse_document(markdown, &config, None);
+        let images = ImageResolver::new(&config.images, &base_dir);
+        let options = PrintOptions::from_config(&config);
+        let body = markdown_to_html(&document, &config, &images);
+        let html = crate::generate_html(&config, &options, "", &body, &file_url(&base_dir));
+        fs::write(&html_path, html)?;
+
+        let job = RenderJob {
+            html_path: &html_path,
+            document: &document,
+            header_text: "",
+            cover: None,
+            base_dir: &base_dir,
+            images: &images,
+            work_dir: staging.path(),
+            options: &options,
+        };
+        render_with_retries(backend, &job, retries, Duration::from_millis(1))
+    }
+
+    #[test]
+    fn renders_markdown_through_the_pipeline() {
+        let backend = FakeBackend::default();
+        let pdf = render("# Title\n\nSome *text*.\n", &backend, 0).unwrap();
+
+        assert!(pdf.starts_with(b"%PDF"));
+        assert_eq!(backend.attempts.get(), 1);
+        let html = backend.html.borrow();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1 id=\"title\">"));
+        assert!(html.contains("<em>text</em>"));
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let backend = FakeBackend::failing(vec![transient(), transient()]);
+        assert!(render("text\n", &backend, 2).is_ok());
+        assert_eq!(backend.attempts.get(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let backend = FakeBackend::failing(vec![transient(), transient(), transient()]);
+        assert!(render("text\n", &backend, 1).is_err());
+        assert_eq!(backend.attempts.get(), 2);
+    }
+
+    #[test]
+    fn does_not_retry_permanent_failures() {
+        let failures: Vec<Box<dyn Error>> = vec![
+            Box::new(io::Error::new(io::ErrorKind::NotFound, "no weasyprint")),
+            Box::new(CdpError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no browser",
+            ))),
+            Box::new(CdpError::Command {
+                method: "Page.navigate".to_string(),
+                message: "net::ERR_FILE_NOT_FOUND".to_string(),
+            }),
+            "unknown font".into(),
+        ];
+        for failure in failures {
+            let backend = FakeBackend::failing(vec![failure]);
+            assert!(render("text\n", &backend, 3).is_err());
+            assert_eq!(backend.attempts.get(), 1);
+        }
+    }
+
+    #[test]
+    fn retries_dropped_browser_connections() {
+        let backend = FakeBackend::failing(vec![
+            Box::new(CdpError::Protocol(
+                "browser closed the connection".to_string(),
+            )),
+            timeout_hint(CdpError::Timeout(
+                "waiting for a DevTools message".to_string(),
+            )),
+        ]);
+        assert!(render("text\n", &backend, 2).is_ok());
+        assert_eq!(backend.attempts.get(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_renderers_that_time_out() {
+        let started = std::time::Instant::now();
+        let error =
+            run_renderer(Command::new("sleep").arg("10"), Duration::from_millis(200)).unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(error.is::<TransientError>());
+        assert!(error.to_string().contains("print.timeout_secs"));
+    }
+
+    #[test]
+    fn does_not_retry_missing_renderers() {
+        let error = run_renderer(
+            &mut Command::new("mandy-no-such-renderer"),
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+        assert!(!is_transient(error.as_ref()));
+    }
+
+    #[test]
+    fn builds_file_urls() {
+        assert_eq!(
+            file_url(Path::new("/home/me/my notes/ü.md")),
+            "file:///home/me/my%20notes/%C3%BC.md"
+        );
+        assert_eq!(
+            file_url(Path::new(r"\\?\C:\Docs\a.md")),
+            "file:///C:/Docs/a.md"
+        );
+    }
+}
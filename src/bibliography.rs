@@ -0,0 +1,607 @@
+//! Reading bibliographies for citations, from BibTeX (`.bib`) or CSL-JSON
+//! (`.json`) files.
+//!
+//! Only the fields a reference list needs are kept. BibTeX values have
+//! `@string` abbreviations expanded, their braces removed and the common
+//! LaTeX accents, letters such as `\ss` and dashes turned into Unicode; other
+//! commands are dropped, keeping their arguments.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// References by citation key.
+pub type Bibliography = HashMap<String, Reference>;
+
+#[derive(Debug, Clone, Default)]
+pub struct Reference {
+    pub authors: Vec<Name>,
+    pub year: String,
+    pub title: String,
+    /// Journal, or the book a chapter or paper appeared in.
+    pub container: String,
+    pub volume: String,
+    pub issue: String,
+    pub pages: String,
+    pub publisher: String,
+    pub doi: String,
+    pub url: String,
+}
+
+/// A person, or an organisation given as one `family` name.
+#[derive(Debug, Clone, Default)]
+pub struct Name {
+    pub family: String,
+    pub given: String,
+}
+
+pub fn load_bibliography(path: &Path) -> Result<Bibliography, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read bibliography '{}': {}", path.display(), e))?;
+
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let result = if is_json {
+        parse_csl_json(&content)
+    } else {
+        parse_bibtex(&content)
+    };
+    result.map_err(|e| format!("Bibliography '{}' is not valid: {}", path.display(), e))
+}
+
+fn parse_csl_json(content: &str) -> Result<Bibliography, String> {
+    let items: Vec<Value> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let mut bibliography = Bibliography::new();
+
+    for item in &items {
+        let Some(key) = item.get("id").map(scalar).filter(|key| !key.is_empty()) else {
+            continue;
+        };
+        let field = |name: &str| item.get(name).map(scalar).unwrap_or_default();
+
+        let names = |role: &str| -> Vec<Name> {
+            item.get(role)
+                .and_then(Value::as_array)
+                .map(|names| {
+                    names
+                        .iter()
+                        .map(|name| match name.get("literal") {
+                            Some(literal) => Name {
+                                family: scalar(literal),
+                                given: String::new(),
+                            },
+                            None => Name {
+                                family: name.get("family").map(scalar).unwrap_or_default(),
+                                given: name.get("given").map(scalar).unwrap_or_default(),
+                            },
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let mut authors = names("author");
+        if authors.is_empty() {
+            authors = names("editor");
+        }
+
+        let issued = item.get("issued");
+        let year = issued
+            .and_then(|issued| issued.pointer("/date-parts/0/0"))
+            .or_else(|| issued.and_then(|issued| issued.get("raw")))
+            .or_else(|| issued.and_then(|issued| issued.get("literal")))
+            .map(scalar)
+            .unwrap_or_default();
+
+        bibliography.insert(
+            key,
+            Reference {
+                authors,
+                year,
+                title: field("title"),
+                container: field("container-title"),
+                volume: field("volume"),
+                issue: field("issue"),
+                pages: field("page"),
+                publisher: field("publisher"),
+                doi: field("DOI"),
+                url: field("URL"),
+            },
+        );
+    }
+
+    Ok(bibliography)
+}
+
+/// A string or number from CSL-JSON as text.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_bibtex(content: &str) -> Result<Bibliography, String> {
+    let mut bibliography = Bibliography::new();
+    // `@string` abbreviations by lowercase name.
+    let mut strings = HashMap::new();
+    let mut rest = content;
+
+    while let Some(at) = rest.find('@') {
+        rest = &rest[at + 1..];
+        let Some(open) = rest.find(['{', '(']) else {
+            break;
+        };
+        let kind = rest[..open].trim().to_ascii_lowercase();
+        let close = if rest[open..].starts_with('{') {
+            '}'
+        } else {
+            ')'
+        };
+        let (body, after) = delimited(&rest[open..], close)
+            .ok_or_else(|| format!("entry '@{}' is never closed", kind))?;
+        rest = after;
+
+        if kind == "string" {
+            let defined = bibtex_fields(body, &strings)
+                .ok_or_else(|| format!("'@string{{{}}}' is malformed", body.trim()))?;
+            strings.extend(defined);
+            continue;
+        }
+        if matches!(kind.as_str(), "comment" | "preamble") {
+            continue;
+        }
+        let Some((key, fields)) = body.split_once(',') else {
+            continue;
+        };
+        let fields = bibtex_fields(fields, &strings)
+            .ok_or_else(|| format!("entry '{}' has a malformed field", key.trim()))?;
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map_or(String::new(), |value| latex_text(value))
+        };
+        // Links are taken as written, where `~` and `--` mean themselves.
+        let link = |name: &str| {
+            fields.get(name).map_or(String::new(), |value| {
+                value.replace(['{', '}'], "").trim().to_string()
+            })
+        };
+
+        let mut authors = fields.get("author").map(|value| bibtex_names(value));
+        if authors.as_ref().is_none_or(Vec::is_empty) {
+            authors = fields.get("editor").map(|value| bibtex_names(value));
+        }
+        let year = match field("year") {
+            year if year.is_empty() => field("date").chars().take(4).collect(),
+            year => year,
+        };
+        let container = ["journal", "journaltitle", "booktitle"]
+            .into_iter()
+            .map(field)
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        let publisher = ["publisher", "institution", "school", "organization"]
+            .into_iter()
+            .map(field)
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+
+        bibliography.insert(
+            key.trim().to_string(),
+            Reference {
+                authors: authors.unwrap_or_default(),
+                year,
+                title: field("title"),
+                container,
+                volume: field("volume"),
+                issue: field("number"),
+                pages: field("pages"),
+                publisher,
+                doi: link("doi"),
+                url: link("url"),
+            },
+        );
+    }
+
+    Ok(bibliography)
+}
+
+/// The text between the opening character at the start of `text` and its
+/// matching `close`, and what follows it. Braces nest.
+fn delimited(text: &str, close: char) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if c == close && depth == 0 => return Some((&text[1..index], &text[index + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `name = {value}, other = "value" # {more}, year = 2020` as raw values by
+/// lowercase name, with bare words defined in `strings` expanded. `None` if
+/// a value is not closed.
+fn bibtex_fields(
+    mut text: &str,
+    strings: &HashMap<String, String>,
+) -> Option<HashMap<String, String>> {
+    let mut fields = HashMap::new();
+
+    loop {
+        text = text.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if text.is_empty() {
+            return Some(fields);
+        }
+        let (name, after) = text.split_once('=')?;
+        text = after;
+
+        let mut value = String::new();
+        loop {
+            text = text.trim_start();
+            let (part, after) = if text.starts_with('{') {
+                delimited(text, '}')?
+            } else if text.starts_with('"') {
+                delimited(text, '"')?
+            } else {
+                let end = text.find([',', '#']).unwrap_or(text.len());
+                let word = text[..end].trim();
+                let word = strings
+                    .get(&word.to_ascii_lowercase())
+                    .map_or(word, String::as_str);
+                (word, &text[end..])
+            };
+            value.push_str(part);
+            text = after.trim_start();
+            match text.strip_prefix('#') {
+                Some(after) => text = after,
+                None => break,
+            }
+        }
+
+        fields.insert(name.trim().to_ascii_lowercase(), value);
+    }
+}
+
+/// Splits a raw `author` field at the `and`s outside braces.
+fn bibtex_names(value: &str) -> Vec<Name> {
+    let mut names = Vec::new();
+    let mut name: Vec<&str> = Vec::new();
+    let mut depth = 0;
+
+    for word in value.split_whitespace() {
+        if word == "and" && depth == 0 {
+            names.push(bibtex_name(&name.join(" ")));
+            name.clear();
+            continue;
+        }
+        depth += word.matches('{').count() as i32 - word.matches('}').count() as i32;
+        name.push(word);
+    }
+    names.push(bibtex_name(&name.join(" ")));
+
+    names.retain(|name| !name.family.is_empty());
+    names
+}
+
+/// `Last, First`, `First Last`, or `{Some Organisation}` taken whole.
+fn bibtex_name(name: &str) -> Name {
+    let name = name.trim();
+    if name.starts_with('{') && delimited(name, '}').is_some_and(|(_, rest)| rest.is_empty()) {
+        return Name {
+            family: latex_text(name),
+            given: String::new(),
+        };
+    }
+
+    match name.split_once(',') {
+        Some((family, given)) => Name {
+            family: latex_text(family),
+            given: latex_text(given),
+        },
+        None => {
+            let name = latex_text(name);
+            match name.rsplit_once(' ') {
+                Some((given, family)) => Name {
+                    family: family.to_string(),
+                    given: given.to_string(),
+                },
+                None => Name {
+                    family: name,
+                    given: String::new(),
+                },
+            }
+        }
+    }
+}
+
+/// Plain text for a LaTeX-flavoured BibTeX value.
+fn latex_text(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => {}
+            '~' => text.push('\u{a0}'),
+            '\\' => match chars.next() {
+                Some(accent @ ('\'' | '"' | '`' | '^' | '~' | '=' | '.')) => {
+                    if let Some(letter) = accent_base(&mut chars) {
+                        text.push_str(&letter);
+                        text.push(combining_mark(accent));
+                    }
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut name = String::from(c);
+                    while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                        name.push(c);
+                    }
+                    while chars.next_if_eq(&' ').is_some() {}
+
+                    if let Some(symbol) = text_symbol(&name) {
+                        text.push_str(symbol);
+                    } else if let Some(mark) = letter_accent(&name) {
+                        if let Some(letter) = accent_base(&mut chars) {
+                            text.push_str(&letter);
+                            text.push(mark);
+                        }
+                    }
+                    // Any other command, such as `\emph`: keep only its
+                    // argument.
+                }
+                Some(c) => text.push(c),
+                None => {}
+            },
+            c if c.is_whitespace() => {
+                if !text.ends_with(' ') {
+                    text.push(' ');
+                }
+            }
+            c => text.push(c),
+        }
+    }
+
+    text.replace("---", "\u{2014}")
+        .replace("--", "\u{2013}")
+        .trim()
+        .to_string()
+}
+
+/// The letter an accent sits on: a character, or a command such as `\i`,
+/// whose dot the accent replaces.
+fn accent_base(chars: &mut Peekable<Chars>) -> Option<String> {
+    while chars.next_if_eq(&'{').is_some() {}
+    match chars.next()? {
+        '\\' => {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                name.push(c);
+            }
+            Some(match name.as_str() {
+                "i" | "j" => name,
+                name => text_symbol(name).unwrap_or_default().to_string(),
+            })
+        }
+        letter => Some(letter.to_string()),
+    }
+}
+
+fn combining_mark(accent: char) -> char {
+    match accent {
+        '\'' => '\u{301}',
+        '"' => '\u{308}',
+        '`' => '\u{300}',
+        '^' => '\u{302}',
+        '~' => '\u{303}',
+        '=' => '\u{304}',
+        _ => '\u{307}',
+    }
+}
+
+/// Letters and symbols written as argument-less commands, such as `\ss`.
+fn text_symbol(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "ss" => "ß",
+        "SS" => "SS",
+        "o" => "ø",
+        "O" => "Ø",
+        "aa" => "å",
+        "AA" => "Å",
+        "ae" => "æ",
+        "AE" => "Æ",
+        "oe" => "œ",
+        "OE" => "Œ",
+        "l" => "ł",
+        "L" => "Ł",
+        "i" => "ı",
+        "j" => "ȷ",
+        "dh" => "ð",
+        "DH" => "Ð",
+        "dj" => "đ",
+        "DJ" => "Đ",
+        "th" => "þ",
+        "TH" => "Þ",
+        "ng" => "ŋ",
+        "NG" => "Ŋ",
+        "textendash" => "\u{2013}",
+        "textemdash" => "\u{2014}",
+        "textquoteleft" => "\u{2018}",
+        "textquoteright" => "\u{2019}",
+        "textellipsis" | "dots" | "ldots" => "\u{2026}",
+        "textregistered" => "®",
+        "texttrademark" => "™",
+        "copyright" | "textcopyright" => "©",
+        "S" => "§",
+        "P" => "¶",
+        "TeX" => "TeX",
+        "LaTeX" => "LaTeX",
+        _ => return None,
+    })
+}
+
+/// Accents written as a letter command before their letter, such as `\c{c}`.
+fn letter_accent(command: &str) -> Option<char> {
+    match command {
+        "c" => Some('\u{327}'),
+        "v" => Some('\u{30c}'),
+        "u" => Some('\u{306}'),
+        "H" => Some('\u{30b}'),
+        "r" => Some('\u{30a}'),
+        "k" => Some('\u{328}'),
+        "d" => Some('\u{323}'),
+        "b" => Some('\u{331}'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(reference: &Reference) -> Vec<(&str, &str)> {
+        reference
+            .authors
+            .iter()
+            .map(|name| (name.family.as_str(), name.given.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn converts_latex_to_text() {
+        assert_eq!(latex_text("Gau\\ss"), "Gauß");
+        assert_eq!(
+            latex_text("Gau{\\ss} and {\\O}stergaard"),
+            "Gauß and Østergaard"
+        );
+        assert_eq!(latex_text("\\AA{}ngstr\\\"{o}m"), "Ångstro\u{308}m");
+        assert_eq!(
+            latex_text("\\L{}ukasiewicz, {\\l}\\ae\\oe"),
+            "Łukasiewicz, łæœ"
+        );
+        assert_eq!(latex_text("Mart\\'{\\i}nez"), "Marti\u{301}nez");
+        assert_eq!(
+            latex_text("Gar\\c{c}on \\v{S}koda"),
+            "Garc\u{327}on S\u{30c}koda"
+        );
+        assert_eq!(latex_text("\\emph{Deep} {Learning}"), "Deep Learning");
+        assert_eq!(
+            latex_text("pages 1--10 --- or~not"),
+            "pages 1\u{2013}10 \u{2014} or\u{a0}not"
+        );
+        assert_eq!(latex_text("R\\&D  in   \\LaTeX"), "R&D in LaTeX");
+    }
+
+    #[test]
+    fn parses_bibtex_entries() {
+        let bibliography = parse_bibtex(
+            r#"
+            @comment{ @article{ignored, title = {Not an entry}} }
+            @string{ jacm = "Journal of the {ACM}" }
+            @STRING(pub = {ACM} # " Press")
+
+            @article{doe2020,
+              author = {Doe, Jane and John {van der} Smith and {Acme Research Group}},
+              title = {The {Nested {Braces}} Problem},
+              journal = jacm,
+              year = 2020,
+              volume = "12", number = {3},
+              pages = {1--10},
+              doi = {10.1000/x--y},
+            }
+
+            @book(lee2019,
+              editor = "Lee, Ann",
+              title = "Caching" # { at } # "Scale",
+              publisher = pub,
+              date = {2019-05-01}
+            )
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bibliography.len(), 2);
+
+        let doe = &bibliography["doe2020"];
+        assert_eq!(
+            names(doe),
+            [
+                ("Doe", "Jane"),
+                ("Smith", "John van der"),
+                ("Acme Research Group", "")
+            ]
+        );
+        assert_eq!(doe.title, "The Nested Braces Problem");
+        assert_eq!(doe.container, "Journal of the ACM");
+        assert_eq!(doe.year, "2020");
+        assert_eq!(doe.volume, "12");
+        assert_eq!(doe.issue, "3");
+        assert_eq!(doe.pages, "1\u{2013}10");
+        assert_eq!(doe.doi, "10.1000/x--y");
+
+        let lee = &bibliography["lee2019"];
+        assert_eq!(names(lee), [("Lee", "Ann")]);
+        assert_eq!(lee.title, "Caching at Scale");
+        assert_eq!(lee.publisher, "ACM Press");
+        assert_eq!(lee.year, "2019");
+    }
+
+    #[test]
+    fn reports_unclosed_bibtex_entries() {
+        assert_eq!(
+            parse_bibtex("@article{doe2020, title = {Open").unwrap_err(),
+            "entry '@article' is never closed"
+        );
+        assert_eq!(
+            parse_bibtex("@article{doe2020, title = {Open}, year = \"2020}").unwrap_err(),
+            "entry 'doe2020' has a malformed field"
+        );
+    }
+
+    #[test]
+    fn parses_csl_json() {
+        let bibliography = parse_csl_json(
+            r#"[
+                {
+                    "id": "doe2020",
+                    "author": [{ "family": "Doe", "given": "Jane" }, { "literal": "Acme" }],
+                    "title": "Caching",
+                    "container-title": "Journal",
+                    "volume": 12,
+                    "page": "1-10",
+                    "issued": { "date-parts": [[2020, 5]] },
+                    "DOI": "10.1000/xyz"
+                },
+                {
+                    "id": "lee2019",
+                    "editor": [{ "family": "Lee", "given": "Ann" }],
+                    "issued": { "raw": "2019" }
+                },
+                { "title": "No id" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(bibliography.len(), 2);
+
+        let doe = &bibliography["doe2020"];
+        assert_eq!(names(doe), [("Doe", "Jane"), ("Acme", "")]);
+        assert_eq!(doe.year, "2020");
+        assert_eq!(doe.volume, "12");
+        assert_eq!(doe.pages, "1-10");
+        assert_eq!(doe.doi, "10.1000/xyz");
+
+        let lee = &bibliography["lee2019"];
+        assert_eq!(names(lee), [("Lee", "Ann")]);
+        assert_eq!(lee.year, "2019");
+    }
+
+    #[test]
+    fn rejects_invalid_csl_json() {
+        assert!(parse_csl_json("{\"id\": \"not a list\"}").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Book mode: many markdown files combined into one PDF.
+//!
+//! Chapters come from the command line, from the links in an mdBook-style
+//! `SUMMARY.md`, or from `book.files` in the config. Each chapter is parsed
+//! on its own, so its heading ids, links and image paths can be fixed up
+//! against its own file, and the chapters are then numbered together as one
+//! document with a single table of contents.
+
+use crate::bibliography::Bibliography;
+use crate::config::{BookConfig, Config};
+use crate::images::{is_external, percent_decode};
+use crate::links::{markdown_target, rewrite_markdown_links, LinkTarget};
+use crate::markdown::{parse_events, Document, PAGE_BREAK};
+use crate::sections::{assign_heading_ids, TOC};
+use pulldown_cmark::{Event, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SUMMARY: &str = "SUMMARY.md";
+
+/// The chapter files, in order: `inputs` from the command line (or a single
+/// `SUMMARY.md` listing them), else `book.files`.
+pub fn chapter_files(inputs: &[String], config: &BookConfig) -> Result<Vec<PathBuf>, String> {
+    let files = if inputs.is_empty() {
+        &config.files
+    } else {
+        inputs
+    };
+
+    match files {
+        [] => Err("no chapters given; pass the files, a SUMMARY.md, or set book.files".to_string()),
+        [summary] if is_summary(summary) => summary_chapters(Path::new(summary)),
+        files => files
+            .iter()
+            .map(|file| chapter_path(Path::new(file)))
+            .collect(),
+    }
+}
+
+fn is_summary(file: &str) -> bool {
+    Path::new(file)
+        .file_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case(SUMMARY))
+}
+
+fn chapter_path(file: &Path) -> Result<PathBuf, String> {
+    fs::canonicalize(file).map_err(|e| format!("Chapter '{}' not found: {}", file.display(), e))
+}
+
+/// Every markdown file `SUMMARY.md` links to, in order. Draft chapters
+/// (links without a target) are skipped.
+fn summary_chapters(summary: &Path) -> Result<Vec<PathBuf>, String> {
+    let content = fs::read_to_string(summary)
+        .map_err(|e| format!("Failed to read '{}': {}", summary.display(), e))?;
+    let dir = summary.parent().unwrap_or(Path::new("."));
+
+    let mut chapters = Vec::new();
+    let mut seen = HashSet::new();
+    for event in parse_events(&content) {
+        let Event::Start(Tag::Link { dest_url, .. }) = event else {
+            continue;
+        };
+        let Some((path, _, _)) = markdown_target(&dest_url) else {
+            continue;
+        };
+
+        let chapter = chapter_path(&dir.join(percent_decode(path)))?;
+        if seen.insert(chapter.clone()) {
+            chapters.push(chapter);
+        }
+    }
+
+    if chapters.is_empty() {
+        return Err(format!(
+            "'{}' does not link to any chapters",
+            summary.display()
+        ));
+    }
+    Ok(chapters)
+}
+
+/// Combines the chapters into one document. `sources` is the markdown of
+/// every chapter in `chapters`.
+pub fn assemble<'a>(
+    chapters: &[PathBuf],
+    sources: &'a [String],
+    config: &Config,
+    bibliography: Option<&Bibliography>,
+) -> Document<'a> {
+    // Heading ids are made unique across the whole book before any links are
+    // rewritten, so links can point forward into later chapters.
+    let mut used_ids = HashSet::new();
+    let mut parsed = Vec::new();
+    let mut targets = HashMap::new();
+    for (chapter, source) in chapters.iter().zip(sources) {
+        let mut events = parse_events(source);
+        let ids = assign_heading_ids(&mut events, &mut used_ids);
+        let anchor = events.iter().find_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        });
+
+        targets.insert(chapter.clone(), LinkTarget::Chapter { anchor, ids });
+        parsed.push(events);
+    }
+
+    let auto_toc =
+        config.book.toc && chapters.len() > 1 && !sources.iter().any(|source| source.contains(TOC));
+
+    let mut events = Vec::new();
+    for (index, (chapter, chapter_events)) in chapters.iter().zip(parsed).enumerate() {
+        let dir = chapter.parent().unwrap_or(Path::new("."));
+        let chapter_events =
+            rewrite_markdown_links(chapter_events, &config.links, dir, &targets, Some(chapter));
+        let chapter_events = absolute_image_paths(chapter_events, dir);
+
+        if index > 0 && config.book.chapter_page_break && !breaks_page(&chapter_events, config) {
+            events.push(Event::Html(PAGE_BREAK.into()));
+        }
+        events.extend(chapter_events);
+
+        // The first chapter is the title page; the contents follow it.
+        if index == 0 && auto_toc {
+            events.extend([
+                Event::Html(PAGE_BREAK.into()),
+                Event::Start(Tag::Paragraph),
+                Event::Text(TOC.into()),
+                Event::End(TagEnd::Paragraph),
+            ]);
+        }
+    }
+
+    Document::new(events, config, bibliography)
+}
+
+/// Whether the chapter starts with a heading that already begins a new page.
+fn breaks_page(events: &[Event], config: &Config) -> bool {
+    match events.first() {
+        Some(Event::Start(Tag::Heading { level, .. })) => {
+            config.headings.page_break_before(*level as u8)
+        }
+        _ => false,
+    }
+}
+
+/// Relative image paths are relative to the chapter's own file, which need
+/// not be where the book is rendered from.
+fn absolute_image_paths<'a>(events: Vec<Event<'a>>, dir: &Path) -> Vec<Event<'a>> {
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) if !is_external(&dest_url) && !dest_url.is_empty() => {
+                let (path, suffix) =
+                    dest_url.split_at(dest_url.find(['?', '#']).unwrap_or(dest_url.len()));
+                let absolute = dir.join(percent_decode(path));
+                // Keep characters that `ImageResolver` would decode intact.
+                let absolute = absolute
+                    .display()
+                    .to_string()
+                    .replace('%', "%25")
+                    .replace('#', "%23")
+                    .replace('?', "%3F");
+
+                Event::Start(Tag::Image {
+                    link_type,
+                    dest_url: format!("{}{}", absolute, suffix).into(),
+                    title,
+                    id,
+                })
+            }
+            event => event,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use crate::staging::StagingDir;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_chapters_from_the_summary() {
+        let staging = StagingDir::create().unwrap();
+        let dir = fs::canonicalize(staging.path()).unwrap();
+        let intro = write(&dir, "intro.md", "# Intro\n");
+        let usage = write(&dir, "my usage.md", "# Usage\n");
+        let summary = write(
+            &dir,
+            "SUMMARY.md",
+            "# Summary\n\n- [Intro](intro.md)\n- [Usage](my%20usage.md#top)\n- [Draft]()\n- [Again](intro.md)\n- [Site](https://example.com/x.md)\n",
+        );
+
+        let inputs = [summary.display().to_string()];
+        let chapters = chapter_files(&inputs, &BookConfig::default()).unwrap();
+        assert_eq!(chapters, [intro, usage]);
+    }
+
+    #[test]
+    fn prefers_command_line_chapters_over_the_config() {
+        let staging = StagingDir::create().unwrap();
+        let dir = fs::canonicalize(staging.path()).unwrap();
+        let a = write(&dir, "a.md", "");
+        let b = write(&dir, "b.md", "");
+        let config = BookConfig {
+            files: vec![a.display().to_string()],
+            ..BookConfig::default()
+        };
+
+        assert_eq!(
+            chapter_files(&[], &config).unwrap(),
+            std::slice::from_ref(&a)
+        );
+        let inputs = [b.display().to_string(), a.display().to_string()];
+        assert_eq!(chapter_files(&inputs, &config).unwrap(), [b, a]);
+    }
+
+    #[test]
+    fn reports_missing_chapters() {
+        let staging = StagingDir::create().unwrap();
+        let dir = staging.path();
+
+        assert!(chapter_files(&[], &BookConfig::default())
+            .unwrap_err()
+            .starts_with("no chapters given"));
+
+        let missing = dir.join("missing.md").display().to_string();
+        let error = chapter_files(&[missing], &BookConfig::default()).unwrap_err();
+        assert!(error.starts_with("Chapter '"), "{}", error);
+
+        let summary = write(dir, "summary.md", "- [Site](https://example.com)\n");
+        let error =
+            chapter_files(&[summary.display().to_string()], &BookConfig::default()).unwrap_err();
+        assert!(
+            error.ends_with("does not link to any chapters"),
+            "{}",
+            error
+        );
+    }
+
+    /// The book's events as HTML, with the chapters written to a temporary
+    /// directory first.
+    fn book(chapters: &[(&str, &str)], config: &Config) -> String {
+        let staging = StagingDir::create().unwrap();
+        let dir = fs::canonicalize(staging.path()).unwrap();
+        let paths: Vec<_> = chapters
+            .iter()
+            .map(|(name, content)| write(&dir, name, content))
+            .collect();
+        let sources: Vec<_> = chapters
+            .iter()
+            .map(|(_, content)| content.to_string())
+            .collect();
+
+        let document = assemble(&paths, &sources, config, None);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, document.events.into_iter());
+        html.replace(&dir.display().to_string(), "DIR")
+    }
+
+    #[test]
+    fn links_chapters_and_keeps_ids_unique() {
+        let config = get_default_config();
+        let html = book(
+            &[
+                (
+                    "one.md",
+                    "# Setup\n\nSee [two](two.md) and [its setup](two.md#setup).\n",
+                ),
+                ("two.md", "# Setup\n\n![Chart](img/chart.png)\n"),
+            ],
+            &config,
+        );
+
+        assert!(html.contains("<h1 id=\"setup\">"), "{}", html);
+        assert!(html.contains("<h1 id=\"setup-1\">"), "{}", html);
+        assert!(html.contains("href=\"#setup-1\">two"), "{}", html);
+        assert!(html.contains("href=\"#setup-1\">its setup"), "{}", html);
+        assert!(html.contains("src=\"DIR/img/chart.png\""), "{}", html);
+    }
+
+    #[test]
+    fn breaks_pages_between_chapters_and_adds_contents() {
+        let mut config = get_default_config();
+        config.headings.h1_page_break_before = false;
+        let html = book(
+            &[("one.md", "Title page\n"), ("two.md", "# Two\n")],
+            &config,
+        );
+        assert_eq!(html.matches("page-break").count(), 2, "{}", html);
+        assert!(!html.contains(TOC), "{}", html);
+
+        // A chapter whose heading already starts a page needs no extra break.
+        config.headings.h1_page_break_before = true;
+        let html = book(
+            &[("one.md", "Title page\n"), ("two.md", "# Two\n")],
+            &config,
+        );
+        assert_eq!(html.matches("page-break").count(), 1, "{}", html);
+
+        config.book.toc = false;
+        config.book.chapter_page_break = false;
+        let html = book(
+            &[("one.md", "Title page\n"), ("two.md", "# Two\n")],
+            &config,
+        );
+        assert!(!html.contains("page-break"), "{}", html);
+    }
+}
@@ -0,0 +1,898 @@
+//! A small Chrome DevTools Protocol client.
+//!
+//! Rather than relying on `--print-to-pdf`, the browser is started with remote
+//! debugging enabled and driven over a single WebSocket connection. This lets us
+//! wait for fonts, images and scripts to settle, pass real print parameters and
+//! report what went wrong inside the page.
+
+use crate::backend::PrintOptions;
+use crate::child::Supervised;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves once web fonts and every `<img>` have finished loading, then gives
+/// late scripts (highlight.js, caption generation) a moment to run.
+const SETTLE_SCRIPT: &str = r#"(async () => {
+    await document.fonts.ready;
+    await Promise.all(Array.from(document.images)
+        .filter((img) => !img.complete)
+        .map((img) => new Promise((resolve) => { img.onload = img.onerror = resolve; })));
+    await new Promise((resolve) => setTimeout(resolve, SETTLE_MS));
+    return true;
+})()"#;
+
+#[derive(Debug)]
+pub enum CdpError {
+    Io(io::Error),
+    Handshake(String),
+    Protocol(String),
+    Command { method: String, message: String },
+    Timeout(String),
+}
+
+impl fmt::Display for CdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CdpError::Io(e) => write!(f, "I/O error talking to the browser: {}", e),
+            CdpError::Handshake(msg) => write!(f, "DevTools handshake failed: {}", msg),
+            CdpError::Protocol(msg) => write!(f, "DevTools protocol error: {}", msg),
+            CdpError::Command { method, message } => write!(f, "{} failed: {}", method, message),
+            CdpError::Timeout(what) => write!(f, "Timed out {}", what),
+        }
+    }
+}
+
+impl std::error::Error for CdpError {}
+
+impl From<io::Error> for CdpError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                CdpError::Timeout("waiting for a DevTools message".to_string())
+            }
+            _ => CdpError::Io(e),
+        }
+    }
+}
+
+/// Problems reported by the page while it was loading.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub console_errors: Vec<String>,
+    pub failed_requests: Vec<String>,
+}
+
+impl Diagnostics {
+    pub fn report(&self) {
+        for error in &self.console_errors {
+            eprintln!("Warning: page error: {}", error);
+        }
+        for failure in &self.failed_requests {
+            eprintln!("Warning: failed to load {}", failure);
+        }
+    }
+}
+
+/// A headless browser process with an open DevTools connection. Everything
+/// from launch to the finished PDF must happen within the launch `timeout`;
+/// the browser's process tree is killed when this is dropped.
+pub struct Browser {
+    child: Supervised,
+    client: CdpClient,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl Browser {
+    pub fn launch(
+        browser_path: &Path,
+        user_data_dir: &Path,
+        timeout: Duration,
+    ) -> Result<Browser, CdpError> {
+        let deadline = Instant::now() + timeout;
+        let port_file = user_data_dir.join("DevToolsActivePort");
+        let _ = fs::remove_file(&port_file);
+
+        let mut child = Supervised::spawn(
+            Command::new(browser_path)
+                .arg("--headless=new")
+                .arg("--disable-gpu")
+                .arg("--no-first-run")
+                .arg("--no-default-browser-check")
+                .arg("--disable-extensions")
+                .arg("--disable-background-networking")
+                .arg("--remote-debugging-port=0")
+                .arg(format!("--user-data-dir={}", user_data_dir.display()))
+                .arg("about:blank")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+        )?;
+
+        let stderr = child.stderr.take().map(|mut pipe| {
+            thread::spawn(move || {
+                let mut output = String::new();
+                let _ = pipe.read_to_string(&mut output);
+                output
+            })
+        });
+
+        let ws_url = loop {
+            if let Ok(contents) = fs::read_to_string(&port_file) {
+                let mut lines = contents.lines();
+                if let (Some(port), Some(path)) = (lines.next(), lines.next()) {
+                    break format!("ws://127.0.0.1:{}{}", port.trim(), path.trim());
+                }
+            }
+
+            if let Some(status) = child.try_wait()? {
+                let output = stderr.and_then(|h| h.join().ok()).unwrap_or_default();
+                return Err(CdpError::Protocol(format!(
+                    "browser exited ({}) before DevTools was ready\n{}",
+                    status,
+                    output.trim()
+                )));
+            }
+
+            if Instant::now() >= deadline {
+                return Err(CdpError::Timeout(
+                    "waiting for the browser to open its DevTools port".to_string(),
+                ));
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        Ok(Browser {
+            child,
+            client: CdpClient::connect(&ws_url, deadline)?,
+            stderr,
+        })
+    }
+
+    pub fn print_to_pdf(
+        &mut self,
+        url: &str,
+        options: &PrintOptions,
+    ) -> Result<(Vec<u8>, Diagnostics), CdpError> {
+        print_page(&mut self.client, url, options)
+    }
+
+    /// Shuts the browser down and returns whatever it wrote to stderr.
+    pub fn close(mut self) -> String {
+        let _ = self.client.send(None, "Browser.close", json!({}));
+
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(Some(_)) | Err(_) => break,
+                Ok(None) => thread::sleep(Duration::from_millis(50)),
+            }
+        }
+
+        self.child.kill_tree();
+
+        self.stderr
+            .take()
+            .and_then(|h| h.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Loads `url` in a fresh tab and prints it. Works against any endpoint that
+/// speaks the browser-level protocol; the tests script one.
+pub fn print_page(
+    client: &mut CdpClient,
+    url: &str,
+    options: &PrintOptions,
+) -> Result<(Vec<u8>, Diagnostics), CdpError> {
+    let target = client.call(None, "Target.createTarget", json!({ "url": "about:blank" }))?;
+    let target_id = target["targetId"]
+        .as_str()
+        .ok_or_else(|| CdpError::Protocol("Target.createTarget returned no targetId".to_string()))?
+        .to_string();
+
+    let attached = client.call(
+        None,
+        "Target.attachToTarget",
+        json!({ "targetId": target_id, "flatten": true }),
+    )?;
+    let session = attached["sessionId"]
+        .as_str()
+        .ok_or_else(|| {
+            CdpError::Protocol("Target.attachToTarget returned no sessionId".to_string())
+        })?
+        .to_string();
+    let session = Some(session.as_str());
+
+    for domain in ["Page", "Runtime", "Log", "Network"] {
+        client.call(session, &format!("{}.enable", domain), json!({}))?;
+    }
+
+    let mut tracker = LoadTracker::default();
+
+    let navigation = client.call(session, "Page.navigate", json!({ "url": url }))?;
+    if let Some(error) = navigation["errorText"].as_str() {
+        return Err(CdpError::Command {
+            method: "Page.navigate".to_string(),
+            message: error.to_string(),
+        });
+    }
+
+    let deadline = client.deadline;
+    while !tracker.loaded {
+        let event = client.next_event(deadline)?;
+        tracker.observe(&event);
+    }
+
+    let settled = client.call(
+        session,
+        "Runtime.evaluate",
+        json!({
+            "expression": SETTLE_SCRIPT.replace("SETTLE_MS", &options.settle_ms.to_string()),
+            "awaitPromise": true,
+            "returnByValue": true,
+        }),
+    )?;
+    if let Some(details) = settled.get("exceptionDetails") {
+        tracker
+            .diagnostics
+            .console_errors
+            .push(exception_text(details));
+    }
+
+    let printed = client.call(session, "Page.printToPDF", print_params(options));
+
+    while let Some(event) = client.events.pop_front() {
+        tracker.observe(&event);
+    }
+
+    // Closed whether or not there is a PDF, so a failed print leaves no tab
+    // behind in a reused browser.
+    let _ = client.call(None, "Target.closeTarget", json!({ "targetId": target_id }));
+
+    let printed = printed?;
+    let data = printed["data"]
+        .as_str()
+        .ok_or_else(|| CdpError::Protocol("Page.printToPDF returned no data".to_string()))?;
+    let pdf = BASE64
+        .decode(data)
+        .map_err(|e| CdpError::Protocol(format!("invalid PDF data: {}", e)))?;
+
+    Ok((pdf, tracker.diagnostics))
+}
+
+/// Margins are passed as a fallback; `@page` rules in the stylesheet win.
+fn print_params(options: &PrintOptions) -> Value {
+    let templates = !options.header_template.is_empty() || !options.footer_template.is_empty();
+
+    json!({
+        "paperWidth": options.paper_width,
+        "paperHeight": options.paper_height,
+        "marginTop": options.margin,
+        "marginBottom": options.margin,
+        "marginLeft": options.margin,
+        "marginRight": options.margin,
+        "preferCSSPageSize": true,
+        "printBackground": options.print_background,
+        "generateDocumentOutline": options.outline,
+        "displayHeaderFooter": templates,
+        // Chrome substitutes its own date/title header for an empty template.
+        "headerTemplate": if templates && options.header_template.is_empty() { "<span></span>" } else { &options.header_template },
+        "footerTemplate": if templates && options.footer_template.is_empty() { "<span></span>" } else { &options.footer_template },
+    })
+}
+
+#[derive(Default)]
+struct LoadTracker {
+    loaded: bool,
+    urls: HashMap<String, String>,
+    diagnostics: Diagnostics,
+}
+
+impl LoadTracker {
+    fn observe(&mut self, event: &Value) {
+        let params = &event["params"];
+
+        match event["method"].as_str().unwrap_or_default() {
+            "Page.loadEventFired" => self.loaded = true,
+            "Network.requestWillBeSent" => {
+                if let (Some(id), Some(url)) = (
+                    params["requestId"].as_str(),
+                    params["request"]["url"].as_str(),
+                ) {
+                    self.urls.insert(id.to_string(), url.to_string());
+                }
+            }
+            "Network.loadingFailed" => {
+                let url = params["requestId"]
+                    .as_str()
+                    .and_then(|id| self.urls.get(id))
+                    .map(String::as_str)
+                    .unwrap_or("<unknown resource>");
+                let reason = params["errorText"].as_str().unwrap_or("unknown error");
+                self.diagnostics
+                    .failed_requests
+                    .push(format!("{}: {}", url, reason));
+            }
+            "Runtime.consoleAPICalled" if params["type"] == "error" => {
+                let text = params["args"]
+                    .as_array()
+                    .map(|args| {
+                        args.iter()
+                            .map(|arg| match &arg["value"] {
+                                Value::String(s) => s.clone(),
+                                Value::Null => {
+                                    arg["description"].as_str().unwrap_or_default().to_string()
+                                }
+                                other => other.to_string(),
+                            })
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .unwrap_or_default();
+                self.diagnostics.console_errors.push(text);
+            }
+            "Runtime.exceptionThrown" => {
+                self.diagnostics
+                    .console_errors
+                    .push(exception_text(&params["exceptionDetails"]));
+            }
+            // Network failures are already reported through Network.loadingFailed.
+            "Log.entryAdded"
+                if params["entry"]["level"] == "error"
+                    && params["entry"]["source"] != "network" =>
+            {
+                if let Some(text) = params["entry"]["text"].as_str() {
+                    self.diagnostics.console_errors.push(text.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn exception_text(details: &Value) -> String {
+    details["exception"]["description"]
+        .as_str()
+        .or_else(|| details["text"].as_str())
+        .unwrap_or("unknown exception")
+        .to_string()
+}
+
+/// A DevTools connection: request/response matching plus a queue of events
+/// that arrived while waiting for responses.
+pub struct CdpClient {
+    socket: WebSocket,
+    next_id: u64,
+    events: VecDeque<Value>,
+    deadline: Instant,
+}
+
+impl CdpClient {
+    /// Every command sent on this connection must be answered before
+    /// `deadline`.
+    pub fn connect(ws_url: &str, deadline: Instant) -> Result<CdpClient, CdpError> {
+        Ok(CdpClient {
+            socket: WebSocket::connect(ws_url, deadline)?,
+            next_id: 1,
+            events: VecDeque::new(),
+            deadline,
+        })
+    }
+
+    fn send(
+        &mut self,
+        session: Option<&str>,
+        method: &str,
+        params: Value,
+    ) -> Result<u64, CdpError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut message = json!({ "id": id, "method": method, "params": params });
+        if let Some(session) = session {
+            message["sessionId"] = json!(session);
+        }
+
+        self.socket.send_text(&message.to_string())?;
+        Ok(id)
+    }
+
+    pub fn call(
+        &mut self,
+        session: Option<&str>,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, CdpError> {
+        let id = self.send(session, method, params)?;
+        let deadline = self.deadline;
+
+        loop {
+            let message = self.receive(deadline)?;
+
+            if message["id"].as_u64() == Some(id) {
+                if let Some(error) = message.get("error") {
+                    return Err(CdpError::Command {
+                        method: method.to_string(),
+                        message: error["message"]
+                            .as_str()
+                            .unwrap_or("unknown error")
+                            .to_string(),
+                    });
+                }
+                return Ok(message["result"].clone());
+            }
+
+            if message.get("method").is_some() {
+                self.events.push_back(message);
+            }
+        }
+    }
+
+    fn next_event(&mut self, deadline: Instant) -> Result<Value, CdpError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        loop {
+            let message = self.receive(deadline)?;
+            if message.get("method").is_some() {
+                return Ok(message);
+            }
+        }
+    }
+
+    fn receive(&mut self, deadline: Instant) -> Result<Value, CdpError> {
+        let text = self.socket.read_text(deadline)?;
+        serde_json::from_str(&text)
+            .map_err(|e| CdpError::Protocol(format!("invalid JSON message: {}", e)))
+    }
+}
+
+/// Just enough of RFC 6455 to talk to a local DevTools endpoint: unencrypted,
+/// client-masked text frames, with fragmentation and ping handling.
+struct WebSocket {
+    stream: TcpStream,
+}
+
+impl WebSocket {
+    fn connect(url: &str, deadline: Instant) -> Result<WebSocket, CdpError> {
+        let rest = url
+            .strip_prefix("ws://")
+            .ok_or_else(|| CdpError::Handshake(format!("unsupported URL '{}'", url)))?;
+        let (host, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(CdpError::Timeout(
+                "connecting to the browser's DevTools port".to_string(),
+            ));
+        }
+        let address = host
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| CdpError::Handshake(format!("could not resolve '{}'", host)))?;
+        let mut stream = TcpStream::connect_timeout(&address, remaining)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(remaining))?;
+
+        let key = BASE64.encode(random_bytes::<16>());
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key
+        )?;
+
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte)? == 0 {
+                return Err(CdpError::Handshake(
+                    "connection closed during upgrade".to_string(),
+                ));
+            }
+            response.push(byte[0]);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or_default();
+        if status.split_whitespace().nth(1) != Some("101") {
+            return Err(CdpError::Handshake(format!(
+                "unexpected response '{}'",
+                status
+            )));
+        }
+
+        Ok(WebSocket { stream })
+    }
+
+    fn send_text(&mut self, text: &str) -> Result<(), CdpError> {
+        self.send_frame(0x1, text.as_bytes())
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), CdpError> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        let mask = random_bytes::<4>();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    fn read_text(&mut self, deadline: Instant) -> Result<String, CdpError> {
+        let mut message = Vec::new();
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(CdpError::Timeout(
+                    "waiting for a DevTools message".to_string(),
+                ));
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+
+            let mut header = [0u8; 2];
+            self.stream.read_exact(&mut header)?;
+            let fin = header[0] & 0x80 != 0;
+            let opcode = header[0] & 0x0f;
+            let masked = header[1] & 0x80 != 0;
+
+            let len = match header[1] & 0x7f {
+                126 => {
+                    let mut bytes = [0u8; 2];
+                    self.stream.read_exact(&mut bytes)?;
+                    u16::from_be_bytes(bytes) as usize
+                }
+                127 => {
+                    let mut bytes = [0u8; 8];
+                    self.stream.read_exact(&mut bytes)?;
+                    u64::from_be_bytes(bytes) as usize
+                }
+                len => len as usize,
+            };
+
+            let mut mask = [0u8; 4];
+            if masked {
+                self.stream.read_exact(&mut mask)?;
+            }
+
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload)?;
+            if masked {
+                payload
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, b)| *b ^= mask[i % 4]);
+            }
+
+            match opcode {
+                0x0..=0x2 => {
+                    message.extend_from_slice(&payload);
+                    if fin {
+                        return String::from_utf8(message).map_err(|_| {
+                            CdpError::Protocol("message is not valid UTF-8".to_string())
+                        });
+                    }
+                }
+                0x8 => {
+                    return Err(CdpError::Protocol(
+                        "browser closed the connection".to_string(),
+                    ))
+                }
+                0x9 => self.send_frame(0xA, &payload)?,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Masking keys and handshake nonces only need to be unpredictable to
+/// intermediaries, so a clock-seeded xorshift is plenty.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut state = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0x2545_f491_4f6c_dd1d)
+        | 1;
+
+    let mut bytes = [0u8; N];
+    for byte in bytes.iter_mut() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *byte = state as u8;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    type Commands = Arc<Mutex<Vec<Value>>>;
+
+    /// Serves one DevTools connection, answering every command with the
+    /// messages `respond` returns for it. Returns the URL to connect to and
+    /// the commands received so far.
+    fn fake_browser<F>(respond: F) -> (String, Commands)
+    where
+        F: Fn(&Value) -> Vec<Value> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Commands::default();
+        let received = Arc::clone(&commands);
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                if stream.read(&mut byte).unwrap_or(0) == 0 {
+                    return;
+                }
+                request.push(byte[0]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n")
+                .unwrap();
+
+            let mut socket = WebSocket {
+                stream: stream.try_clone().unwrap(),
+            };
+            let deadline = Instant::now() + Duration::from_secs(30);
+            while let Ok(text) = socket.read_text(deadline) {
+                let command: Value = serde_json::from_str(&text).unwrap();
+                received.lock().unwrap().push(command.clone());
+                for message in respond(&command) {
+                    if write_frame(&mut stream, &message.to_string()).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        (
+            format!("ws://127.0.0.1:{}/devtools/browser/fake", port),
+            commands,
+        )
+    }
+
+    /// An unmasked server-to-client text frame.
+    fn write_frame(stream: &mut TcpStream, text: &str) -> io::Result<()> {
+        let mut frame = vec![0x81];
+        match text.len() {
+            len if len < 126 => frame.push(len as u8),
+            len => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(text.as_bytes());
+        stream.write_all(&frame)
+    }
+
+    fn reply(command: &Value, result: Value) -> Value {
+        json!({ "id": command["id"], "result": result })
+    }
+
+    fn event(method: &str, params: Value) -> Value {
+        json!({ "method": method, "params": params })
+    }
+
+    /// A browser that sends `page_events` after navigating, then fires the
+    /// load event unless `loads` is false.
+    fn chrome(page_events: Vec<Value>, loads: bool) -> impl Fn(&Value) -> Vec<Value> {
+        move |command| {
+            let result = match command["method"].as_str().unwrap_or_default() {
+                "Target.createTarget" => json!({ "targetId": "target-1" }),
+                "Target.attachToTarget" => json!({ "sessionId": "session-1" }),
+                "Page.navigate" => {
+                    let mut messages = vec![reply(command, json!({ "frameId": "frame-1" }))];
+                    messages.extend(page_events.iter().cloned());
+                    if loads {
+                        messages.push(event("Page.loadEventFired", json!({ "timestamp": 1.0 })));
+                    }
+                    return messages;
+                }
+                "Runtime.evaluate" => json!({ "result": { "type": "boolean", "value": true } }),
+                "Page.printToPDF" => json!({ "data": BASE64.encode(b"%PDF-1.7 fake") }),
+                _ => json!({}),
+            };
+            vec![reply(command, result)]
+        }
+    }
+
+    fn options() -> PrintOptions {
+        PrintOptions {
+            paper_width: 8.5,
+            paper_height: 11.0,
+            margin: 1.0,
+            print_background: true,
+            outline: false,
+            header_template: String::new(),
+            footer_template: "<span class=\"pageNumber\"></span>".to_string(),
+            settle_ms: 0,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    fn connect(url: &str, timeout: Duration) -> CdpClient {
+        CdpClient::connect(url, Instant::now() + timeout).unwrap()
+    }
+
+    #[test]
+    fn prints_a_page() {
+        let (url, commands) = fake_browser(chrome(Vec::new(), true));
+        let mut client = connect(&url, Duration::from_secs(10));
+
+        let (pdf, diagnostics) = print_page(&mut client, "file:///doc.html", &options()).unwrap();
+        assert_eq!(pdf, b"%PDF-1.7 fake");
+        assert!(diagnostics.console_errors.is_empty());
+        assert!(diagnostics.failed_requests.is_empty());
+
+        let commands = commands.lock().unwrap();
+        let methods: Vec<&str> = commands
+            .iter()
+            .map(|command| command["method"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                "Target.createTarget",
+                "Target.attachToTarget",
+                "Page.enable",
+                "Runtime.enable",
+                "Log.enable",
+                "Network.enable",
+                "Page.navigate",
+                "Runtime.evaluate",
+                "Page.printToPDF",
+                "Target.closeTarget",
+            ]
+        );
+        assert_eq!(commands[6]["sessionId"], "session-1");
+        assert_eq!(commands[6]["params"]["url"], "file:///doc.html");
+
+        let print = &commands[8]["params"];
+        assert_eq!(print["paperWidth"], 8.5);
+        assert_eq!(print["displayHeaderFooter"], true);
+        assert_eq!(print["headerTemplate"], "<span></span>");
+    }
+
+    #[test]
+    fn reports_page_errors_and_failed_requests() {
+        let page_events = vec![
+            event(
+                "Network.requestWillBeSent",
+                json!({ "requestId": "r1", "request": { "url": "file:///missing.png" } }),
+            ),
+            event(
+                "Network.loadingFailed",
+                json!({ "requestId": "r1", "errorText": "net::ERR_FILE_NOT_FOUND" }),
+            ),
+            event(
+                "Runtime.consoleAPICalled",
+                json!({ "type": "error", "args": [{ "type": "string", "value": "broken" }, { "type": "number", "value": 3 }] }),
+            ),
+            event(
+                "Runtime.consoleAPICalled",
+                json!({ "type": "log", "args": [{ "type": "string", "value": "fine" }] }),
+            ),
+            event(
+                "Log.entryAdded",
+                json!({ "entry": { "level": "error", "source": "network", "text": "reported twice" } }),
+            ),
+            event(
+                "Runtime.exceptionThrown",
+                json!({ "exceptionDetails": { "exception": { "description": "TypeError: x is undefined" } } }),
+            ),
+        ];
+        let (url, _) = fake_browser(chrome(page_events, true));
+        let mut client = connect(&url, Duration::from_secs(10));
+
+        let (_, diagnostics) = print_page(&mut client, "file:///doc.html", &options()).unwrap();
+        assert_eq!(
+            diagnostics.console_errors,
+            ["broken 3", "TypeError: x is undefined"]
+        );
+        assert_eq!(
+            diagnostics.failed_requests,
+            ["file:///missing.png: net::ERR_FILE_NOT_FOUND"]
+        );
+    }
+
+    #[test]
+    fn reports_navigation_errors() {
+        let browser = chrome(Vec::new(), true);
+        let (url, _) = fake_browser(move |command| {
+            if command["method"] == "Page.navigate" {
+                vec![reply(
+                    command,
+                    json!({ "errorText": "net::ERR_FILE_NOT_FOUND" }),
+                )]
+            } else {
+                browser(command)
+            }
+        });
+        let mut client = connect(&url, Duration::from_secs(10));
+
+        match print_page(&mut client, "file:///missing.html", &options()) {
+            Err(CdpError::Command { method, message }) => {
+                assert_eq!(method, "Page.navigate");
+                assert_eq!(message, "net::ERR_FILE_NOT_FOUND");
+            }
+            other => panic!("expected a navigation error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn closes_the_target_when_the_pdf_is_unusable() {
+        for result in [json!({}), json!({ "data": "not base64!" })] {
+            let browser = chrome(Vec::new(), true);
+            let (url, commands) = fake_browser(move |command| {
+                if command["method"] == "Page.printToPDF" {
+                    vec![reply(command, result.clone())]
+                } else {
+                    browser(command)
+                }
+            });
+            let mut client = connect(&url, Duration::from_secs(10));
+
+            let printed = print_page(&mut client, "file:///doc.html", &options());
+            assert!(matches!(printed, Err(CdpError::Protocol(_))));
+            let commands = commands.lock().unwrap();
+            assert_eq!(commands.last().unwrap()["method"], "Target.closeTarget");
+        }
+    }
+
+    #[test]
+    fn times_out_when_the_page_never_loads() {
+        let (url, _) = fake_browser(chrome(Vec::new(), false));
+        let mut client = connect(&url, Duration::from_millis(300));
+
+        let started = Instant::now();
+        let result = print_page(&mut client, "file:///doc.html", &options());
+        assert!(matches!(result, Err(CdpError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn times_out_before_connecting_past_the_deadline() {
+        let (url, _) = fake_browser(chrome(Vec::new(), true));
+        let result = CdpClient::connect(&url, Instant::now());
+        assert!(matches!(result, Err(CdpError::Timeout(_))));
+    }
+}
@@ -0,0 +1,207 @@
+//! External renderer processes that can be timed out and cancelled.
+//!
+//! Browsers fork helper processes (renderer, GPU, network service), so killing
+//! only the process we spawned can leave the rest running. Every child is
+//! started in its own process group and killed as a whole tree, whether it
+//! timed out, failed, or the user pressed Ctrl-C.
+
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Process ids of children that are still running, for the Ctrl-C handler.
+static LIVE_CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A child process whose whole tree is killed when it is dropped.
+pub struct Supervised {
+    child: Child,
+}
+
+impl Supervised {
+    pub fn spawn(command: &mut Command) -> io::Result<Supervised> {
+        own_process_group(command);
+        let child = command.spawn()?;
+        live_children().push(child.id());
+        Ok(Supervised { child })
+    }
+
+    /// Kills the process and everything it started, then reaps it.
+    pub fn kill_tree(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            kill_tree_by_id(self.child.id());
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let id = self.child.id();
+        live_children().retain(|&live| live != id);
+    }
+}
+
+impl Deref for Supervised {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for Supervised {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+impl Drop for Supervised {
+    fn drop(&mut self) {
+        self.kill_tree();
+    }
+}
+
+/// Like `Command::output`, but gives up and kills the process tree once
+/// `timeout` has passed, returning an error of kind `TimedOut`.
+pub fn output_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<Output> {
+    let mut child = Supervised::spawn(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+
+    // Drain both pipes on threads so a chatty child can't block on a full pipe.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill_tree();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("renderer timed out after {}s", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Kills every supervised process still running. Used when interrupted.
+pub fn kill_all() {
+    let ids: Vec<u32> = live_children().drain(..).collect();
+    for id in ids {
+        kill_tree_by_id(id);
+    }
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+fn live_children() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    LIVE_CHILDREN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(windows)]
+fn own_process_group(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP | CREATE_NO_WINDOW);
+}
+
+#[cfg(unix)]
+fn own_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(windows)]
+fn kill_tree_by_id(id: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &id.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+#[cfg(unix)]
+fn kill_tree_by_id(id: u32) {
+    // The child leads its own process group, so a negative id signals the
+    // whole group.
+    let _ = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", id)])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::staging::StagingDir;
+    use std::fs;
+
+    fn is_running(id: &str) -> bool {
+        Command::new("kill")
+            .args(["-0", id])
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    #[test]
+    fn collects_output_of_finished_commands() {
+        let output = output_with_timeout(
+            Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn kills_the_whole_tree_on_timeout() {
+        let staging = StagingDir::create().unwrap();
+        let pid_file = staging.path().join("helper.pid");
+        let script = format!("sleep 30 & echo $! > '{}'; wait", pid_file.display());
+
+        let started = Instant::now();
+        let error = output_with_timeout(
+            Command::new("sh").args(["-c", &script]),
+            Duration::from_millis(300),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // The helper the shell started goes with it.
+        let helper = fs::read_to_string(&pid_file).unwrap();
+        let helper = helper.trim();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while is_running(helper) && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert!(!is_running(helper));
+    }
+}
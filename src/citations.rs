@@ -0,0 +1,555 @@
+//! Citations and the reference list.
+//!
+//! `[@doe2020]`, `[see @doe2020, p. 33; @lee2019]` and, for author-year
+//! style, `[-@doe2020]` (year only) are looked up in the bibliography and
+//! become "[1]" or "(Doe 2020)", linked to their entry. The cited works are
+//! listed where a paragraph reads `[[references]]`, or at the end of the
+//! document, numbered in order of first citation or sorted by author.
+
+use crate::bibliography::{Bibliography, Name, Reference};
+use crate::config::CitationsConfig;
+use crate::figures::merge_text;
+use pulldown_cmark::{CowStr, Event, HeadingLevel, LinkType, Tag, TagEnd};
+use std::collections::HashSet;
+
+const REFERENCES: &str = "[[references]]";
+
+/// One `@key` of a citation.
+struct Cite<'t> {
+    prefix: &'t str,
+    key: &'t str,
+    locator: &'t str,
+    year_only: bool,
+}
+
+/// Resolves the citations in `events` against `bibliography` and adds the
+/// reference list.
+pub fn cite<'a>(
+    events: Vec<Event<'a>>,
+    bibliography: &Bibliography,
+    config: &CitationsConfig,
+) -> Vec<Event<'a>> {
+    let events = merge_text(events);
+    // Cited keys in order of first citation.
+    let mut cited: Vec<&str> = Vec::new();
+    let mut warned = HashSet::new();
+    let mut output = Vec::with_capacity(events.len());
+    let mut link_depth = 0usize;
+    let mut in_code = false;
+    let mut placeholder = None;
+
+    let mut index = 0;
+    while index < events.len() {
+        let event = &events[index];
+        match event {
+            Event::Start(Tag::Link { .. } | Tag::Image { .. }) => link_depth += 1,
+            Event::End(TagEnd::Link | TagEnd::Image) => link_depth = link_depth.saturating_sub(1),
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            _ => {}
+        }
+
+        match event {
+            Event::Start(Tag::Paragraph)
+                if matches!(events.get(index + 1), Some(Event::Text(text)) if text.trim() == REFERENCES)
+                    && events.get(index + 2) == Some(&Event::End(TagEnd::Paragraph)) =>
+            {
+                placeholder.get_or_insert(output.len());
+                index += 3;
+                continue;
+            }
+            Event::Text(text) if !in_code && text.contains('@') => {
+                output.extend(resolve_citations(
+                    text,
+                    bibliography,
+                    config,
+                    link_depth == 0,
+                    &mut cited,
+                    &mut warned,
+                ));
+            }
+            event => output.push(event.clone()),
+        }
+        index += 1;
+    }
+
+    let cited: Vec<String> = cited.into_iter().map(str::to_string).collect();
+    if cited.is_empty() {
+        return output;
+    }
+    let list = reference_list(&output, &cited, bibliography, config);
+    let at = placeholder.unwrap_or(output.len());
+    output.splice(at..at, list);
+    output
+}
+
+/// Replaces the citations in `text`, as links to the reference list when
+/// `link` is set. Citations with unknown keys are left as written.
+fn resolve_citations<'a, 'b>(
+    text: &str,
+    bibliography: &'b Bibliography,
+    config: &CitationsConfig,
+    link: bool,
+    cited: &mut Vec<&'b str>,
+    warned: &mut HashSet<String>,
+) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find(']').map(|close| open + close) else {
+            break;
+        };
+        plain.push_str(&rest[..open]);
+        let inner = &rest[open + 1..close];
+
+        let cites = parse_citation(inner).filter(|cites| {
+            let unknown: Vec<&str> = cites
+                .iter()
+                .map(|cite| cite.key)
+                .filter(|key| !bibliography.contains_key(*key))
+                .collect();
+            for key in &unknown {
+                if warned.insert(key.to_string()) {
+                    eprintln!("Warning: citation '@{}' is not in the bibliography", key);
+                }
+            }
+            unknown.is_empty()
+        });
+        let Some(cites) = cites else {
+            plain.push('[');
+            rest = &rest[open + 1..];
+            continue;
+        };
+        rest = &rest[close + 1..];
+
+        let author_year = config.author_year();
+        plain.push(if author_year { '(' } else { '[' });
+        let separator = if author_year || cites.iter().any(|cite| !cite.locator.is_empty()) {
+            "; "
+        } else {
+            ", "
+        };
+
+        for (position, cite) in cites.iter().enumerate() {
+            let (key, reference) = bibliography.get_key_value(cite.key).expect("checked above");
+            if !cited.contains(&key.as_str()) {
+                cited.push(key);
+            }
+
+            if position > 0 {
+                plain.push_str(separator);
+            }
+            if !cite.prefix.is_empty() {
+                plain.push_str(cite.prefix);
+                plain.push(' ');
+            }
+
+            let label = if !author_year {
+                let number = cited.iter().position(|cited| *cited == key).unwrap_or(0) + 1;
+                number.to_string()
+            } else if cite.year_only {
+                reference.year.clone()
+            } else {
+                format!("{} {}", short_authors(&reference.authors), reference.year)
+            };
+            if link {
+                if !plain.is_empty() {
+                    events.push(Event::Text(std::mem::take(&mut plain).into()));
+                }
+                events.extend([
+                    Event::Start(Tag::Link {
+                        link_type: LinkType::Inline,
+                        dest_url: format!("#{}", anchor(key)).into(),
+                        title: "".into(),
+                        id: "".into(),
+                    }),
+                    Event::Text(label.into()),
+                    Event::End(TagEnd::Link),
+                ]);
+            } else {
+                plain.push_str(&label);
+            }
+
+            if !cite.locator.is_empty() {
+                plain.push_str(", ");
+                plain.push_str(cite.locator);
+            }
+        }
+        plain.push(if author_year { ')' } else { ']' });
+    }
+
+    plain.push_str(rest);
+    if !plain.is_empty() {
+        events.push(Event::Text(plain.into()));
+    }
+    events
+}
+
+/// The `@key`s between the brackets of a citation, or `None` if it is not
+/// one: every `;`-separated part needs an `@key`, optionally with words
+/// before it and a locator after a comma.
+fn parse_citation(inner: &str) -> Option<Vec<Cite<'_>>> {
+    inner
+        .split(';')
+        .map(|part| {
+            let at = part.find('@')?;
+            let (prefix, year_only) = match part[..at].strip_suffix('-') {
+                Some(prefix) => (prefix, true),
+                None => (&part[..at], false),
+            };
+            if !(prefix.is_empty() || prefix.ends_with(char::is_whitespace)) {
+                return None;
+            }
+
+            let after = &part[at + 1..];
+            let (key, locator) = after.split_once(',').unwrap_or((after, ""));
+            let key = key.trim_end();
+            let valid_key = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || "_-:./+".contains(c));
+            valid_key.then(|| Cite {
+                prefix: prefix.trim(),
+                key,
+                locator: locator.trim(),
+                year_only,
+            })
+        })
+        .collect()
+}
+
+fn anchor(key: &str) -> String {
+    format!("ref-{}", key)
+}
+
+/// "Doe", "Doe and Lee" or "Doe et al.".
+fn short_authors(authors: &[Name]) -> String {
+    match authors {
+        [] => "Anon.".to_string(),
+        [one] => one.family.clone(),
+        [first, second] => format!("{} and {}", first.family, second.family),
+        [first, ..] => format!("{} et al.", first.family),
+    }
+}
+
+/// "Doe, J., Lee, K. and Smith, A."
+fn full_authors(authors: &[Name]) -> String {
+    let names: Vec<String> = authors
+        .iter()
+        .map(|name| {
+            let initials: Vec<String> = name
+                .given
+                .split([' ', '-'])
+                .filter_map(|given| given.chars().next())
+                .map(|initial| format!("{}.", initial))
+                .collect();
+            if initials.is_empty() {
+                name.family.clone()
+            } else {
+                format!("{}, {}", name.family, initials.join(" "))
+            }
+        })
+        .collect();
+
+    match names.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// The heading and entries of the reference list.
+fn reference_list<'a>(
+    events: &[Event],
+    cited: &[String],
+    bibliography: &Bibliography,
+    config: &CitationsConfig,
+) -> Vec<Event<'a>> {
+    let mut entries: Vec<(usize, &String, &Reference)> = cited
+        .iter()
+        .enumerate()
+        .map(|(index, key)| (index + 1, key, &bibliography[key]))
+        .collect();
+    if config.author_year() {
+        entries.sort_by_cached_key(|(_, _, reference)| {
+            (
+                full_authors(&reference.authors).to_lowercase(),
+                reference.year.clone(),
+                reference.title.to_lowercase(),
+            )
+        });
+    }
+
+    let mut list = Vec::new();
+    if !config.title.is_empty() {
+        list.extend([
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H2,
+                id: Some(heading_id(events).into()),
+                classes: Vec::new(),
+                attrs: Vec::new(),
+            }),
+            Event::Text(config.title.clone().into()),
+            Event::End(TagEnd::Heading(HeadingLevel::H2)),
+        ]);
+    }
+
+    list.push(Event::Html("<div class=\"references\">\n".into()));
+    for (number, key, reference) in entries {
+        list.push(Event::Start(Tag::Paragraph));
+        list.push(Event::InlineHtml(
+            format!("<span id=\"{}\"></span>", anchor(key)).into(),
+        ));
+        if !config.author_year() {
+            list.push(Event::Text(format!("[{}] ", number).into()));
+        }
+        list.extend(entry_events(reference));
+        list.push(Event::End(TagEnd::Paragraph));
+    }
+    list.push(Event::Html("</div>\n".into()));
+
+    list
+}
+
+/// "Doe, J. (2020). Title. *Journal*, 4(2), 10–20. Publisher. https://doi.org/..."
+/// with the journal, or the title of a standalone work, in italics.
+fn entry_events<'a>(reference: &Reference) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    let mut text = String::new();
+
+    let authors = full_authors(&reference.authors);
+    if !authors.is_empty() {
+        text.push_str(&authors);
+        text.push(' ');
+    }
+    if !reference.year.is_empty() {
+        text.push_str(&format!("({}). ", reference.year));
+    }
+
+    let italic = |events: &mut Vec<Event<'a>>, text: &mut String, italic: &str| {
+        if !text.is_empty() {
+            events.push(Event::Text(std::mem::take(text).into()));
+        }
+        events.extend([
+            Event::Start(Tag::Emphasis),
+            Event::Text(CowStr::from(italic.to_string())),
+            Event::End(TagEnd::Emphasis),
+        ]);
+    };
+
+    if reference.container.is_empty() {
+        if !reference.title.is_empty() {
+            italic(&mut events, &mut text, &reference.title);
+            text.push_str(full_stop(&reference.title));
+        }
+    } else {
+        if !reference.title.is_empty() {
+            text.push_str(&reference.title);
+            text.push_str(full_stop(&reference.title));
+        }
+        italic(&mut events, &mut text, &reference.container);
+        let mut last = reference.container.as_str();
+        if !reference.volume.is_empty() {
+            text.push_str(", ");
+            text.push_str(&reference.volume);
+            last = &reference.volume;
+        }
+        if !reference.issue.is_empty() {
+            text.push_str(&format!("({})", reference.issue));
+            last = ")";
+        }
+        if !reference.pages.is_empty() {
+            text.push_str(", ");
+            text.push_str(&reference.pages);
+            last = &reference.pages;
+        }
+        text.push_str(full_stop(last));
+    }
+
+    if !reference.publisher.is_empty() {
+        text.push_str(&reference.publisher);
+        text.push_str(full_stop(&reference.publisher));
+    }
+
+    let link = if !reference.doi.is_empty() {
+        format!("https://doi.org/{}", reference.doi)
+    } else {
+        reference.url.clone()
+    };
+    if !link.is_empty() {
+        events.push(Event::Text(std::mem::take(&mut text).into()));
+        events.extend([
+            Event::Start(Tag::Link {
+                link_type: LinkType::Autolink,
+                dest_url: link.clone().into(),
+                title: "".into(),
+                id: "".into(),
+            }),
+            Event::Text(link.into()),
+            Event::End(TagEnd::Link),
+        ]);
+    }
+
+    let text = text.trim_end();
+    if !text.is_empty() {
+        events.push(Event::Text(text.to_string().into()));
+    }
+    events
+}
+
+/// What ends a part of an entry, unless it already ends a sentence.
+fn full_stop(part: &str) -> &'static str {
+    if part.ends_with(['.', '?', '!']) {
+        " "
+    } else {
+        ". "
+    }
+}
+
+/// "references", unless a heading already has that id.
+fn heading_id(events: &[Event]) -> String {
+    let used: HashSet<&str> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.as_ref()),
+            _ => None,
+        })
+        .collect();
+
+    let mut id = "references".to_string();
+    let mut repeat = 0;
+    while used.contains(id.as_str()) {
+        repeat += 1;
+        id = format!("references-{}", repeat);
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::parse_events;
+
+    fn bibliography() -> Bibliography {
+        let name = |family: &str, given: &str| Name {
+            family: family.to_string(),
+            given: given.to_string(),
+        };
+        Bibliography::from([
+            (
+                "doe2020".to_string(),
+                Reference {
+                    authors: vec![name("Doe", "Jane Ann")],
+                    year: "2020".to_string(),
+                    title: "Parsing things".to_string(),
+                    container: "Journal of Parsing".to_string(),
+                    volume: "4".to_string(),
+                    issue: "2".to_string(),
+                    pages: "10–20".to_string(),
+                    doi: "10.1000/xyz".to_string(),
+                    ..Reference::default()
+                },
+            ),
+            (
+                "lee2019".to_string(),
+                Reference {
+                    authors: vec![name("Lee", "Kim"), name("Adams", ""), name("Ng", "Al")],
+                    year: "2019".to_string(),
+                    title: "A book?".to_string(),
+                    publisher: "ACME".to_string(),
+                    ..Reference::default()
+                },
+            ),
+        ])
+    }
+
+    fn cited(markdown: &str, style: &str) -> String {
+        let config = CitationsConfig {
+            style: style.to_string(),
+            ..CitationsConfig::default()
+        };
+        let events = cite(parse_events(markdown), &bibliography(), &config);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        html
+    }
+
+    #[test]
+    fn parses_citations() {
+        let cites = parse_citation("see @doe2020, p. 33; -@lee2019").unwrap();
+        let parts: Vec<_> = cites
+            .iter()
+            .map(|cite| (cite.prefix, cite.key, cite.locator, cite.year_only))
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                ("see", "doe2020", "p. 33", false),
+                ("", "lee2019", "", true)
+            ]
+        );
+        assert!(parse_citation("mail me@example.com").is_none());
+        assert!(parse_citation("@doe2020; no key").is_none());
+        assert!(parse_citation("@").is_none());
+    }
+
+    #[test]
+    fn numbers_citations_in_order_of_first_use() {
+        let html = cited(
+            "As shown [@lee2019; @doe2020] and [@doe2020, p. 3].\n\n[@unknown] `[@doe2020]`\n",
+            "numeric",
+        );
+        assert!(
+            html.starts_with(
+                "<p>As shown [<a href=\"#ref-lee2019\">1</a>, <a href=\"#ref-doe2020\">2</a>] \
+                 and [<a href=\"#ref-doe2020\">2</a>, p. 3].</p>\n<p>[@unknown] <code>[@doe2020]</code></p>\n"
+            ),
+            "{}",
+            html
+        );
+        assert!(html.contains(
+            "<h2 id=\"references\">References</h2>\n<div class=\"references\">\n\
+             <p><span id=\"ref-lee2019\"></span>[1] Lee, K., Adams and Ng, A. (2019). <em>A book?</em> ACME.</p>\n\
+             <p><span id=\"ref-doe2020\"></span>[2] Doe, J. A. (2020). Parsing things. <em>Journal of Parsing</em>, 4(2), 10–20. \
+             <a href=\"https://doi.org/10.1000/xyz\">https://doi.org/10.1000/xyz</a></p>\n</div>\n"
+        ), "{}", html);
+    }
+
+    #[test]
+    fn cites_by_author_and_year() {
+        let html = cited(
+            "[see @lee2019; -@doe2020]\n\n# References {#references}\n\n[[references]]\n\nEnd\n",
+            "author-year",
+        );
+        assert!(html.starts_with(
+            "<p>(see <a href=\"#ref-lee2019\">Lee et al. 2019</a>; <a href=\"#ref-doe2020\">2020</a>)</p>"
+        ), "{}", html);
+        // Placed at the marker, under an id of its own, sorted by author.
+        let list = html.find("<h2 id=\"references-1\">").unwrap();
+        assert!(list < html.find("End").unwrap(), "{}", html);
+        assert!(
+            html.find("ref-doe2020\"></span>Doe").unwrap()
+                < html.find("ref-lee2019\"></span>Lee").unwrap()
+        );
+    }
+
+    #[test]
+    fn leaves_citations_in_links_unlinked() {
+        let html = cited("[text [@doe2020]](https://example.com)\n", "numeric");
+        assert!(
+            html.starts_with("<p><a href=\"https://example.com\">text [1]</a></p>"),
+            "{}",
+            html
+        );
+    }
+
+    #[test]
+    fn adds_no_list_without_citations() {
+        assert_eq!(
+            cited("No citations @here.\n", "numeric"),
+            "<p>No citations @here.</p>\n"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::env;
+
+const USAGE: &str = "Usage: mandy-converter [OPTIONS] [FILE]...
+
+FILE is the markdown to convert, or - to read it from stdin. Several files
+are converted one after another, each to its own PDF, and relative links
+between them are pointed at the PDFs.
+
+With --book, the files are chapters of one PDF instead. A single SUMMARY.md
+lists the chapters as links, mdBook style; without any FILE the chapters
+come from book.files in config.json.
+
+Options:
+    -o, --output <PATH>    Where to write the PDF, or - for stdout; only
+                           with a single FILE or --book [default: FILE with
+                           a .pdf extension, stdout when reading stdin, or
+                           book.pdf next to the first FILE]
+    --base-dir <DIR>       Directory that relative image, link, include and
+                           bibliography paths are resolved against [default:
+                           FILE's directory, or the current directory when
+                           reading stdin]
+    --book                 Combine all FILEs into one PDF
+    --profile <NAME>       Activate a profile from config.json, replacing
+                           profiles.active; may be repeated or comma-separated
+    --var <NAME=VALUE>     Set the value of {{ NAME }} in the markdown; may
+                           be repeated
+    --backend <NAME>       PDF backend: auto, chromium, weasyprint, wkhtmltopdf,
+                           native
+    -h, --help             Print this help";
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub inputs: Vec<String>,
+    pub output: Option<String>,
+    pub base_dir: Option<String>,
+    pub backend: Option<String>,
+    pub book: bool,
+    pub vars: Vec<(String, String)>,
+    pub profiles: Vec<String>,
+}
+
+pub fn parse_args() -> Args {
+    parse(env::args().skip(1)).unwrap_or_else(|e| usage_error(&e))
+}
+
+fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut iter = arguments.into_iter();
+
+    while let Some(arg) = iter.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "-o" | "--output" => args.output = Some(flag_value(&flag, inline_value, &mut iter)?),
+            "--base-dir" => args.base_dir = Some(flag_value(&flag, inline_value, &mut iter)?),
+            "--backend" => args.backend = Some(flag_value(&flag, inline_value, &mut iter)?),
+            "--book" => args.book = true,
+            "--profile" => {
+                let profiles = flag_value(&flag, inline_value, &mut iter)?;
+                args.profiles.extend(
+                    profiles
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|profile| !profile.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            "--var" => {
+                let var = flag_value(&flag, inline_value, &mut iter)?;
+                match var.split_once('=') {
+                    Some((name, value)) => args.vars.push((name.to_string(), value.to_string())),
+                    None => return Err(format!("--var expects NAME=VALUE, got '{}'", var)),
+                }
+            }
+            _ if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            _ => args.inputs.push(arg),
+        }
+    }
+
+    if args.book {
+        if args.inputs.iter().any(|input| input == "-") {
+            return Err("- (stdin) cannot be used with --book".to_string());
+        }
+    } else if args.inputs.len() > 1 {
+        if args.output.is_some() {
+            return Err("--output can only be used with a single FILE".to_string());
+        }
+        if args.inputs.iter().any(|input| input == "-") {
+            return Err("- (stdin) can only be used with a single FILE".to_string());
+        }
+    }
+
+    Ok(args)
+}
+
+fn flag_value(
+    flag: &str,
+    inline_value: Option<String>,
+    iter: &mut impl Iterator<Item = String>,
+) -> Result<String, String> {
+    inline_value
+        .or_else(|| iter.next())
+        .ok_or_else(|| format!("Missing value for '{}'", flag))
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Args, String> {
+        parse(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_files_and_options() {
+        let args = parse_line("notes.md -o out.pdf --base-dir=assets --backend native").unwrap();
+        assert_eq!(args.inputs, ["notes.md"]);
+        assert_eq!(args.output.as_deref(), Some("out.pdf"));
+        assert_eq!(args.base_dir.as_deref(), Some("assets"));
+        assert_eq!(args.backend.as_deref(), Some("native"));
+        assert!(!args.book);
+    }
+
+    #[test]
+    fn reads_stdin_and_writes_stdout() {
+        let args = parse_line("- --output -").unwrap();
+        assert_eq!(args.inputs, ["-"]);
+        assert_eq!(args.output.as_deref(), Some("-"));
+    }
+
+    #[test]
+    fn collects_variables_and_profiles() {
+        let args =
+            parse_line("a.md --var version=1.2=beta --profile print,draft --profile=web").unwrap();
+        assert_eq!(args.vars, [("version".to_string(), "1.2=beta".to_string())]);
+        assert_eq!(args.profiles, ["print", "draft", "web"]);
+    }
+
+    #[test]
+    fn rejects_invalid_combinations() {
+        for (line, error) in [
+            (
+                "a.md b.md -o out.pdf",
+                "--output can only be used with a single FILE",
+            ),
+            ("a.md -", "- (stdin) can only be used with a single FILE"),
+            ("--book -", "- (stdin) cannot be used with --book"),
+            (
+                "a.md --var version",
+                "--var expects NAME=VALUE, got 'version'",
+            ),
+            ("a.md --colour", "Unknown option '--colour'"),
+            ("a.md --backend", "Missing value for '--backend'"),
+        ] {
+            assert_eq!(parse_line(line).unwrap_err(), error, "{}", line);
+        }
+        assert_eq!(parse_line("a.md b.md").unwrap().inputs, ["a.md", "b.md"]);
+        assert!(parse_line("--book a.md b.md -o book.pdf").is_ok());
+    }
+}
@@ -0,0 +1,432 @@
+//! Fenced code block attributes: a title bar, line numbers and highlighted
+//! lines, given after the language in the fence's info string.
+//!
+//! ````markdown
+//! ```rust title="main.rs" linenos hl_lines="3-5 8"
+//! ````
+//!
+//! `linenos` numbers the lines (`linenos=false` turns off
+//! `code_blocks.line_numbers` for one block), `linenostart` sets the first
+//! number, and `hl_lines` counts lines from 1 at the top of the block
+//! whatever the numbering. Unknown attributes are ignored.
+//!
+//! `diff` blocks colour added and removed lines, and `diff-rust` and the like
+//! also highlight the code in the lines as that language.
+
+use crate::config::CodeBlocksConfig;
+use crate::markdown::escape_html;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Default)]
+pub struct CodeAttrs {
+    /// For diffs, the language of the lines being changed, if any.
+    pub language: String,
+    pub diff: bool,
+    pub title: String,
+    pub line_numbers: bool,
+    pub first_number: usize,
+    highlighted: Vec<RangeInclusive<usize>>,
+}
+
+impl CodeAttrs {
+    pub fn parse(info: &str, config: &CodeBlocksConfig) -> CodeAttrs {
+        let mut attrs = CodeAttrs {
+            line_numbers: config.line_numbers,
+            first_number: 1,
+            ..CodeAttrs::default()
+        };
+
+        for (index, token) in tokens(info).into_iter().enumerate() {
+            let (name, value) = match token.split_once('=') {
+                Some((name, value)) => (name, Some(unquote(value))),
+                None => (token.as_str(), None),
+            };
+            match (name, value) {
+                ("title", Some(value)) => attrs.title = value.to_string(),
+                ("linenos", None) => attrs.line_numbers = true,
+                ("linenos", Some(value)) => attrs.line_numbers = value != "false",
+                ("linenostart", Some(value)) => {
+                    if let Ok(start) = value.parse() {
+                        attrs.line_numbers = true;
+                        attrs.first_number = start;
+                    }
+                }
+                ("hl_lines", Some(value)) => attrs.highlighted = line_ranges(value),
+                ("diff", None) if index == 0 => attrs.diff = true,
+                (language, None) if index == 0 => match language.strip_prefix("diff-") {
+                    Some(language) => {
+                        attrs.diff = true;
+                        attrs.language = language.to_string();
+                    }
+                    None => attrs.language = language.to_string(),
+                },
+                _ => {}
+            }
+        }
+
+        attrs
+    }
+
+    /// Whether line `line` of the block, counting from 1, is highlighted.
+    pub fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted.iter().any(|range| range.contains(&line))
+    }
+
+    /// Whether the block is set line by line, for numbers, highlights or a
+    /// diff.
+    pub fn has_lines(&self) -> bool {
+        self.line_numbers || self.diff || !self.highlighted.is_empty()
+    }
+
+    /// The number printed beside line `line` of the block.
+    pub fn number(&self, line: usize) -> usize {
+        self.first_number + line - 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffLine {
+    Added,
+    Removed,
+    Unchanged,
+    /// `@@` hunk headers and the `diff`, `index`, `---` and `+++` lines
+    /// naming the files.
+    Header,
+}
+
+impl DiffLine {
+    /// The kind of every line of a diff. `---` and `+++` name files only as
+    /// a pair before a hunk; inside one they remove a line starting `--` or
+    /// add one starting `++`. A `diff` line starts the next file.
+    pub fn classify(lines: &[&str]) -> Vec<DiffLine> {
+        let mut in_hunk = false;
+        let mut kinds = Vec::with_capacity(lines.len());
+
+        for (index, line) in lines.iter().enumerate() {
+            let previous = index.checked_sub(1).map_or("", |index| lines[index]);
+            let next = lines.get(index + 1).copied().unwrap_or_default();
+            let kind = if line.starts_with("@@") {
+                in_hunk = true;
+                DiffLine::Header
+            } else if line.starts_with("diff ") {
+                in_hunk = false;
+                DiffLine::Header
+            } else if !in_hunk
+                && (line.starts_with("index ")
+                    || (line.starts_with("---") && next.starts_with("+++"))
+                    || (line.starts_with("+++") && previous.starts_with("---")))
+            {
+                DiffLine::Header
+            } else if line.starts_with('+') {
+                DiffLine::Added
+            } else if line.starts_with('-') {
+                DiffLine::Removed
+            } else {
+                DiffLine::Unchanged
+            };
+            kinds.push(kind);
+        }
+
+        kinds
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            DiffLine::Added => " diff-added",
+            DiffLine::Removed => " diff-removed",
+            DiffLine::Unchanged => "",
+            DiffLine::Header => " diff-header",
+        }
+    }
+}
+
+/// Splits an info string at whitespace outside quotes.
+fn tokens(info: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+
+    for c in info.chars() {
+        match (quote, c) {
+            (Some(open), c) if c == open => {
+                quote = None;
+                current.push(c);
+            }
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, c) if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            (_, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// `3-5 8` or `3-5,8` as line ranges. Anything else, including ranges that
+/// end before they start, is skipped with a warning.
+fn line_ranges(value: &str) -> Vec<RangeInclusive<usize>> {
+    let line = |number: &str| number.trim().parse::<usize>().ok();
+    let mut ranges = Vec::new();
+
+    for part in value.split(|c: char| c == ',' || c.is_whitespace()) {
+        let range = match part.split_once('-') {
+            Some((start, end)) => line(start).zip(line(end)),
+            None => line(part).map(|line| (line, line)),
+        };
+        match range {
+            Some((start, end)) if start <= end => ranges.push(start..=end),
+            Some(_) => eprintln!("Warning: hl_lines range '{}' ends before it starts", part),
+            None if part.is_empty() => {}
+            None => eprintln!("Warning: hl_lines entry '{}' is not a line or range", part),
+        }
+    }
+
+    ranges
+}
+
+/// The block as HTML: a title bar above the usual `<pre><code>`, and a
+/// `code-line` span per line inside the one `<code>` when lines are
+/// numbered, highlighted or diffed. Line numbers and a diff line's `+`, `-`
+/// or space sit outside its `line-text`, which is all the page script hands
+/// highlight.js, as one piece so that comments and strings can span lines.
+pub fn code_block_html(code: &str, attrs: &CodeAttrs) -> String {
+    let language = if attrs.language.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"language-{}\"", escape_html(&attrs.language))
+    };
+
+    let mut html = String::from("<div class=\"code-block\">\n");
+    if !attrs.title.is_empty() {
+        html.push_str(&format!(
+            "<div class=\"code-title\">{}</div>\n",
+            escape_html(&attrs.title)
+        ));
+    }
+
+    if !attrs.has_lines() {
+        html.push_str(&format!(
+            "<pre><code{}>{}</code></pre>\n</div>\n",
+            language,
+            escape_html(code)
+        ));
+        return html;
+    }
+
+    let lines: Vec<&str> = code
+        .strip_suffix('\n')
+        .unwrap_or(code)
+        .split('\n')
+        .collect();
+    let digits = attrs.number(lines.len()).to_string().len();
+    let diffs = if attrs.diff {
+        DiffLine::classify(&lines)
+    } else {
+        vec![DiffLine::Unchanged; lines.len()]
+    };
+
+    // No newlines between the lines: `pre` would show them as blank lines.
+    html.push_str(&format!(
+        "<pre class=\"code-lines\" style=\"--line-number-digits: {}\"><code{}>",
+        digits, language
+    ));
+    for (index, line) in lines.iter().enumerate() {
+        let line_number = index + 1;
+        let diff = diffs[index];
+        let highlighted = if attrs.is_highlighted(line_number) {
+            " highlighted"
+        } else {
+            ""
+        };
+        html.push_str(&format!(
+            "<span class=\"code-line{}{}\">",
+            diff.class(),
+            highlighted
+        ));
+        if attrs.line_numbers {
+            html.push_str(&format!(
+                "<span class=\"line-number\">{}</span>",
+                attrs.number(line_number)
+            ));
+        }
+
+        let text = match diff {
+            DiffLine::Header => *line,
+            _ if attrs.diff => {
+                let marker = line.chars().next().map_or(0, char::len_utf8);
+                html.push_str(&format!(
+                    "<span class=\"diff-marker\">{}</span>",
+                    escape_html(&line[..marker])
+                ));
+                &line[marker..]
+            }
+            _ => *line,
+        };
+        html.push_str(&format!(
+            "<span class=\"line-text\">{}</span></span>",
+            escape_html(text)
+        ));
+    }
+    html.push_str("</code></pre>\n</div>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use DiffLine::*;
+
+    fn attrs(info: &str) -> CodeAttrs {
+        CodeAttrs::parse(info, &get_default_config().code_blocks)
+    }
+
+    fn classify(diff: &str) -> Vec<DiffLine> {
+        DiffLine::classify(&diff.lines().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_language_and_attributes() {
+        let attrs = attrs(r#"rust title="src/main.rs" linenos hl_lines="3-5,8""#);
+        assert_eq!(attrs.language, "rust");
+        assert!(!attrs.diff);
+        assert_eq!(attrs.title, "src/main.rs");
+        assert!(attrs.line_numbers);
+        assert_eq!(attrs.number(1), 1);
+        assert!(attrs.has_lines());
+        let highlighted: Vec<usize> = (1..=9).filter(|&line| attrs.is_highlighted(line)).collect();
+        assert_eq!(highlighted, [3, 4, 5, 8]);
+    }
+
+    #[test]
+    fn parses_quoted_titles_with_spaces() {
+        assert_eq!(
+            attrs(r#"python title="My script.py""#).title,
+            "My script.py"
+        );
+        assert_eq!(
+            attrs("python title='It is \"quoted\"'").title,
+            "It is \"quoted\""
+        );
+        let attrs = attrs(r#"title="No language here""#);
+        assert_eq!(attrs.title, "No language here");
+        assert_eq!(attrs.language, "");
+    }
+
+    #[test]
+    fn parses_line_numbering() {
+        let mut config = get_default_config().code_blocks;
+        config.line_numbers = true;
+        assert!(CodeAttrs::parse("rust", &config).line_numbers);
+        assert!(!CodeAttrs::parse("rust linenos=false", &config).line_numbers);
+
+        let attrs = attrs("rust linenostart=10");
+        assert!(attrs.line_numbers);
+        assert_eq!(attrs.number(1), 10);
+        assert_eq!(attrs.number(3), 12);
+        assert!(!self::attrs("rust linenostart=x").line_numbers);
+    }
+
+    #[test]
+    fn counts_highlighted_lines_from_the_top_of_the_block() {
+        let attrs = attrs(r#"rust linenostart=10 hl_lines="2 4""#);
+        assert!(attrs.is_highlighted(2));
+        assert!(!attrs.is_highlighted(11));
+        assert!(attrs.is_highlighted(4));
+    }
+
+    #[test]
+    fn skips_inverted_and_invalid_ranges() {
+        assert_eq!(line_ranges("5-3"), []);
+        assert_eq!(line_ranges("5-3, 7"), [7..=7]);
+        assert_eq!(line_ranges("x 2-y 1-2"), [1..=2]);
+        assert!(!attrs(r#"rust hl_lines="5-3""#).has_lines());
+    }
+
+    #[test]
+    fn renders_titles_and_numbered_lines() {
+        let html = code_block_html(
+            "let a = 1;\nlet b = 2;\n",
+            &attrs(r#"rust title="a & b" linenos"#),
+        );
+        assert!(html.contains("<div class=\"code-title\">a &amp; b</div>"));
+        assert!(html.contains("<span class=\"code-line\"><span class=\"line-number\">2</span><span class=\"line-text\">let b = 2;</span></span>"));
+        assert_eq!(html.matches("code-line\"").count(), 2);
+    }
+
+    #[test]
+    fn keeps_numbered_lines_in_one_code_element() {
+        let html = code_block_html("/* one\n   two */\nfn f() {}\n", &attrs("rust linenos"));
+        assert!(html.contains("<pre class=\"code-lines\" style=\"--line-number-digits: 1\"><code class=\"language-rust\"><span class=\"code-line\"><span class=\"line-number\">1</span><span class=\"line-text\">/* one</span></span>"));
+        assert!(html.contains(
+            "<span class=\"line-number\">2</span><span class=\"line-text\">   two */</span>"
+        ));
+        assert!(html
+            .ends_with("<span class=\"line-text\">fn f() {}</span></span></code></pre>\n</div>\n"));
+        assert_eq!(html.matches("<code").count(), 1);
+    }
+
+    #[test]
+    fn classifies_file_headers_and_hunks() {
+        let diff = "diff --git a/x.rs b/x.rs\nindex 1..2 100644\n--- a/x.rs\n+++ b/x.rs\n@@ -1,2 +1,2 @@\n fn main() {\n-    old();\n+    new();\n";
+        assert_eq!(
+            classify(diff),
+            [Header, Header, Header, Header, Header, Unchanged, Removed, Added]
+        );
+    }
+
+    #[test]
+    fn treats_dashes_inside_hunks_as_changes() {
+        let diff = "@@ -1,3 +1,3 @@\n--- removed comment\n+++ added comment\n---\n+++\n";
+        assert_eq!(classify(diff), [Header, Removed, Added, Removed, Added]);
+    }
+
+    #[test]
+    fn needs_both_file_lines_for_a_header() {
+        assert_eq!(classify("--- a\n-b\n+c"), [Removed, Removed, Added]);
+        assert_eq!(classify("-a\n+++ b"), [Removed, Added]);
+    }
+
+    #[test]
+    fn starts_a_new_file_after_a_diff_line() {
+        let diff = "@@ -1 +1 @@\n-a\n+b\ndiff --git a/y b/y\n--- a/y\n+++ b/y\n@@ -1 +1 @@\n---x\n";
+        assert_eq!(
+            classify(diff),
+            [Header, Removed, Added, Header, Header, Header, Header, Removed]
+        );
+    }
+
+    #[test]
+    fn keeps_diff_markers_out_of_the_code() {
+        let attrs = attrs("diff-rust");
+        assert!(attrs.diff);
+        assert_eq!(attrs.language, "rust");
+
+        let html = code_block_html("@@ -1 +1 @@\n--- x\n+ y\n", &attrs);
+        assert!(html.contains("<code class=\"language-rust\"><span class=\"code-line diff-header\"><span class=\"line-text\">@@ -1 +1 @@</span>"));
+        assert!(html.contains("<span class=\"code-line diff-removed\"><span class=\"diff-marker\">-</span><span class=\"line-text\">-- x</span>"));
+        assert!(html.contains("<span class=\"code-line diff-added\"><span class=\"diff-marker\">+</span><span class=\"line-text\"> y</span>"));
+        assert_eq!(html.matches("<code").count(), 1);
+    }
+}
@@ -0,0 +1,222 @@
+//! Conditional content for producing several editions from one source.
+//!
+//! ```markdown
+//! <!-- if: internal -->
+//! Internal support hotline: 555-0100
+//! <!-- else -->
+//! Contact your account manager.
+//! <!-- endif -->
+//! ```
+//!
+//! A condition names a profile, `!name` negates one, and a comma-separated
+//! list holds when any of its terms does. Blocks nest. The markers must stand
+//! on lines of their own, and are left alone inside fenced code blocks.
+
+use crate::markdown::CodeFences;
+
+enum Marker<'a> {
+    If(&'a str),
+    Else,
+    EndIf,
+}
+
+/// An `if` block that is still open.
+struct Block {
+    line: usize,
+    holds: bool,
+    in_else: bool,
+}
+
+impl Block {
+    fn keeps(&self) -> bool {
+        self.holds != self.in_else
+    }
+}
+
+/// Removes the branches of conditional blocks that do not apply to the
+/// active `profiles`, along with the markers.
+pub fn filter_conditionals(markdown: &str, profiles: &[String]) -> Result<String, String> {
+    let mut output = String::with_capacity(markdown.len());
+    let mut fences = CodeFences::default();
+    let mut blocks: Vec<Block> = Vec::new();
+
+    for (index, line) in markdown.split_inclusive('\n').enumerate() {
+        let number = index + 1;
+        let marker = if fences.is_code(line) {
+            None
+        } else {
+            marker(line)
+        };
+
+        match marker {
+            None => {
+                if blocks.iter().all(Block::keeps) {
+                    output.push_str(line);
+                }
+            }
+            Some(Marker::If(condition)) => {
+                if condition.is_empty() {
+                    return Err(format!("line {}: <!-- if: --> needs a condition", number));
+                }
+                blocks.push(Block {
+                    line: number,
+                    holds: holds(condition, profiles),
+                    in_else: false,
+                });
+            }
+            Some(Marker::Else) => match blocks.last_mut() {
+                Some(block) if !block.in_else => block.in_else = true,
+                Some(block) => {
+                    return Err(format!(
+                        "line {}: second <!-- else --> for the <!-- if --> on line {}",
+                        number, block.line
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "line {}: <!-- else --> without an <!-- if -->",
+                        number
+                    ))
+                }
+            },
+            Some(Marker::EndIf) => {
+                if blocks.pop().is_none() {
+                    return Err(format!(
+                        "line {}: <!-- endif --> without an <!-- if -->",
+                        number
+                    ));
+                }
+            }
+        }
+    }
+
+    match blocks.last() {
+        Some(block) => Err(format!(
+            "line {}: <!-- if --> is never closed with <!-- endif -->",
+            block.line
+        )),
+        None => Ok(output),
+    }
+}
+
+fn marker(line: &str) -> Option<Marker<'_>> {
+    let inner = line
+        .trim()
+        .strip_prefix("<!--")?
+        .strip_suffix("-->")?
+        .trim();
+
+    match inner {
+        "else" => Some(Marker::Else),
+        "endif" => Some(Marker::EndIf),
+        _ => inner
+            .strip_prefix("if:")
+            .map(|condition| Marker::If(condition.trim())),
+    }
+}
+
+/// Whether any of the comma-separated terms of `condition` holds.
+fn holds(condition: &str, profiles: &[String]) -> bool {
+    condition.split(',').map(str::trim).any(|term| {
+        let (negated, name) = match term.strip_prefix('!') {
+            Some(name) => (true, name.trim()),
+            None => (false, term),
+        };
+        profiles.iter().any(|profile| profile == name) != negated
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+Intro
+<!-- if: internal -->
+Hotline
+<!-- else -->
+Account manager
+<!-- endif -->
+Outro
+";
+
+    fn filter(markdown: &str, profiles: &[&str]) -> Result<String, String> {
+        let profiles: Vec<String> = profiles.iter().map(|p| p.to_string()).collect();
+        filter_conditionals(markdown, &profiles)
+    }
+
+    #[test]
+    fn keeps_the_branch_for_the_active_profiles() {
+        assert_eq!(
+            filter(SOURCE, &["internal"]).unwrap(),
+            "Intro\nHotline\nOutro\n"
+        );
+        assert_eq!(
+            filter(SOURCE, &[]).unwrap(),
+            "Intro\nAccount manager\nOutro\n"
+        );
+    }
+
+    #[test]
+    fn evaluates_negations_and_lists() {
+        let markdown =
+            "<!-- if: !print -->\nWeb\n<!-- endif -->\n<!-- if: a, b -->\nA or B\n<!-- endif -->\n";
+        assert_eq!(filter(markdown, &[]).unwrap(), "Web\n");
+        assert_eq!(filter(markdown, &["print", "b"]).unwrap(), "A or B\n");
+    }
+
+    #[test]
+    fn nests_blocks() {
+        let markdown = "\
+<!-- if: a -->
+A
+<!-- if: b -->
+A and B
+<!-- else -->
+A not B
+<!-- endif -->
+<!-- else -->
+Not A
+<!-- if: b -->
+Not A but B
+<!-- endif -->
+<!-- endif -->
+";
+        assert_eq!(filter(markdown, &["a", "b"]).unwrap(), "A\nA and B\n");
+        assert_eq!(filter(markdown, &["a"]).unwrap(), "A\nA not B\n");
+        assert_eq!(filter(markdown, &["b"]).unwrap(), "Not A\nNot A but B\n");
+        assert_eq!(filter(markdown, &[]).unwrap(), "Not A\n");
+    }
+
+    #[test]
+    fn leaves_markers_in_code_and_inline_alone() {
+        let markdown = "```\n<!-- if: a -->\n```\nText <!-- endif -->\n";
+        assert_eq!(filter(markdown, &[]).unwrap(), markdown);
+    }
+
+    #[test]
+    fn reports_unbalanced_markers() {
+        let cases = [
+            ("<!-- if: -->\n", "line 1: <!-- if: --> needs a condition"),
+            (
+                "x\n<!-- else -->\n",
+                "line 2: <!-- else --> without an <!-- if -->",
+            ),
+            (
+                "<!-- endif -->\n",
+                "line 1: <!-- endif --> without an <!-- if -->",
+            ),
+            (
+                "<!-- if: a -->\n<!-- else -->\n<!-- else -->\n",
+                "line 3: second <!-- else --> for the <!-- if --> on line 1",
+            ),
+            (
+                "<!-- if: a -->\n<!-- if: b -->\n<!-- endif -->\n",
+                "line 1: <!-- if --> is never closed with <!-- endif -->",
+            ),
+        ];
+        for (markdown, error) in cases {
+            assert_eq!(filter(markdown, &[]).unwrap_err(), error);
+        }
+    }
+}
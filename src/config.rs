@@ -0,0 +1,1170 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const COMPACT_TABLE_PADDING: &str = "2pt 4pt";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub page: PageConfig,
+    pub fonts: FontsConfig,
+    pub headings: HeadingsConfig,
+    pub spacing: SpacingConfig,
+    pub code_blocks: CodeBlocksConfig,
+    pub syntax_highlighting: SyntaxHighlightingConfig,
+    pub images: ImagesConfig,
+    pub title_page: TitlePageConfig,
+    #[serde(default)]
+    pub captions: CaptionsConfig,
+    #[serde(default)]
+    pub links: LinksConfig,
+    #[serde(default)]
+    pub toc: TocConfig,
+    #[serde(default)]
+    pub book: BookConfig,
+    #[serde(default)]
+    pub variables: VariablesConfig,
+    #[serde(default)]
+    pub profiles: ProfilesConfig,
+    #[serde(default)]
+    pub citations: CitationsConfig,
+    #[serde(default)]
+    pub tables: TablesConfig,
+    #[serde(default)]
+    pub blockquote: BlockquoteConfig,
+    #[serde(default)]
+    pub lists: ListsConfig,
+    #[serde(default)]
+    pub print: PrintConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PageConfig {
+    pub margin: String,
+    pub first_page_top_margin: String,
+    #[serde(default)]
+    pub columns: ColumnsConfig,
+}
+
+/// Columns the body text is set in. `<!-- columns: N -->` blocks override
+/// them for a section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ColumnsConfig {
+    pub count: usize,
+    pub gap: String,
+    /// CSS border shorthand for a line between columns, or `none`.
+    pub rule: String,
+    /// Headings of this level and above span every column; 0 for none.
+    pub span_headings: u8,
+}
+
+impl Default for ColumnsConfig {
+    fn default() -> Self {
+        ColumnsConfig {
+            count: 1,
+            gap: "1em".to_string(),
+            rule: "none".to_string(),
+            span_headings: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FontsConfig {
+    pub body_family: String,
+    pub body_size: String,
+    pub code_family: String,
+    pub inline_code_size: String,
+    pub block_code_size: String,
+    /// Font files embedded in the output, so it does not depend on what is
+    /// installed where the conversion runs.
+    #[serde(default)]
+    pub files: Vec<FontFaceConfig>,
+}
+
+/// One face of a family: a TTF, OTF or WOFF2 file, relative to config.json.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FontFaceConfig {
+    pub family: String,
+    pub path: String,
+    /// `normal`, `bold` or a number from 100 to 900.
+    #[serde(default = "default_font_weight")]
+    pub weight: String,
+    /// `normal` or `italic`.
+    #[serde(default = "default_font_style")]
+    pub style: String,
+}
+
+fn default_font_weight() -> String {
+    "normal".to_string()
+}
+
+fn default_font_style() -> String {
+    "normal".to_string()
+}
+
+impl FontFaceConfig {
+    pub fn is_bold(&self) -> bool {
+        match self.weight.trim() {
+            "bold" | "bolder" => true,
+            weight => weight.parse::<u32>().is_ok_and(|weight| weight >= 600),
+        }
+    }
+
+    pub fn is_italic(&self) -> bool {
+        matches!(self.style.trim(), "italic" | "oblique")
+    }
+
+    /// The file, with a relative path taken from config.json's directory.
+    pub fn file_path(&self) -> PathBuf {
+        config_dir().join(&self.path)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeadingsConfig {
+    pub h1_size: String,
+    pub h1_align: String,
+    pub h1_page_break_before: bool,
+    #[serde(default)]
+    pub h1_page_break_after: bool,
+    #[serde(default = "default_keep_with_next")]
+    pub h1_keep_with_next: bool,
+    pub h2_size: String,
+    pub h2_page_break_before: bool,
+    #[serde(default)]
+    pub h2_page_break_after: bool,
+    #[serde(default = "default_keep_with_next")]
+    pub h2_keep_with_next: bool,
+    pub h3_size: String,
+    #[serde(default)]
+    pub h3_page_break_before: bool,
+    #[serde(default)]
+    pub h3_page_break_after: bool,
+    #[serde(default = "default_keep_with_next")]
+    pub h3_keep_with_next: bool,
+    pub h4_size: String,
+    #[serde(default)]
+    pub h4_page_break_before: bool,
+    #[serde(default)]
+    pub h4_page_break_after: bool,
+    #[serde(default = "default_keep_with_next")]
+    pub h4_keep_with_next: bool,
+    pub h5_size: String,
+    #[serde(default)]
+    pub h5_page_break_before: bool,
+    #[serde(default)]
+    pub h5_page_break_after: bool,
+    #[serde(default = "default_keep_with_next")]
+    pub h5_keep_with_next: bool,
+    pub h6_size: String,
+    #[serde(default)]
+    pub h6_page_break_before: bool,
+    #[serde(default)]
+    pub h6_page_break_after: bool,
+    #[serde(default = "default_keep_with_next")]
+    pub h6_keep_with_next: bool,
+    #[serde(default)]
+    pub numbering: NumberingConfig,
+}
+
+fn default_keep_with_next() -> bool {
+    true
+}
+
+impl HeadingsConfig {
+    /// Whether headings of `level` (1 to 6) start a new page.
+    pub fn page_break_before(&self, level: u8) -> bool {
+        match level {
+            1 => self.h1_page_break_before,
+            2 => self.h2_page_break_before,
+            3 => self.h3_page_break_before,
+            4 => self.h4_page_break_before,
+            5 => self.h5_page_break_before,
+            _ => self.h6_page_break_before,
+        }
+    }
+
+    /// Whether a new page follows headings of `level`.
+    pub fn page_break_after(&self, level: u8) -> bool {
+        match level {
+            1 => self.h1_page_break_after,
+            2 => self.h2_page_break_after,
+            3 => self.h3_page_break_after,
+            4 => self.h4_page_break_after,
+            5 => self.h5_page_break_after,
+            _ => self.h6_page_break_after,
+        }
+    }
+
+    /// Whether headings of `level` stay on the same page as what follows.
+    pub fn keep_with_next(&self, level: u8) -> bool {
+        match level {
+            1 => self.h1_keep_with_next,
+            2 => self.h2_keep_with_next,
+            3 => self.h3_keep_with_next,
+            4 => self.h4_keep_with_next,
+            5 => self.h5_keep_with_next,
+            _ => self.h6_keep_with_next,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NumberingConfig {
+    pub enabled: bool,
+    pub h1_chapter: bool,
+    pub max_level: u8,
+    pub format: String,
+    pub separator: String,
+}
+
+impl Default for NumberingConfig {
+    fn default() -> Self {
+        NumberingConfig {
+            enabled: false,
+            h1_chapter: false,
+            max_level: 3,
+            format: "1.1.1".to_string(),
+            separator: " ".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpacingConfig {
+    pub line_height: String,
+    pub paragraph_margin: String,
+    pub h1_bottom_margin: String,
+    pub h2_bottom_margin: String,
+    pub h3_margins: String,
+    pub h4_margins: String,
+    pub h5_margins: String,
+    pub h6_margins: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CodeBlocksConfig {
+    pub background_color: String,
+    pub border: String,
+    pub padding: String,
+    pub margin: String,
+    pub word_wrap: bool,
+    pub page_break_inside: bool,
+    /// Number every block's lines, as if each fence said `linenos`.
+    #[serde(default)]
+    pub line_numbers: bool,
+    #[serde(default = "default_line_number_color")]
+    pub line_number_color: String,
+    /// Background of the lines picked out with `hl_lines`.
+    #[serde(default = "default_highlight_background")]
+    pub highlight_background: String,
+    #[serde(default = "default_title_background")]
+    pub title_background: String,
+    #[serde(default = "default_title_color")]
+    pub title_color: String,
+    /// Backgrounds of the lines a `diff` block adds and removes, and of its
+    /// hunk and file headers.
+    #[serde(default = "default_diff_added_background")]
+    pub diff_added_background: String,
+    #[serde(default = "default_diff_removed_background")]
+    pub diff_removed_background: String,
+    #[serde(default = "default_diff_header_background")]
+    pub diff_header_background: String,
+}
+
+fn default_line_number_color() -> String {
+    "#999".to_string()
+}
+
+fn default_highlight_background() -> String {
+    "#fff5b1".to_string()
+}
+
+fn default_title_background() -> String {
+    "#e8e8e8".to_string()
+}
+
+fn default_title_color() -> String {
+    "#333".to_string()
+}
+
+fn default_diff_added_background() -> String {
+    "#e6ffed".to_string()
+}
+
+fn default_diff_removed_background() -> String {
+    "#ffeef0".to_string()
+}
+
+fn default_diff_header_background() -> String {
+    "#f1f8ff".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyntaxHighlightingConfig {
+    pub theme: String,
+    pub enabled: bool,
+    pub text_color: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImagesConfig {
+    pub show_captions: bool,
+    pub caption_size: String,
+    pub caption_style: String,
+    pub caption_align: String,
+    pub caption_color: String,
+    #[serde(default)]
+    pub embed: bool,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub search_paths: Vec<String>,
+    #[serde(default = "default_image_max_width")]
+    pub max_width: String,
+    #[serde(default = "default_image_max_height")]
+    pub max_height: String,
+}
+
+fn default_image_max_width() -> String {
+    "100%".to_string()
+}
+
+fn default_image_max_height() -> String {
+    "80vh".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TitlePageConfig {
+    pub extract_header: bool,
+    pub first_paragraph_size: String,
+    #[serde(default)]
+    pub cover: CoverConfig,
+}
+
+/// A cover page of its own, built from these values and the document's
+/// front matter.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CoverConfig {
+    pub enabled: bool,
+    pub title: String,
+    pub subtitle: String,
+    pub authors: Vec<AuthorConfig>,
+    pub date: String,
+    pub version: String,
+    pub logo: String,
+    #[serde(rename = "abstract")]
+    pub abstract_text: String,
+    pub notice: String,
+    pub align: String,
+    pub title_size: String,
+    pub subtitle_size: String,
+    pub logo_width: String,
+    pub logo_position: String,
+}
+
+impl Default for CoverConfig {
+    fn default() -> Self {
+        CoverConfig {
+            enabled: false,
+            title: String::new(),
+            subtitle: String::new(),
+            authors: Vec::new(),
+            date: String::new(),
+            version: String::new(),
+            logo: String::new(),
+            abstract_text: String::new(),
+            notice: String::new(),
+            align: "center".to_string(),
+            title_size: "32pt".to_string(),
+            subtitle_size: "18pt".to_string(),
+            logo_width: "2in".to_string(),
+            logo_position: "top".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthorConfig {
+    pub name: String,
+    pub affiliation: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CaptionsConfig {
+    pub numbering: bool,
+    pub figure_label: String,
+    pub table_label: String,
+    pub section_label: String,
+    pub separator: String,
+    pub table_position: String,
+}
+
+impl Default for CaptionsConfig {
+    fn default() -> Self {
+        CaptionsConfig {
+            numbering: true,
+            figure_label: "Figure".to_string(),
+            table_label: "Table".to_string(),
+            section_label: "Section".to_string(),
+            separator: ": ".to_string(),
+            table_position: "above".to_string(),
+        }
+    }
+}
+
+impl CaptionsConfig {
+    pub fn table_caption_above(&self) -> bool {
+        self.table_position != "below"
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LinksConfig {
+    pub rewrite_markdown: bool,
+    pub unconverted: String,
+    pub color: String,
+    pub underline: bool,
+    /// `none`, `inline` (after the link text) or `footnote`.
+    pub show_url: String,
+}
+
+impl Default for LinksConfig {
+    fn default() -> Self {
+        LinksConfig {
+            rewrite_markdown: true,
+            unconverted: "pdf".to_string(),
+            color: "#0000ee".to_string(),
+            underline: true,
+            show_url: "none".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TocConfig {
+    pub title: String,
+    pub max_level: u8,
+}
+
+impl Default for TocConfig {
+    fn default() -> Self {
+        TocConfig {
+            title: "Contents".to_string(),
+            max_level: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BookConfig {
+    pub files: Vec<String>,
+    pub title: String,
+    pub toc: bool,
+    pub chapter_page_break: bool,
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        BookConfig {
+            files: Vec::new(),
+            title: String::new(),
+            toc: true,
+            chapter_page_break: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VariablesConfig {
+    pub values: HashMap<String, String>,
+    pub in_code: bool,
+}
+
+/// Named editions of a document. Each active profile switches on the
+/// `<!-- if: name -->` blocks for it and merges its overrides into the config.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProfilesConfig {
+    pub active: Vec<String>,
+    pub overrides: HashMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CitationsConfig {
+    pub bibliography: String,
+    pub style: String,
+    pub title: String,
+}
+
+impl Default for CitationsConfig {
+    fn default() -> Self {
+        CitationsConfig {
+            bibliography: String::new(),
+            style: "numeric".to_string(),
+            title: "References".to_string(),
+        }
+    }
+}
+
+impl CitationsConfig {
+    /// Whether citations read "(Doe 2020)" rather than "[1]".
+    pub fn author_year(&self) -> bool {
+        self.style.eq_ignore_ascii_case("author-year")
+    }
+}
+
+/// Table borders, colours and sizes, and what happens to tables with more
+/// than `wide_columns` columns: `wrap` them as usual, `shrink` their text to
+/// `shrink_font_size`, or put them on a `landscape` page of their own.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TablesConfig {
+    pub border: String,
+    pub padding: String,
+    pub compact: bool,
+    pub font_size: String,
+    pub align: String,
+    pub markdown_alignment: bool,
+    pub header_background: String,
+    pub header_color: String,
+    pub repeat_header: bool,
+    pub zebra: bool,
+    pub zebra_background: String,
+    pub wide_columns: usize,
+    pub wide: String,
+    pub shrink_font_size: String,
+}
+
+impl Default for TablesConfig {
+    fn default() -> Self {
+        TablesConfig {
+            border: "1px solid #000".to_string(),
+            padding: "6pt".to_string(),
+            compact: false,
+            font_size: "100%".to_string(),
+            align: "left".to_string(),
+            markdown_alignment: true,
+            header_background: "#f5f5f5".to_string(),
+            header_color: "#000000".to_string(),
+            repeat_header: true,
+            zebra: false,
+            zebra_background: "#fafafa".to_string(),
+            wide_columns: 6,
+            wide: "wrap".to_string(),
+            shrink_font_size: "80%".to_string(),
+        }
+    }
+}
+
+impl TablesConfig {
+    /// Cell padding, tighter in compact mode.
+    pub fn cell_padding(&self) -> &str {
+        if self.compact {
+            COMPACT_TABLE_PADDING
+        } else {
+            &self.padding
+        }
+    }
+
+    /// Whether a table with `columns` columns gets the `wide` treatment.
+    pub fn is_wide(&self, columns: usize) -> bool {
+        self.wide_columns > 0 && columns > self.wide_columns && self.wide != "wrap"
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BlockquoteConfig {
+    pub border_width: String,
+    pub border_color: String,
+    pub color: String,
+    pub padding: String,
+    pub italic: bool,
+}
+
+impl Default for BlockquoteConfig {
+    fn default() -> Self {
+        BlockquoteConfig {
+            border_width: "3px".to_string(),
+            border_color: "#ccc".to_string(),
+            color: "#666".to_string(),
+            padding: "12pt".to_string(),
+            italic: false,
+        }
+    }
+}
+
+/// List markers by nesting level, the first entry for top-level lists. The
+/// last entry carries on for deeper levels.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ListsConfig {
+    pub indent: String,
+    pub item_spacing: String,
+    /// CSS keywords (`disc`, `circle`, `square`) or any character.
+    pub bullets: Vec<String>,
+    /// `decimal`, `lower-alpha`, `upper-alpha`, `lower-roman` or
+    /// `upper-roman`.
+    pub numbering: Vec<String>,
+    /// Numbers nested ordered lists `1.1`, `1.1.1` and so on.
+    pub legal: bool,
+}
+
+impl Default for ListsConfig {
+    fn default() -> Self {
+        ListsConfig {
+            indent: "24pt".to_string(),
+            item_spacing: "6pt".to_string(),
+            bullets: vec![
+                "disc".to_string(),
+                "circle".to_string(),
+                "square".to_string(),
+            ],
+            numbering: vec!["decimal".to_string()],
+            legal: false,
+        }
+    }
+}
+
+impl ListsConfig {
+    /// The bullet for lists nested `depth` deep.
+    pub fn bullet(&self, depth: usize) -> &str {
+        level(&self.bullets, depth).unwrap_or("disc")
+    }
+
+    /// The numbering style for lists nested `depth` deep.
+    pub fn numbering(&self, depth: usize) -> &str {
+        level(&self.numbering, depth).unwrap_or("decimal")
+    }
+}
+
+fn level(values: &[String], depth: usize) -> Option<&str> {
+    values.get(depth).or(values.last()).map(String::as_str)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrintConfig {
+    pub backend: String,
+    pub paper_size: String,
+    pub landscape: bool,
+    pub print_background: bool,
+    pub outline: bool,
+    pub header_template: String,
+    pub footer_template: String,
+    pub settle_ms: u64,
+    pub timeout_secs: u64,
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for PrintConfig {
+    fn default() -> Self {
+        PrintConfig {
+            backend: "auto".to_string(),
+            paper_size: "A4".to_string(),
+            landscape: false,
+            print_background: true,
+            outline: true,
+            header_template: String::new(),
+            footer_template: String::new(),
+            settle_ms: 250,
+            timeout_secs: 120,
+            retries: 0,
+            retry_backoff_ms: 1000,
+        }
+    }
+}
+
+impl PrintConfig {
+    /// Paper width and height in inches, honouring `landscape`.
+    pub fn paper_inches(&self) -> (f64, f64) {
+        let (width, height) = match self.paper_size.to_ascii_lowercase().as_str() {
+            "letter" => (8.5, 11.0),
+            "legal" => (8.5, 14.0),
+            "a3" => (11.69, 16.54),
+            "a5" => (5.83, 8.27),
+            "a4" => (8.27, 11.69),
+            other => {
+                eprintln!("Warning: unknown paper size '{}', using A4", other);
+                (8.27, 11.69)
+            }
+        };
+
+        if self.landscape {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+/// Converts a single CSS length (`1in`, `2.5cm`, `20mm`, `72pt`, `96px`) to inches.
+pub fn length_to_inches(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let inches = match unit.trim() {
+        "in" => number,
+        "cm" => number / 2.54,
+        "mm" => number / 25.4,
+        "pt" => number / 72.0,
+        "pc" => number / 6.0,
+        "px" => number / 96.0,
+        "" if number == 0.0 => 0.0,
+        _ => return None,
+    };
+
+    Some(inches)
+}
+
+/// Loads config.json from next to the executable. `profiles` from the command
+/// line replace `profiles.active`.
+pub fn load_config(profiles: &[String]) -> Config {
+    let config_path = config_dir().join("config.json");
+
+    let mut config = if config_path.exists() {
+        let config_content = fs::read_to_string(&config_path).expect("Failed to read config.json");
+        match parse_config(&config_content, profiles) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error parsing config.json: {}", e);
+                eprintln!("Using default configuration instead.");
+                get_default_config()
+            }
+        }
+    } else {
+        eprintln!("Warning: config.json not found, using default configuration");
+        get_default_config()
+    };
+
+    if !profiles.is_empty() {
+        config.profiles.active = profiles.to_vec();
+    }
+    config
+}
+
+/// The directory holding config.json, next to the executable.
+pub fn config_dir() -> PathBuf {
+    let exe_path = env::current_exe().expect("Failed to get executable path");
+    exe_path
+        .parent()
+        .expect("Failed to get executable directory")
+        .to_path_buf()
+}
+
+/// Parses the config with the overrides of every active profile merged in,
+/// in order.
+fn parse_config(content: &str, profiles: &[String]) -> serde_json::Result<Config> {
+    let mut value: Value = serde_json::from_str(content)?;
+
+    let active: Vec<String> = if profiles.is_empty() {
+        value
+            .pointer("/profiles/active")
+            .and_then(Value::as_array)
+            .map(|active| {
+                active
+                    .iter()
+                    .filter_map(|profile| profile.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        profiles.to_vec()
+    };
+
+    for profile in &active {
+        let overrides = value
+            .get("profiles")
+            .and_then(|profiles| profiles.get("overrides"))
+            .and_then(|overrides| overrides.get(profile))
+            .cloned();
+        if let Some(overrides) = overrides {
+            merge(&mut value, overrides);
+        }
+    }
+
+    serde_json::from_value(value)
+}
+
+/// Merges `overrides` into `value`: objects key by key, anything else by
+/// replacing it.
+fn merge(value: &mut Value, overrides: Value) {
+    match (value, overrides) {
+        (Value::Object(value), Value::Object(overrides)) => {
+            for (key, overrides) in overrides {
+                match value.get_mut(&key) {
+                    Some(value) => merge(value, overrides),
+                    None => {
+                        value.insert(key, overrides);
+                    }
+                }
+            }
+        }
+        (value, overrides) => *value = overrides,
+    }
+}
+
+pub(crate) fn get_default_config() -> Config {
+    serde_json::from_str(DEFAULT_CONFIG).expect("Failed to parse default config")
+}
+
+const DEFAULT_CONFIG: &str = r##"{
+    "page": {
+        "margin": "1in",
+        "first_page_top_margin": "2in",
+        "columns": {
+            "count": 1,
+            "gap": "1em",
+            "rule": "none",
+            "span_headings": 2
+        }
+    },
+    "fonts": {
+        "body_family": "Times New Roman",
+        "body_size": "12pt",
+        "code_family": "Courier New",
+        "inline_code_size": "12pt",
+        "block_code_size": "9pt",
+        "files": []
+    },
+    "headings": {
+        "h1_size": "24pt",
+        "h1_align": "center",
+        "h1_page_break_before": true,
+        "h1_page_break_after": false,
+        "h1_keep_with_next": true,
+        "h2_size": "16pt",
+        "h2_page_break_before": true,
+        "h2_page_break_after": false,
+        "h2_keep_with_next": true,
+        "h3_size": "14pt",
+        "h3_page_break_before": false,
+        "h3_page_break_after": false,
+        "h3_keep_with_next": true,
+        "h4_size": "13pt",
+        "h4_page_break_before": false,
+        "h4_page_break_after": false,
+        "h4_keep_with_next": true,
+        "h5_size": "12pt",
+        "h5_page_break_before": false,
+        "h5_page_break_after": false,
+        "h5_keep_with_next": true,
+        "h6_size": "12pt",
+        "h6_page_break_before": false,
+        "h6_page_break_after": false,
+        "h6_keep_with_next": true,
+        "numbering": {
+            "enabled": false,
+            "h1_chapter": false,
+            "max_level": 3,
+            "format": "1.1.1",
+            "separator": " "
+        }
+    },
+    "spacing": {
+        "line_height": "1.25",
+        "paragraph_margin": "12pt",
+        "h1_bottom_margin": "12pt",
+        "h2_bottom_margin": "16pt",
+        "h3_margins": "24pt 0 12pt 0",
+        "h4_margins": "20pt 0 10pt 0",
+        "h5_margins": "16pt 0 8pt 0",
+        "h6_margins": "16pt 0 8pt 0"
+    },
+    "code_blocks": {
+        "background_color": "transparent",
+        "border": "none",
+        "padding": "0",
+        "margin": "6pt 0",
+        "word_wrap": true,
+        "page_break_inside": false,
+        "line_numbers": false,
+        "line_number_color": "#999",
+        "highlight_background": "#fff5b1",
+        "title_background": "#e8e8e8",
+        "title_color": "#333",
+        "diff_added_background": "#e6ffed",
+        "diff_removed_background": "#ffeef0",
+        "diff_header_background": "#f1f8ff"
+    },
+    "syntax_highlighting": {
+        "theme": "monokai",
+        "enabled": true,
+        "text_color": "#0a0947ff"
+    },
+    "images": {
+        "show_captions": true,
+        "caption_size": "10pt",
+        "caption_style": "italic",
+        "caption_align": "center",
+        "caption_color": "#2b2b2bff",
+        "embed": false,
+        "strict": false,
+        "search_paths": [],
+        "max_width": "100%",
+        "max_height": "80vh"
+    },
+    "captions": {
+        "numbering": true,
+        "figure_label": "Figure",
+        "table_label": "Table",
+        "section_label": "Section",
+        "separator": ": ",
+        "table_position": "above"
+    },
+    "links": {
+        "rewrite_markdown": true,
+        "unconverted": "pdf",
+        "color": "#0000ee",
+        "underline": true,
+        "show_url": "none"
+    },
+    "toc": {
+        "title": "Contents",
+        "max_level": 3
+    },
+    "book": {
+        "files": [],
+        "title": "",
+        "toc": true,
+        "chapter_page_break": true
+    },
+    "variables": {
+        "values": {},
+        "in_code": false
+    },
+    "profiles": {
+        "active": [],
+        "overrides": {}
+    },
+    "citations": {
+        "bibliography": "",
+        "style": "numeric",
+        "title": "References"
+    },
+    "tables": {
+        "border": "1px solid #000",
+        "padding": "6pt",
+        "compact": false,
+        "font_size": "100%",
+        "align": "left",
+        "markdown_alignment": true,
+        "header_background": "#f5f5f5",
+        "header_color": "#000000",
+        "repeat_header": true,
+        "zebra": false,
+        "zebra_background": "#fafafa",
+        "wide_columns": 6,
+        "wide": "wrap",
+        "shrink_font_size": "80%"
+    },
+    "blockquote": {
+        "border_width": "3px",
+        "border_color": "#ccc",
+        "color": "#666",
+        "padding": "12pt",
+        "italic": false
+    },
+    "lists": {
+        "indent": "24pt",
+        "item_spacing": "6pt",
+        "bullets": ["disc", "circle", "square"],
+        "numbering": ["decimal"],
+        "legal": false
+    },
+    "title_page": {
+        "extract_header": true,
+        "first_paragraph_size": "16pt",
+        "cover": {
+            "enabled": false,
+            "title": "",
+            "subtitle": "",
+            "authors": [],
+            "date": "",
+            "version": "",
+            "logo": "",
+            "abstract": "",
+            "notice": "",
+            "align": "center",
+            "title_size": "32pt",
+            "subtitle_size": "18pt",
+            "logo_width": "2in",
+            "logo_position": "top"
+        }
+    },
+    "print": {
+        "backend": "auto",
+        "paper_size": "A4",
+        "landscape": false,
+        "print_background": true,
+        "outline": true,
+        "header_template": "",
+        "footer_template": "",
+        "settle_ms": 250,
+        "timeout_secs": 120,
+        "retries": 0,
+        "retry_backoff_ms": 1000
+    }
+}"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The default config with `profiles` in place of its own.
+    fn with_profiles(profiles: Value, selected: &[&str]) -> Config {
+        let mut value: Value = serde_json::from_str(DEFAULT_CONFIG).unwrap();
+        value["profiles"] = profiles;
+        let selected: Vec<String> = selected.iter().map(|name| name.to_string()).collect();
+        parse_config(&value.to_string(), &selected).unwrap()
+    }
+
+    fn editions() -> Value {
+        json!({
+            "active": ["draft", "print"],
+            "overrides": {
+                "draft": {
+                    "page": { "margin": "2cm" },
+                    "lists": { "bullets": ["–"] }
+                },
+                "print": {
+                    "page": { "margin": "3cm" },
+                    "citations": { "style": "author-date" }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn merges_objects_and_replaces_everything_else() {
+        let mut value = json!({
+            "page": { "margin": "1in", "columns": { "count": 1, "gap": "1em" } },
+            "bullets": ["disc", "circle"],
+            "retries": 0
+        });
+        merge(
+            &mut value,
+            json!({
+                "page": { "columns": { "count": 2 } },
+                "bullets": ["–"],
+                "retries": "3",
+                "title": "New"
+            }),
+        );
+        assert_eq!(
+            value,
+            json!({
+                "page": { "margin": "1in", "columns": { "count": 2, "gap": "1em" } },
+                "bullets": ["–"],
+                "retries": "3",
+                "title": "New"
+            })
+        );
+    }
+
+    #[test]
+    fn applies_active_profiles_in_order() {
+        let config = with_profiles(editions(), &[]);
+        assert_eq!(config.page.margin, "3cm");
+        assert_eq!(config.page.first_page_top_margin, "2in");
+        assert_eq!(config.lists.bullets, ["–"]);
+        assert_eq!(config.citations.style, "author-date");
+        assert_eq!(config.citations.title, "References");
+    }
+
+    #[test]
+    fn selected_profiles_replace_the_active_ones() {
+        let config = with_profiles(editions(), &["draft"]);
+        assert_eq!(config.page.margin, "2cm");
+        assert_eq!(config.citations.style, "numeric");
+
+        let config = with_profiles(editions(), &["print", "draft"]);
+        assert_eq!(config.page.margin, "2cm");
+        assert_eq!(config.citations.style, "author-date");
+    }
+
+    #[test]
+    fn ignores_unknown_profiles() {
+        let config = with_profiles(editions(), &["web"]);
+        assert_eq!(config.page.margin, "1in");
+        assert_eq!(config.lists.bullets, ["disc", "circle", "square"]);
+    }
+
+    #[test]
+    fn tightens_padding_in_compact_tables() {
+        let mut tables = TablesConfig::default();
+        assert_eq!(tables.cell_padding(), "6pt");
+        tables.compact = true;
+        assert_eq!(tables.cell_padding(), COMPACT_TABLE_PADDING);
+    }
+
+    #[test]
+    fn treats_tables_past_the_column_limit_as_wide() {
+        let mut tables = TablesConfig {
+            wide: "shrink".to_string(),
+            ..TablesConfig::default()
+        };
+        assert!(!tables.is_wide(6));
+        assert!(tables.is_wide(7));
+
+        tables.wide_columns = 0;
+        assert!(!tables.is_wide(20));
+        tables.wide_columns = 6;
+        tables.wide = "wrap".to_string();
+        assert!(!tables.is_wide(20));
+    }
+
+    #[test]
+    fn carries_the_last_list_level_on() {
+        let lists = ListsConfig {
+            bullets: vec!["disc".to_string(), "–".to_string()],
+            numbering: Vec::new(),
+            ..ListsConfig::default()
+        };
+        assert_eq!(lists.bullet(0), "disc");
+        assert_eq!(lists.bullet(1), "–");
+        assert_eq!(lists.bullet(5), "–");
+        assert_eq!(lists.numbering(2), "decimal");
+    }
+
+    fn face(weight: &str, style: &str) -> FontFaceConfig {
+        FontFaceConfig {
+            family: "Inter".to_string(),
+            path: "/fonts/Inter.ttf".to_string(),
+            weight: weight.to_string(),
+            style: style.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_font_weights_and_styles() {
+        assert!(face("bold", "normal").is_bold());
+        assert!(face("600", "normal").is_bold());
+        assert!(!face("500", "normal").is_bold());
+        assert!(!face("normal", "normal").is_bold());
+        assert!(face("normal", " italic").is_italic());
+        assert!(face("normal", "oblique").is_italic());
+        assert!(!face("normal", "normal").is_italic());
+    }
+}
@@ -1,202 +1,170 @@
-use serde::Deserialize;
+mod backend;
+mod bibliography;
+mod book;
+mod cdp;
+mod child;
+mod citations;
+mod cli;
+mod code_blocks;
+mod conditions;
+mod config;
+mod cover;
+mod directives;
+mod figures;
+mod fonts;
+mod images;
+mod includes;
+mod links;
+mod markdown;
+mod native;
+mod sections;
+mod staging;
+mod variables;
+
+use backend::{file_url, render_with_retries, select_backend, PdfBackend, PrintOptions, RenderJob};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bibliography::{load_bibliography, Bibliography};
+use book::{assemble, chapter_files};
+use cli::{parse_args, Args};
+use conditions::filter_conditionals;
+use config::{load_config, Config};
+use cover::Cover;
+use directives::expand_directives;
+use fonts::check_families;
+use images::ImageResolver;
+use includes::expand_includes;
+use links::{check_internal_links, rewrite_markdown_links, LinkTarget};
+use markdown::{expand_image_sizes, extract_header, markdown_to_html, parse_document, Document};
+use staging::{install_interrupt_handler, write_output, StagingDir};
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-
-#[derive(Debug, Deserialize)]
-struct Config {
-    page: PageConfig,
-    fonts: FontsConfig,
-    headings: HeadingsConfig,
-    spacing: SpacingConfig,
-    code_blocks: CodeBlocksConfig,
-    syntax_highlighting: SyntaxHighlightingConfig,
-    images: ImagesConfig,
-    title_page: TitlePageConfig,
-}
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use variables::{split_front_matter, substitute_variables};
 
-#[derive(Debug, Deserialize)]
-struct PageConfig {
-    margin: String,
-    first_page_top_margin: String,
-}
+/// Front matter key naming the bibliography for citations.
+const BIBLIOGRAPHY: &str = "bibliography";
 
-#[derive(Debug, Deserialize)]
-struct FontsConfig {
-    body_family: String,
-    body_size: String,
-    code_family: String,
-    inline_code_size: String,
-    block_code_size: String,
-}
+/// Default file name for a book, next to its first input.
+const BOOK_OUTPUT: &str = "book.pdf";
 
-#[derive(Debug, Deserialize)]
-struct HeadingsConfig {
-    h1_size: String,
-    h1_align: String,
-    h1_page_break_before: bool,
-    h2_size: String,
-    h2_page_break_before: bool,
-    h3_size: String,
-    h4_size: String,
-    h5_size: String,
-    h6_size: String,
-}
+fn main() {
+    install_interrupt_handler();
 
-#[derive(Debug, Deserialize)]
-struct SpacingConfig {
-    line_height: String,
-    paragraph_margin: String,
-    h1_bottom_margin: String,
-    h2_bottom_margin: String,
-    h3_margins: String,
-    h4_margins: String,
-    h5_margins: String,
-    h6_margins: String,
-}
+    let args = parse_args();
+    let config = load_config(&args.profiles);
 
-#[derive(Debug, Deserialize)]
-struct CodeBlocksConfig {
-    background_color: String,
-    border: String,
-    padding: String,
-    margin: String,
-    word_wrap: bool,
-    page_break_inside: bool,
-}
+    if args.book {
+        let backend = backend_for(&config, &args);
+        convert_book(&config, &args, backend.as_ref());
+        return;
+    }
 
-#[derive(Debug, Deserialize)]
-struct SyntaxHighlightingConfig {
-    theme: String,
-    enabled: bool,
-    text_color: String,
-}
+    let inputs = if args.inputs.is_empty() {
+        eprintln!("Enter the path to the markdown file:");
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .expect("Failed to read input");
+        vec![input.trim().to_string()]
+    } else {
+        args.inputs.clone()
+    };
+
+    for input in &inputs {
+        if input != "-" && !Path::new(input).exists() {
+            eprintln!("Error: File '{}' does not exist", input);
+            std::process::exit(1);
+        }
+    }
+
+    let backend = backend_for(&config, &args);
 
-#[derive(Debug, Deserialize)]
-struct ImagesConfig {
-    show_captions: bool,
-    caption_size: String,
-    caption_style: String,
-    caption_align: String,
-    caption_color: String,
+    // Links between the files converted in this run point at their PDFs.
+    let targets: HashMap<PathBuf, LinkTarget> = inputs
+        .iter()
+        .filter_map(|input| fs::canonicalize(input).ok())
+        .map(|path| (path, LinkTarget::Pdf))
+        .collect();
+
+    for input in &inputs {
+        convert(&config, &args, backend.as_ref(), input, &targets);
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct TitlePageConfig {
-    extract_header: bool,
-    first_paragraph_size: String,
+fn backend_for(config: &Config, args: &Args) -> Box<dyn PdfBackend> {
+    let backend_name = args.backend.as_deref().unwrap_or(&config.print.backend);
+    select_backend(backend_name, config).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    })
 }
 
-fn load_config() -> Config {
-    let exe_path = env::current_exe().expect("Failed to get executable path");
-    let exe_dir = exe_path
-        .parent()
-        .expect("Failed to get executable directory");
-    let config_path = exe_dir.join("config.json");
-
-    if config_path.exists() {
-        let config_content = fs::read_to_string(&config_path).expect("Failed to read config.json");
-        match serde_json::from_str(&config_content) {
-            Ok(config) => config,
-            Err(e) => {
-                eprintln!("Error parsing config.json: {}", e);
-                eprintln!("Using default configuration instead.");
-                get_default_config()
-            }
-        }
-    } else {
-        eprintln!("Warning: config.json not found, using default configuration");
-        get_default_config()
+/// `--base-dir` if given, else `default`.
+fn base_dir_for(args: &Args, default: PathBuf) -> PathBuf {
+    match &args.base_dir {
+        Some(dir) => fs::canonicalize(dir).unwrap_or_else(|e| {
+            eprintln!("Error: Base directory '{}' is not usable: {}", dir, e);
+            std::process::exit(1);
+        }),
+        None => default,
     }
 }
 
-fn get_default_config() -> Config {
-    serde_json::from_str(
-        r##"{
-        "page": {
-            "margin": "1in",
-            "first_page_top_margin": "2in"
-        },
-        "fonts": {
-            "body_family": "Times New Roman",
-            "body_size": "12pt",
-            "code_family": "Courier New",
-            "inline_code_size": "12pt",
-            "block_code_size": "9pt"
-        },
-        "headings": {
-            "h1_size": "24pt",
-            "h1_align": "center",
-            "h1_page_break_before": true,
-            "h2_size": "16pt",
-            "h2_page_break_before": true,
-            "h3_size": "14pt",
-            "h4_size": "13pt",
-            "h5_size": "12pt",
-            "h6_size": "12pt"
-        },
-        "spacing": {
-            "line_height": "1.25",
-            "paragraph_margin": "12pt",
-            "h1_bottom_margin": "12pt",
-            "h2_bottom_margin": "16pt",
-            "h3_margins": "24pt 0 12pt 0",
-            "h4_margins": "20pt 0 10pt 0",
-            "h5_margins": "16pt 0 8pt 0",
-            "h6_margins": "16pt 0 8pt 0"
-        },
-        "code_blocks": {
-            "background_color": "transparent",
-            "border": "none",
-            "padding": "0",
-            "margin": "6pt 0",
-            "word_wrap": true,
-            "page_break_inside": false
-        },
-        "syntax_highlighting": {
-            "theme": "monokai",
-            "enabled": true,
-            "text_color": "#0a0947ff"
-        },
-        "images": {
-            "show_captions": true,
-            "caption_size": "10pt",
-            "caption_style": "italic",
-            "caption_align": "center",
-            "caption_color": "#2b2b2bff"
-        },
-        "title_page": {
-            "extract_header": true,
-            "first_paragraph_size": "16pt"
-        }
-    }"##,
-    )
-    .expect("Failed to parse default config")
+/// `--output` if given, else `default`. `None` means the PDF goes to stdout.
+fn output_for(args: &Args, default: Option<PathBuf>) -> Option<PathBuf> {
+    match args.output.as_deref() {
+        Some("-") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => default,
+    }
 }
 
-fn main() {
-    let config = load_config();
-
-    let args: Vec<String> = env::args().collect();
+/// Converts one markdown file (or stdin, for `-`) to PDF, exiting on failure.
+fn convert(
+    config: &Config,
+    args: &Args,
+    backend: &dyn PdfBackend,
+    md_path: &str,
+    targets: &HashMap<PathBuf, LinkTarget>,
+) {
+    let from_stdin = md_path == "-";
 
-    let md_path = if args.len() < 2 {
-        println!("Enter the path to the markdown file:");
-        let mut input = String::new();
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read input");
-        input.trim().to_string()
+    let (markdown_content, md_dir, source) = if from_stdin {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .expect("Failed to read markdown from stdin");
+        let cwd = env::current_dir().expect("Failed to get current directory");
+        (content, cwd, None)
     } else {
-        args[1].clone()
+        let md_path_absolute =
+            fs::canonicalize(md_path).expect("Failed to get absolute path for markdown file");
+        let content = fs::read_to_string(md_path).expect("Failed to read markdown file");
+        let dir = md_path_absolute
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        (content, dir, Some(md_path_absolute))
     };
 
-    if !Path::new(&md_path).exists() {
-        eprintln!("Error: File '{}' does not exist", md_path);
-        std::process::exit(1);
-    }
+    // Includes and the bibliography resolve like images and links do.
+    let base_dir = base_dir_for(args, md_dir);
+    let markdown_content = expand_includes(&markdown_content, &base_dir, source.as_deref())
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+
+    let source_name = if from_stdin { "<stdin>" } else { md_path };
+    let (markdown_content, front_matter) = preprocess(config, args, &markdown_content, source_name);
+    let bibliography = bibliography_for(config, &front_matter, &base_dir);
 
-    let markdown_content = fs::read_to_string(&md_path).expect("Failed to read markdown file");
+    let default_output = source.map(|source| source.with_extension("pdf"));
+    let output_pdf = output_for(args, default_output);
 
     let (header_text, processed_markdown) = if config.title_page.extract_header {
         extract_header(&markdown_content)
@@ -204,106 +172,646 @@ fn main() {
         (String::new(), markdown_content)
     };
 
-    let html = markdown_to_html(&processed_markdown);
+    let processed_markdown = expand_image_sizes(&processed_markdown);
 
-    let full_html = generate_html(&config, &header_text, &html);
+    let mut document = parse_document(&processed_markdown, config, bibliography.as_ref());
+    document.events = rewrite_markdown_links(
+        std::mem::take(&mut document.events),
+        &config.links,
+        &base_dir,
+        targets,
+        None,
+    );
 
-    let temp_html_path = Path::new(&md_path).with_extension("temp.html");
-    fs::write(&temp_html_path, full_html).expect("Failed to write temporary HTML file");
+    let cover = Cover::build(&config.title_page.cover, &front_matter, &header_text);
+    publish(
+        config,
+        backend,
+        &document,
+        &header_text,
+        cover.as_ref(),
+        &base_dir,
+        output_pdf,
+        source_name,
+    );
+}
 
-    let output_pdf_path = Path::new(&md_path).with_extension("pdf");
+/// Combines the book's chapters into one PDF, exiting on failure.
+fn convert_book(config: &Config, args: &Args, backend: &dyn PdfBackend) {
+    let chapters = chapter_files(&args.inputs, &config.book).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
 
-    let edge_paths = vec![
-        r"C:\Program Files (x86)\Microsoft\Edge\Application\msedge.exe",
-        r"C:\Program Files\Microsoft\Edge\Application\msedge.exe",
-    ];
+    // The book lives where its first input (a chapter or SUMMARY.md) does.
+    let book_dir = match args.inputs.first() {
+        Some(input) => fs::canonicalize(input)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf)),
+        None => env::current_dir().ok(),
+    }
+    .unwrap_or_else(|| PathBuf::from("."));
+    let base_dir = base_dir_for(args, book_dir.clone());
 
-    let chrome_paths = vec![
-        r"C:\Program Files\Google\Chrome\Application\chrome.exe",
-        r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe",
-    ];
+    let mut header_text = config.book.title.clone();
+    let mut bibliography = None;
+    let mut cover_front_matter = HashMap::new();
+    let mut sources = Vec::new();
+    for (index, chapter) in chapters.iter().enumerate() {
+        let content = fs::read_to_string(chapter).unwrap_or_else(|e| {
+            eprintln!(
+                "Error: Failed to read chapter '{}': {}",
+                chapter.display(),
+                e
+            );
+            std::process::exit(1);
+        });
+        // Includes and bibliographies follow `--base-dir` like images and
+        // links; without it each chapter's are relative to the chapter.
+        let dir = match args.base_dir {
+            Some(_) => &base_dir,
+            None => chapter.parent().unwrap_or(Path::new(".")),
+        };
+        let content = expand_includes(&content, dir, Some(chapter)).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
 
-    let mut browser_path = None;
-    for path in edge_paths.iter().chain(chrome_paths.iter()) {
-        if Path::new(path).exists() {
-            browser_path = Some(path);
-            break;
+        let (content, front_matter) =
+            preprocess(config, args, &content, &chapter.display().to_string());
+        // The first chapter to name a bibliography names it for the book.
+        if bibliography.is_none() && front_matter.contains_key(BIBLIOGRAPHY) {
+            bibliography = bibliography_for(config, &front_matter, dir);
+        }
+        if index == 0 {
+            cover_front_matter = front_matter;
         }
+
+        // Only the first chapter's header makes it onto the title page.
+        let content = if config.title_page.extract_header {
+            let (header, content) = extract_header(&content);
+            if index == 0 && header_text.is_empty() {
+                header_text = header;
+            }
+            content
+        } else {
+            content
+        };
+        sources.push(expand_image_sizes(&content));
     }
 
-    let browser_path = browser_path
-        .expect("Could not find Edge or Chrome. Please install Microsoft Edge or Chrome.");
+    if bibliography.is_none() {
+        bibliography = bibliography_for(config, &HashMap::new(), &base_dir);
+    }
+    let document = assemble(&chapters, &sources, config, bibliography.as_ref());
 
-    let temp_html_absolute =
-        fs::canonicalize(&temp_html_path).expect("Failed to get absolute path for HTML file");
+    let output_pdf = output_for(args, Some(book_dir.join(BOOK_OUTPUT)));
+    let source_name = format!("{} chapters", chapters.len());
+    let cover = Cover::build(&config.title_page.cover, &cover_front_matter, &header_text);
+    publish(
+        config,
+        backend,
+        &document,
+        &header_text,
+        cover.as_ref(),
+        &base_dir,
+        output_pdf,
+        &source_name,
+    );
+}
 
-    let output_pdf_absolute = fs::canonicalize(Path::new(&md_path).parent().unwrap())
-        .expect("Failed to get absolute path for output directory")
-        .join(output_pdf_path.file_name().unwrap());
+/// Drops the conditional blocks that do not apply to the active profiles,
+/// takes the front matter off, fills in `{{ name }}` variables and expands
+/// layout directives, exiting on failure. Returns the front matter too.
+fn preprocess(
+    config: &Config,
+    args: &Args,
+    markdown: &str,
+    source_name: &str,
+) -> (String, HashMap<String, String>) {
+    let markdown = filter_conditionals(markdown, &config.profiles.active).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", source_name, e);
+        std::process::exit(1);
+    });
 
-    println!("Using browser: {}", browser_path);
-    println!(
-        "Converting: {} -> {}",
-        md_path,
-        output_pdf_absolute.display()
-    );
+    let (front_matter, markdown) = split_front_matter(&markdown);
+    let mut values = config.variables.values.clone();
+    values.extend(front_matter.clone());
+    values.extend(args.vars.iter().cloned());
+    let markdown = substitute_variables(markdown, &values, config.variables.in_code);
+    let markdown = expand_directives(&markdown).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", source_name, e);
+        std::process::exit(1);
+    });
+    (markdown, front_matter)
+}
 
-    let temp_dir = env::temp_dir();
-    let user_data_dir = temp_dir.join("mandy_browser_data");
+/// The bibliography named in the front matter, or else in the config, with
+/// relative paths taken from `dir`. Exits if it cannot be read.
+fn bibliography_for(
+    config: &Config,
+    front_matter: &HashMap<String, String>,
+    dir: &Path,
+) -> Option<Bibliography> {
+    let path = front_matter
+        .get(BIBLIOGRAPHY)
+        .unwrap_or(&config.citations.bibliography);
+    if path.is_empty() {
+        return None;
+    }
 
-    let temp_html_str = temp_html_absolute.to_string_lossy().to_string();
-    let cleaned_path = if temp_html_str.starts_with(r"\\?\") {
-        temp_html_str[4..].to_string()
-    } else {
-        temp_html_str
-    };
+    let bibliography = load_bibliography(&dir.join(path)).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    Some(bibliography)
+}
+
+/// Renders a parsed document, behind its cover if it has one, and writes the
+/// PDF to `output_pdf` (stdout if `None`), exiting on failure.
+#[allow(clippy::too_many_arguments)]
+fn publish(
+    config: &Config,
+    backend: &dyn PdfBackend,
+    document: &Document,
+    header_text: &str,
+    cover: Option<&Cover>,
+    base_dir: &Path,
+    output_pdf: Option<PathBuf>,
+    source_name: &str,
+) {
+    check_internal_links(document);
+    // The native backend reports the fonts it substitutes itself.
+    if backend.name() != "native" {
+        check_families(config);
+    }
+    let images = ImageResolver::new(&config.images, base_dir);
+    let mut html = markdown_to_html(document, config, &images);
+    if config.page.columns.count > 1 {
+        html = format!("<div class=\"page-columns\">\n{}</div>\n", html);
+    }
+    if let Some(cover) = cover {
+        html = cover.to_html(&config.title_page.cover, &images) + &html;
+    }
+    // The cover carries the title, so the first page's header box stays empty.
+    let header_text = if cover.is_some() { "" } else { header_text };
 
-    let file_url = format!("file:///{}", cleaned_path.replace("\\", "/"));
-
-    println!("File URL: {}", file_url);
-
-    let output = Command::new(browser_path)
-        .arg("--headless=new")
-        .arg("--disable-gpu")
-        .arg("--no-first-run")
-        .arg("--no-default-browser-check")
-        .arg("--disable-extensions")
-        .arg("--disable-background-networking")
-        .arg("--no-pdf-header-footer")
-        .arg(format!("--user-data-dir={}", user_data_dir.display()))
-        .arg(format!("--print-to-pdf={}", output_pdf_absolute.display()))
-        .arg(&file_url)
-        .output()
-        .expect("Failed to execute browser");
-
-    fs::remove_file(&temp_html_path).expect("Failed to remove temporary HTML file");
-
-    if output.status.success() {
-        println!(
-            "PDF created successfully: {}",
-            output_pdf_absolute.display()
+    let missing_images = images.missing();
+    if config.images.strict && !missing_images.is_empty() {
+        eprintln!(
+            "Error: {} image(s) not found and images.strict is enabled",
+            missing_images.len()
         );
-    } else {
-        eprintln!("Error: Browser failed to generate PDF");
-        eprintln!("STDOUT: {}", String::from_utf8_lossy(&output.stdout));
-        eprintln!("STDERR: {}", String::from_utf8_lossy(&output.stderr));
         std::process::exit(1);
     }
+
+    let options = PrintOptions::from_config(config);
+    let full_html = generate_html(config, &options, header_text, &html, &file_url(base_dir));
+
+    let staging = StagingDir::create().unwrap_or_else(|e| {
+        eprintln!("Error: Failed to create temporary directory: {}", e);
+        std::process::exit(1);
+    });
+    let temp_html_path = staging.path().join("document.html");
+    fs::write(&temp_html_path, &full_html).expect("Failed to write temporary HTML file");
+
+    let output_name = output_pdf
+        .as_ref()
+        .map_or("<stdout>".to_string(), |path| path.display().to_string());
+    eprintln!("Using backend: {}", backend.name());
+    eprintln!("Converting: {} -> {}", source_name, output_name);
+
+    let job = RenderJob {
+        html_path: &temp_html_path,
+        document,
+        header_text,
+        cover,
+        base_dir,
+        images: &images,
+        work_dir: staging.path(),
+        options: &options,
+    };
+    let result = render_with_retries(
+        backend,
+        &job,
+        config.print.retries,
+        Duration::from_millis(config.print.retry_backoff_ms),
+    );
+
+    // `process::exit` skips destructors, so clean up before reporting.
+    drop(staging);
+
+    match result {
+        Ok(pdf) => match &output_pdf {
+            Some(path) => {
+                write_output(path, &pdf).expect("Failed to write PDF file");
+                eprintln!("PDF created successfully: {}", path.display());
+            }
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout
+                    .write_all(&pdf)
+                    .and_then(|()| stdout.flush())
+                    .expect("Failed to write PDF to stdout");
+            }
+        },
+        Err(e) => {
+            eprintln!("Error: {} backend failed to generate PDF", backend.name());
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `@font-face` rules for `fonts.files`, with each file embedded so the
+/// renderer needs nothing installed.
+fn font_face_styles(config: &Config) -> String {
+    let mut styles = String::new();
+
+    for file in &config.fonts.files {
+        let path = file.file_path();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        let (mime, format) = match extension.as_deref() {
+            Some("ttf") => ("font/ttf", "truetype"),
+            Some("otf") => ("font/otf", "opentype"),
+            Some("woff2") => ("font/woff2", "woff2"),
+            Some("woff") => ("font/woff", "woff"),
+            _ => {
+                eprintln!(
+                    "Warning: font file '{}' is not a TTF, OTF, WOFF or WOFF2 file",
+                    path.display()
+                );
+                continue;
+            }
+        };
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!(
+                    "Warning: Failed to read font file '{}': {}",
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        styles.push_str(&format!(
+            r#"@font-face {{
+            font-family: '{}';
+            src: url(data:{};base64,{}) format('{}');
+            font-weight: {};
+            font-style: {};
+        }}
+        
+        "#,
+            file.family.replace('\\', "\\\\").replace('\'', "\\'"),
+            mime,
+            BASE64.encode(data),
+            format,
+            file.weight,
+            file.style
+        ));
+    }
+
+    styles
+}
+
+/// Page breaks around each heading level, and keeping headings with what
+/// follows them.
+fn heading_break_styles(config: &Config) -> String {
+    let headings = &config.headings;
+    let mut styles = Vec::new();
+
+    for level in 1..=6 {
+        let mut rules = String::new();
+        if headings.page_break_before(level) {
+            rules.push_str(" page-break-before: always;");
+        }
+        if headings.page_break_after(level) {
+            rules.push_str(" page-break-after: always;");
+        } else if headings.keep_with_next(level) {
+            rules.push_str(" page-break-after: avoid;");
+        }
+        if !rules.is_empty() {
+            styles.push(format!("h{} {{{} }}", level, rules));
+        }
+    }
+
+    styles.join("\n        ")
 }
 
-fn generate_html(config: &Config, header_text: &str, html_content: &str) -> String {
-    let h1_page_break = if config.headings.h1_page_break_before {
-        "page-break-before: always;"
+/// The blocks that layout directives open: kept together, turned to
+/// landscape, or set in columns.
+fn layout_styles(config: &Config, options: &PrintOptions) -> String {
+    let (width, height) = (options.paper_width, options.paper_height);
+    let columns = &config.page.columns;
+    let spanning = (1..=columns.span_headings.min(6))
+        .map(|level| format!("h{}", level))
+        .collect::<Vec<_>>();
+    let spanning = if spanning.is_empty() {
+        String::new()
     } else {
-        ""
+        format!(
+            r#"
+        
+        :is(.page-columns, .columns) :is({}) {{
+            column-span: all;
+        }}"#,
+            spanning.join(", ")
+        )
     };
 
-    let h2_page_break = if config.headings.h2_page_break_before {
-        "page-break-before: always;"
+    format!(
+        r#".keep-together {{
+            page-break-inside: avoid;
+        }}
+        
+        @page landscape {{
+            size: {}in {}in;
+        }}
+        
+        .landscape {{
+            page: landscape;
+        }}
+        
+        .page-columns {{
+            column-count: {};
+        }}
+        
+        :is(.page-columns, .columns) {{
+            column-gap: {};
+            column-rule: {};
+        }}
+        
+        .page-columns .columns {{
+            column-span: all;
+        }}{}"#,
+        width.max(height),
+        width.min(height),
+        columns.count.max(1),
+        columns.gap,
+        columns.rule,
+        spanning
+    )
+}
+
+/// Title bars, line numbers and highlighted lines of code blocks.
+fn code_block_styles(config: &Config) -> String {
+    let blocks = &config.code_blocks;
+    format!(
+        r#".code-title {{
+            font-family: '{}', monospace;
+            font-size: {};
+            font-weight: bold;
+            background-color: {};
+            color: {};
+            border: {};
+            border-bottom: none;
+            padding: 3pt 6pt;
+            margin-top: 6pt;
+            page-break-after: avoid;
+        }}
+        
+        .code-title + pre {{
+            margin-top: 0;
+        }}
+        
+        .code-line {{
+            display: flex;
+        }}
+        
+        .code-line.highlighted {{
+            background-color: {} !important;
+        }}
+        
+        .code-lines code {{
+            display: block;
+        }}
+        
+        .line-text {{
+            flex: 1;
+        }}
+        
+        .line-text:empty::after {{
+            content: " ";
+        }}
+        
+        .diff-added {{
+            background-color: {};
+        }}
+        
+        .diff-removed {{
+            background-color: {};
+        }}
+        
+        .diff-header {{
+            background-color: {};
+        }}
+        
+        .diff-header .line-text {{
+            color: {} !important;
+        }}
+        
+        .diff-marker {{
+            flex: none;
+            width: 1ch;
+        }}
+        
+        .line-number {{
+            flex: none;
+            width: calc(var(--line-number-digits) * 1ch);
+            padding-right: 1em;
+            text-align: right;
+            color: {};
+            user-select: none;
+        }}"#,
+        config.fonts.code_family,
+        config.fonts.block_code_size,
+        blocks.title_background,
+        blocks.title_color,
+        blocks.border,
+        blocks.highlight_background,
+        blocks.diff_added_background,
+        blocks.diff_removed_background,
+        blocks.diff_header_background,
+        blocks.line_number_color,
+        blocks.line_number_color
+    )
+}
+
+fn blockquote_styles(config: &Config) -> String {
+    let blockquote = &config.blockquote;
+    let font_style = if blockquote.italic {
+        "italic"
     } else {
+        "normal"
+    };
+
+    format!(
+        r#"blockquote {{
+            border-left: {} solid {};
+            padding-left: {};
+            margin-left: 0;
+            margin: 12pt 0;
+            color: {};
+            font-style: {};
+        }}"#,
+        blockquote.border_width,
+        blockquote.border_color,
+        blockquote.padding,
+        blockquote.color,
+        font_style
+    )
+}
+
+/// List indents and spacing, and a marker rule per nesting level. Each level's
+/// selector also matches the levels below it, so the last one carries on.
+fn list_styles(config: &Config) -> String {
+    let lists = &config.lists;
+    let mut styles = format!(
+        r#"ul, ol {{
+            margin: 12pt 0;
+            padding-left: {};
+        }}
+        
+        li {{
+            margin: {} 0;
+        }}"#,
+        lists.indent, lists.item_spacing
+    );
+
+    let levels = lists.bullets.len().max(lists.numbering.len());
+    for depth in 0..levels {
+        let parents = ":is(ul, ol) ".repeat(depth);
+        let bullet = lists.bullet(depth);
+        let bullet = if bullet.chars().all(|c| c.is_ascii_alphabetic() || c == '-') {
+            bullet.to_string()
+        } else {
+            format!("\"{} \"", bullet.replace('\\', "\\\\").replace('"', "\\\""))
+        };
+        styles.push_str(&format!(
+            "\n        {}ul {{ list-style-type: {}; }}",
+            parents, bullet
+        ));
+        if !lists.legal {
+            styles.push_str(&format!(
+                "\n        {}ol {{ list-style-type: {}; }}",
+                parents,
+                lists.numbering(depth)
+            ));
+        }
+    }
+
+    if lists.legal {
+        styles.push_str(
+            r#"
+        ol { counter-reset: legal; list-style-type: none; }
+        ol > li { counter-increment: legal; }
+        ol > li::before { content: counters(legal, ".") ". "; }"#,
+        );
+    }
+    styles
+}
+
+fn link_styles(config: &Config) -> String {
+    let links = &config.links;
+    let decoration = if links.underline { "underline" } else { "none" };
+    format!(
+        r#"a {{
+            color: {};
+            text-decoration: {};
+        }}"#,
+        links.color, decoration
+    )
+}
+
+/// The stylesheet rules for tables, from the `tables` section.
+fn table_styles(config: &Config) -> String {
+    let tables = &config.tables;
+    // Markdown column alignment arrives as inline styles, which only
+    // `!important` overrides.
+    let align_priority = if tables.markdown_alignment {
         ""
+    } else {
+        " !important"
+    };
+    let thead_display = if tables.repeat_header {
+        "table-header-group"
+    } else {
+        "table-row-group"
+    };
+    let zebra = if tables.zebra {
+        format!(
+            "tbody tr:nth-child(even) td {{ background-color: {}; }}",
+            tables.zebra_background
+        )
+    } else {
+        String::new()
+    };
+    let wide = match tables.wide.as_str() {
+        "shrink" => format!(
+            ".wide-table table {{ font-size: {}; }}",
+            tables.shrink_font_size
+        ),
+        "landscape" => ".wide-table { page: landscape; }".to_string(),
+        _ => String::new(),
     };
 
+    format!(
+        r#"table {{
+            border-collapse: collapse;
+            width: 100%;
+            margin: 12pt 0;
+            font-size: {};
+        }}
+        
+        thead {{
+            display: {};
+        }}
+        
+        tr {{
+            page-break-inside: avoid;
+        }}
+        
+        th, td {{
+            border: {};
+            padding: {};
+            text-align: {}{};
+        }}
+        
+        th {{
+            font-weight: bold;
+            background-color: {};
+            color: {};
+        }}
+        
+        {}
+        
+        {}"#,
+        tables.font_size,
+        thead_display,
+        tables.border,
+        tables.cell_padding(),
+        tables.align,
+        align_priority,
+        tables.header_background,
+        tables.header_color,
+        zebra,
+        wide
+    )
+}
+
+fn generate_html(
+    config: &Config,
+    options: &PrintOptions,
+    header_text: &str,
+    html_content: &str,
+    base_url: &str,
+) -> String {
     let pre_page_break = if !config.code_blocks.page_break_inside {
         "page-break-inside: avoid;"
     } else {
@@ -326,54 +834,56 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
         String::new()
     };
 
-    let image_caption_script = if config.images.show_captions {
-        format!(
-            r#"
-            document.querySelectorAll('img').forEach((img) => {{
-                if (img.alt) {{
-                    const figure = document.createElement('figure');
-                    figure.style.margin = '12pt 0';
-                    figure.style.pageBreakInside = 'avoid';
-                    
-                    img.parentNode.insertBefore(figure, img);
-                    figure.appendChild(img);
-                    
-                    const caption = document.createElement('figcaption');
-                    caption.textContent = img.alt;
-                    caption.style.fontSize = '{}';
-                    caption.style.fontStyle = '{}';
-                    caption.style.textAlign = '{}';
-                    caption.style.marginTop = '6pt';
-                    caption.style.color = '{}';
-                    figure.appendChild(caption);
-                }}
-            }});"#,
-            config.images.caption_size,
-            config.images.caption_style,
-            config.images.caption_align,
-            config.images.caption_color
-        )
-    } else {
-        String::new()
-    };
-
     format!(
         r#"<!DOCTYPE html>
 <html>
 <head>
     <meta charset="UTF-8">
+    <base href="{}/">
     {}
     <script src="https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.9.0/highlight.min.js"></script>
     <script>
         document.addEventListener('DOMContentLoaded', (event) => {{
             document.querySelectorAll('pre code').forEach((block) => {{
-                hljs.highlightBlock(block);
+                if (block.parentElement.classList.contains('code-lines')) {{
+                    highlightLines(block);
+                }} else {{
+                    hljs.highlightBlock(block);
+                }}
             }});
-            {}
         }});
+
+        // Highlights the lines of a numbered block as one text, so comments
+        // and strings can span lines, then splits the markup back into them,
+        // closing the spans still open at the end of a line and reopening
+        // them at the start of the next.
+        function highlightLines(block) {{
+            const language = (block.className.match(/language-(\S+)/) || [])[1];
+            if (language && !hljs.getLanguage(language)) {{
+                return;
+            }}
+            const lines = block.querySelectorAll('.code-line:not(.diff-header) .line-text');
+            const text = Array.from(lines, (line) => line.textContent).join('\n');
+            const result = language
+                ? hljs.highlight(text, {{ language: language, ignoreIllegals: true }})
+                : hljs.highlightAuto(text);
+            let open = [];
+            result.value.split('\n').forEach((markup, index) => {{
+                const reopened = open.join('');
+                for (const tag of markup.match(/<span[^>]*>|<\/span>/g) || []) {{
+                    if (tag === '</span>') {{
+                        open.pop();
+                    }} else {{
+                        open.push(tag);
+                    }}
+                }}
+                lines[index].innerHTML = reopened + markup + '</span>'.repeat(open.length);
+            }});
+            block.classList.add('hljs');
+        }}
     </script>
     <style>
-        @page {{
+        {}@page {{
             margin: {};
         }}
         
@@ -412,7 +922,6 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
             font-weight: bold;
             margin: 0 0 {} 0;
             padding: 0;
-            {}
             text-align: {};
         }}
         
@@ -433,7 +942,6 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
             font-weight: bold;
             margin: 0 0 {} 0;
             padding: 0;
-            {}
         }}
         
         h3 {{
@@ -461,6 +969,8 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
             margin: {};
         }}
         
+        {}
+        
         p {{
             margin: {};
         }}
@@ -513,50 +1023,134 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
             color: #005cc5 !important;
         }}
         
-        blockquote {{
-            border-left: 3px solid #ccc;
-            padding-left: 12pt;
-            margin-left: 0;
-            margin: 12pt 0;
-            color: #666;
+        {}
+        
+        {}
+        
+        {}
+        
+        {}
+        
+        {}
+        
+        img {{
+            max-width: {};
+            max-height: {};
+            object-fit: contain;
+            height: auto;
+            display: block;
+            margin: 0 auto;
         }}
         
-        ul, ol {{
-            margin: 12pt 0;
-            padding-left: 24pt;
+        img.align-left, figure.align-left {{
+            float: left;
+            margin: 0 12pt 6pt 0;
         }}
         
-        li {{
-            margin: 6pt 0;
+        img.align-right, figure.align-right {{
+            float: right;
+            margin: 0 0 6pt 12pt;
         }}
         
-        table {{
-            border-collapse: collapse;
-            width: 100%;
+        .image-row {{
+            display: flex;
+            gap: 12pt;
+            justify-content: center;
+            align-items: flex-start;
+        }}
+        
+        .image-row > img, .image-row > figure {{
+            flex: 0 1 auto;
+            min-width: 0;
+            margin: 0;
+        }}
+        
+        figure {{
             margin: 12pt 0;
+            page-break-inside: avoid;
         }}
         
-        th, td {{
-            border: 1px solid #000;
-            padding: 6pt;
-            text-align: left;
+        figcaption {{
+            font-size: {};
+            font-style: {};
+            text-align: {};
+            color: {};
+            margin-top: 6pt;
         }}
         
-        th {{
-            font-weight: bold;
-            background-color: #f5f5f5;
+        figure.table > figcaption:first-child {{
+            margin: 0 0 6pt 0;
         }}
         
-        img {{
+        figure.table table {{
+            margin: 0;
+        }}
+        
+        .list-of-figures ul, .list-of-tables ul, .toc > ul {{
+            list-style: none;
+            padding-left: 0;
+        }}
+        
+        .toc ul ul {{
+            list-style: none;
+            padding-left: 1.5em;
+        }}
+        
+        .page-break {{
+            page-break-before: always;
+        }}
+        
+        {}
+        
+        .cover {{
+            page-break-after: always;
+            text-align: {};
+        }}
+        
+        .cover-logo img {{
+            width: {};
             max-width: 100%;
-            height: auto;
-            display: block;
-            margin: 0 auto;
+            margin-bottom: 2em;
         }}
         
-        figure {{
-            margin: 12pt 0;
-            page-break-inside: avoid;
+        .cover-title {{
+            font-size: {};
+            font-weight: bold;
+            margin: 1em 0 0.5em 0;
+        }}
+        
+        .cover-subtitle {{
+            font-size: {};
+            margin-bottom: 2em;
+        }}
+        
+        .cover-author {{
+            margin: 0.5em 0;
+        }}
+        
+        .cover-affiliation {{
+            font-style: italic;
+            font-size: 0.9em;
+        }}
+        
+        .cover-date, .cover-version {{
+            margin-top: 0.5em;
+        }}
+        
+        .cover-abstract {{
+            margin-top: 3em;
+            text-align: justify;
+        }}
+        
+        .cover-notice {{
+            margin-top: 3em;
+            font-size: 0.85em;
+            font-weight: bold;
+        }}
+        
+        .references p {{
+            padding-left: 2em;
+            text-indent: -2em;
         }}
     </style>
 </head>
@@ -564,8 +1158,9 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
 {}
 </body>
 </html>"#,
+        base_url.trim_end_matches('/'),
         syntax_link,
-        image_caption_script,
+        font_face_styles(config),
         config.page.margin,
         config.page.first_page_top_margin,
         header_text,
@@ -578,13 +1173,11 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
         config.fonts.body_size,
         config.headings.h1_size,
         config.spacing.h1_bottom_margin,
-        h1_page_break,
         config.headings.h1_align,
         config.title_page.first_paragraph_size,
         config.page.first_page_top_margin,
         config.headings.h2_size,
         config.spacing.h2_bottom_margin,
-        h2_page_break,
         config.headings.h3_size,
         config.spacing.h3_margins,
         config.headings.h4_size,
@@ -593,6 +1186,7 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
         config.spacing.h5_margins,
         config.headings.h6_size,
         config.spacing.h6_margins,
+        heading_break_styles(config),
         config.spacing.paragraph_margin,
         config.fonts.code_family,
         config.fonts.inline_code_size,
@@ -613,39 +1207,181 @@ fn generate_html(config: &Config, header_text: &str, html_content: &str) -> Stri
         config.syntax_highlighting.text_color,
         config.code_blocks.background_color,
         config.syntax_highlighting.text_color,
+        code_block_styles(config),
+        blockquote_styles(config),
+        list_styles(config),
+        link_styles(config),
+        table_styles(config),
+        config.images.max_width,
+        config.images.max_height,
+        config.images.caption_size,
+        config.images.caption_style,
+        config.images.caption_align,
+        config.images.caption_color,
+        layout_styles(config, options),
+        config.title_page.cover.align,
+        config.title_page.cover.logo_width,
+        config.title_page.cover.title_size,
+        config.title_page.cover.subtitle_size,
         html_content
     )
 }
 
-fn extract_header(markdown: &str) -> (String, String) {
-    let lines: Vec<&str> = markdown.lines().collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{get_default_config, FontFaceConfig};
+    use crate::staging::StagingDir;
 
-    if lines.len() >= 2 {
-        let first_line = lines[0].trim();
-        let second_line = lines[1].trim();
+    #[test]
+    fn writes_to_stdout_only_when_asked() {
+        let default = Some(PathBuf::from("notes.pdf"));
+        let args = |output: Option<&str>| Args {
+            output: output.map(str::to_string),
+            ..Args::default()
+        };
 
-        if second_line.chars().all(|c| c == '-') && second_line.len() >= 3 {
-            let header_text = first_line.to_string();
-            let remaining_markdown = lines[2..].join("\n");
-            return (header_text, remaining_markdown);
-        }
+        assert_eq!(output_for(&args(None), default.clone()), default);
+        assert_eq!(output_for(&args(Some("-")), default.clone()), None);
+        assert_eq!(
+            output_for(&args(Some("out.pdf")), default),
+            Some(PathBuf::from("out.pdf"))
+        );
+        // Reading stdin without --output.
+        assert_eq!(output_for(&args(None), None), None);
     }
 
-    (String::new(), markdown.to_string())
-}
+    #[test]
+    fn styles_tables_from_the_config() {
+        let mut config = get_default_config();
+        let css = table_styles(&config);
+        assert!(css.contains("border: 1px solid #000;"), "{}", css);
+        assert!(css.contains("display: table-header-group;"), "{}", css);
+        assert!(css.contains("text-align: left;"), "{}", css);
+        assert!(!css.contains("nth-child"), "{}", css);
+
+        config.tables.compact = true;
+        config.tables.zebra = true;
+        config.tables.repeat_header = false;
+        config.tables.markdown_alignment = false;
+        config.tables.wide = "landscape".to_string();
+        let css = table_styles(&config);
+        assert!(css.contains("padding: 2pt 4pt;"), "{}", css);
+        assert!(css.contains("tbody tr:nth-child(even) td { background-color: #fafafa; }"));
+        assert!(css.contains("display: table-row-group;"), "{}", css);
+        assert!(css.contains("text-align: left !important;"), "{}", css);
+        assert!(css.contains(".wide-table { page: landscape; }"), "{}", css);
+    }
+
+    #[test]
+    fn styles_lists_per_level() {
+        let mut config = get_default_config();
+        config.lists.bullets = vec!["disc".to_string(), "\"»".to_string()];
+        config.lists.numbering = vec!["decimal".to_string(), "lower-alpha".to_string()];
+        let css = list_styles(&config);
+        assert!(
+            css.contains("\n        ul { list-style-type: disc; }"),
+            "{}",
+            css
+        );
+        assert!(
+            css.contains(":is(ul, ol) ul { list-style-type: \"\\\"» \"; }"),
+            "{}",
+            css
+        );
+        assert!(
+            css.contains(":is(ul, ol) ol { list-style-type: lower-alpha; }"),
+            "{}",
+            css
+        );
+        assert!(!css.contains("counters"), "{}", css);
+
+        config.lists.legal = true;
+        let css = list_styles(&config);
+        assert!(!css.contains("ol { list-style-type: decimal"), "{}", css);
+        assert!(
+            css.contains("content: counters(legal, \".\") \". \";"),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn styles_blockquotes_and_links() {
+        let mut config = get_default_config();
+        config.blockquote.italic = true;
+        config.links.underline = false;
+        let css = blockquote_styles(&config);
+        assert!(css.contains("border-left: 3px solid #ccc;"), "{}", css);
+        assert!(css.contains("font-style: italic;"), "{}", css);
+        let css = link_styles(&config);
+        assert!(css.contains("color: #0000ee;"), "{}", css);
+        assert!(css.contains("text-decoration: none;"), "{}", css);
+    }
+
+    #[test]
+    fn breaks_pages_around_headings() {
+        let mut config = get_default_config();
+        config.headings.h3_page_break_after = true;
+        config.headings.h4_keep_with_next = false;
+        let css = heading_break_styles(&config);
+        assert_eq!(
+            css.lines().map(str::trim).collect::<Vec<_>>(),
+            [
+                "h1 { page-break-before: always; page-break-after: avoid; }",
+                "h2 { page-break-before: always; page-break-after: avoid; }",
+                "h3 { page-break-after: always; }",
+                "h5 { page-break-after: avoid; }",
+                "h6 { page-break-after: avoid; }",
+            ]
+        );
+    }
 
-fn markdown_to_html(markdown: &str) -> String {
-    use pulldown_cmark::{html, Options, Parser};
+    #[test]
+    fn sets_pages_in_columns() {
+        let mut config = get_default_config();
+        config.page.columns.count = 2;
+        config.page.columns.rule = "1px solid #ccc".to_string();
+        config.page.columns.span_headings = 3;
+        let css = layout_styles(&config, &PrintOptions::from_config(&config));
+        assert!(css.contains("column-count: 2;"), "{}", css);
+        assert!(css.contains("column-rule: 1px solid #ccc;"), "{}", css);
+        assert!(css.contains(":is(.page-columns, .columns) :is(h1, h2, h3) {"));
 
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_STRIKETHROUGH);
-    options.insert(Options::ENABLE_TABLES);
-    options.insert(Options::ENABLE_TASKLISTS);
+        config.page.columns.span_headings = 0;
+        let css = layout_styles(&config, &PrintOptions::from_config(&config));
+        assert!(!css.contains(":is(h1"), "{}", css);
+    }
 
-    let parser = Parser::new_ext(markdown, options);
+    #[test]
+    fn embeds_declared_font_files() {
+        let staging = StagingDir::create().unwrap();
+        let font = staging.path().join("Body.ttf");
+        fs::write(&font, b"font").unwrap();
+        let face = |path: &Path| FontFaceConfig {
+            family: "Body".to_string(),
+            path: path.display().to_string(),
+            weight: "700".to_string(),
+            style: "italic".to_string(),
+        };
 
-    let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
+        let mut config = get_default_config();
+        config.fonts.files = vec![
+            face(&font),
+            face(&staging.path().join("Body.pfb")),
+            face(&staging.path().join("Missing.otf")),
+        ];
+        assert_eq!(
+            font_face_styles(&config).trim_end(),
+            "@font-face {\n            font-family: 'Body';\n            \
+             src: url(data:font/ttf;base64,Zm9udA==) format('truetype');\n            \
+             font-weight: 700;\n            font-style: italic;\n        }"
+        );
 
-    html_output
-}
\ No newline at end of file
+        config.fonts.files = vec![FontFaceConfig {
+            family: "Ann's \\ Font".to_string(),
+            ..face(&font)
+        }];
+        assert!(font_face_styles(&config).contains("font-family: 'Ann\\'s \\\\ Font';"));
+    }
+}
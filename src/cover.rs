@@ -0,0 +1,297 @@
+//! Cover pages: the title, subtitle, authors, date, version, logo, abstract
+//! and a confidentiality notice on a page of their own, before the body.
+//!
+//! Values come from `title_page.cover` in the config, and the document's
+//! front matter overrides them (`title`, `subtitle`, `author`, `date`,
+//! `version`, `logo`, `abstract`, `notice`). Front matter lists authors
+//! separated by semicolons, each with an optional affiliation in
+//! parentheses: `author: Jane Doe (ACME Labs); Kim Lee`.
+
+use crate::config::{AuthorConfig, CoverConfig};
+use crate::images::ImageResolver;
+use crate::markdown::escape_html;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Cover {
+    pub title: String,
+    pub subtitle: String,
+    pub authors: Vec<AuthorConfig>,
+    pub date: String,
+    pub version: String,
+    pub logo: String,
+    pub abstract_text: String,
+    pub notice: String,
+}
+
+impl Cover {
+    /// The cover for a document, or `None` when covers are off. Without a
+    /// title anywhere, the extracted header is used.
+    pub fn build(
+        config: &CoverConfig,
+        front_matter: &HashMap<String, String>,
+        header_text: &str,
+    ) -> Option<Cover> {
+        if !config.enabled {
+            return None;
+        }
+        let value = |key: &str, default: &str| {
+            front_matter
+                .get(key)
+                .map_or(default.to_string(), |value| value.trim().to_string())
+        };
+
+        let mut title = value("title", &config.title);
+        if title.is_empty() {
+            title = header_text.to_string();
+        }
+        let authors = match front_matter.get("author").or(front_matter.get("authors")) {
+            Some(authors) => parse_authors(authors),
+            None => config.authors.clone(),
+        };
+        let date = match value("date", &config.date) {
+            date if date == "today" => today(),
+            date => date,
+        };
+
+        Some(Cover {
+            title,
+            subtitle: value("subtitle", &config.subtitle),
+            authors,
+            date,
+            version: value("version", &config.version),
+            logo: value("logo", &config.logo),
+            abstract_text: value("abstract", &config.abstract_text),
+            notice: value("notice", &config.notice),
+        })
+    }
+
+    /// The cover as a `<section>` ahead of the body, which the stylesheet
+    /// ends with a page break.
+    pub fn to_html(&self, config: &CoverConfig, images: &ImageResolver) -> String {
+        let mut html = String::from("<section class=\"cover\">\n");
+        let logo = if self.logo.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<div class=\"cover-logo\"><img src=\"{}\" alt=\"\"></div>\n",
+                escape_html(&images.html_src(&self.logo))
+            )
+        };
+        let logo_at_bottom = config.logo_position == "bottom";
+
+        if !logo_at_bottom {
+            html.push_str(&logo);
+        }
+        for (class, text) in [
+            ("cover-title", &self.title),
+            ("cover-subtitle", &self.subtitle),
+        ] {
+            if !text.is_empty() {
+                html.push_str(&format!(
+                    "<div class=\"{}\">{}</div>\n",
+                    class,
+                    escape_html(text)
+                ));
+            }
+        }
+
+        if !self.authors.is_empty() {
+            html.push_str("<div class=\"cover-authors\">\n");
+            for author in &self.authors {
+                html.push_str(&format!(
+                    "<div class=\"cover-author\">{}",
+                    escape_html(&author.name)
+                ));
+                if !author.affiliation.is_empty() {
+                    html.push_str(&format!(
+                        "<div class=\"cover-affiliation\">{}</div>",
+                        escape_html(&author.affiliation)
+                    ));
+                }
+                html.push_str("</div>\n");
+            }
+            html.push_str("</div>\n");
+        }
+
+        for (class, text) in [
+            ("cover-date", &self.date),
+            ("cover-version", &self.version),
+            ("cover-abstract", &self.abstract_text),
+        ] {
+            if !text.is_empty() {
+                html.push_str(&format!(
+                    "<div class=\"{}\">{}</div>\n",
+                    class,
+                    escape_html(text)
+                ));
+            }
+        }
+
+        if logo_at_bottom {
+            html.push_str(&logo);
+        }
+        if !self.notice.is_empty() {
+            html.push_str(&format!(
+                "<div class=\"cover-notice\">{}</div>\n",
+                escape_html(&self.notice)
+            ));
+        }
+
+        html.push_str("</section>\n");
+        html
+    }
+}
+
+/// `Jane Doe (ACME Labs); Kim Lee` as authors with affiliations.
+fn parse_authors(text: &str) -> Vec<AuthorConfig> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|author| !author.is_empty())
+        .map(|author| {
+            match author
+                .strip_suffix(')')
+                .and_then(|author| author.split_once('('))
+            {
+                Some((name, affiliation)) => AuthorConfig {
+                    name: name.trim().to_string(),
+                    affiliation: affiliation.trim().to_string(),
+                },
+                None => AuthorConfig {
+                    name: author.to_string(),
+                    affiliation: String::new(),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Today's date as `YYYY-MM-DD` (UTC).
+fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    date_from_days((seconds / 86_400) as i64)
+}
+
+/// Days since 1970-01-01 as `YYYY-MM-DD`, after Howard Hinnant's
+/// `civil_from_days`.
+fn date_from_days(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use std::path::Path;
+
+    fn front_matter(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn enabled() -> CoverConfig {
+        CoverConfig {
+            enabled: true,
+            title: "Config title".to_string(),
+            version: "1.0".to_string(),
+            authors: parse_authors("Config Author"),
+            ..CoverConfig::default()
+        }
+    }
+
+    #[test]
+    fn is_off_unless_enabled() {
+        assert!(Cover::build(&CoverConfig::default(), &HashMap::new(), "Header").is_none());
+    }
+
+    #[test]
+    fn prefers_front_matter_over_the_config() {
+        let front = front_matter(&[
+            ("title", " Front title "),
+            ("author", "Jane Doe (ACME Labs); Kim Lee;"),
+        ]);
+        let cover = Cover::build(&enabled(), &front, "Header").unwrap();
+        assert_eq!(cover.title, "Front title");
+        assert_eq!(cover.version, "1.0");
+        let authors: Vec<_> = cover
+            .authors
+            .iter()
+            .map(|author| (author.name.as_str(), author.affiliation.as_str()))
+            .collect();
+        assert_eq!(authors, [("Jane Doe", "ACME Labs"), ("Kim Lee", "")]);
+
+        let cover = Cover::build(&enabled(), &HashMap::new(), "Header").unwrap();
+        assert_eq!(cover.title, "Config title");
+        assert_eq!(cover.authors[0].name, "Config Author");
+    }
+
+    #[test]
+    fn falls_back_to_the_header_and_today() {
+        let config = CoverConfig {
+            title: String::new(),
+            date: "today".to_string(),
+            ..enabled()
+        };
+        let cover = Cover::build(&config, &HashMap::new(), "Header").unwrap();
+        assert_eq!(cover.title, "Header");
+        assert_eq!(cover.date, today());
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(date_from_days(0), "1970-01-01");
+        assert_eq!(date_from_days(59), "1970-03-01");
+        assert_eq!(date_from_days(11_016), "2000-02-29");
+        assert_eq!(date_from_days(20_454), "2026-01-01");
+    }
+
+    #[test]
+    fn renders_the_cover() {
+        let config = get_default_config();
+        let images = ImageResolver::new(&config.images, Path::new("."));
+        let front = front_matter(&[
+            ("title", "Q&A"),
+            ("logo", "https://example.com/logo.png"),
+            ("notice", "Confidential"),
+        ]);
+        let cover = Cover::build(&enabled(), &front, "").unwrap();
+
+        let html = cover.to_html(&enabled(), &images);
+        assert!(html.starts_with("<section class=\"cover\">\n<div class=\"cover-logo\"><img src=\"https://example.com/logo.png\""));
+        assert!(html.contains("<div class=\"cover-title\">Q&amp;A</div>"));
+        assert!(html.contains("<div class=\"cover-author\">Config Author</div>"));
+        assert!(html.contains("<div class=\"cover-version\">1.0</div>"));
+        assert!(!html.contains("cover-subtitle"));
+        assert!(html.ends_with("<div class=\"cover-notice\">Confidential</div>\n</section>\n"));
+
+        let bottom = CoverConfig {
+            logo_position: "bottom".to_string(),
+            ..enabled()
+        };
+        let html = cover.to_html(&bottom, &images);
+        assert!(
+            html.find("cover-logo") > html.find("cover-version"),
+            "{}",
+            html
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Layout directives: explicit page breaks, and blocks that are kept on one
+//! page, turned to landscape or set in columns.
+//!
+//! ```markdown
+//! \newpage
+//!
+//! <!-- keep-together -->
+//! | Quarter | Revenue |
+//! |---------|---------|
+//! | Q1      | 1.2M    |
+//! <!-- /keep-together -->
+//!
+//! <!-- columns: 2, gap: 2em, rule: 1px solid #ccc -->
+//! Two columns of text.
+//! <!-- /columns -->
+//! ```
+//!
+//! `<!-- pagebreak -->`, `<!-- newpage -->`, `\newpage` and `\pagebreak`
+//! start a new page. A columns block's gap and rule default to those in
+//! `page.columns`. Blocks nest, each directive stands on a line of its own,
+//! and directives inside fenced code blocks are left alone. They become raw
+//! HTML that the stylesheet and the native backend both understand.
+
+use crate::markdown::{CodeFences, PAGE_BREAK};
+
+pub const KEEP_TOGETHER: &str = "<div class=\"keep-together\">\n";
+pub const KEEP_TOGETHER_END: &str = "</div><!-- /keep-together -->\n";
+pub const LANDSCAPE: &str = "<div class=\"landscape\">\n";
+pub const LANDSCAPE_END: &str = "</div><!-- /landscape -->\n";
+pub const COLUMNS_END: &str = "</div><!-- /columns -->\n";
+const COLUMNS_OPEN: &str = "<div class=\"columns\" style=\"";
+const COLUMNS_OPEN_END: &str = "\">\n";
+
+/// How a columns block sets its content; `None` follows `page.columns`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnLayout {
+    pub count: usize,
+    pub gap: Option<String>,
+    pub rule: Option<String>,
+}
+
+impl ColumnLayout {
+    /// `2, gap: 2em, rule: 1px solid #ccc`.
+    fn parse(argument: &str) -> Result<ColumnLayout, String> {
+        let mut parts = argument.split(',').map(str::trim);
+        let mut layout = match parts.next().unwrap_or_default().parse::<usize>() {
+            Ok(count) if count >= 1 => ColumnLayout {
+                count,
+                ..ColumnLayout::default()
+            },
+            _ => {
+                return Err(format!(
+                    "<!-- columns: {} --> needs a number of columns",
+                    argument
+                ))
+            }
+        };
+
+        for part in parts {
+            let (name, value) = part.split_once(':').unwrap_or((part, ""));
+            let value = value.trim();
+            if value.is_empty() || value.contains(['"', ';', '<', '>']) {
+                return Err(format!("'{}' is not a valid columns setting", part));
+            }
+            match name.trim() {
+                "gap" => layout.gap = Some(value.to_string()),
+                "rule" => layout.rule = Some(value.to_string()),
+                other => {
+                    return Err(format!(
+                        "unknown columns setting '{}' (expected gap or rule)",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(layout)
+    }
+
+    fn to_html(&self) -> String {
+        let mut style = format!("column-count: {}", self.count);
+        if let Some(gap) = &self.gap {
+            style.push_str(&format!("; column-gap: {}", gap));
+        }
+        if let Some(rule) = &self.rule {
+            style.push_str(&format!("; column-rule: {}", rule));
+        }
+        format!("{}{}{}", COLUMNS_OPEN, style, COLUMNS_OPEN_END)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    KeepTogether,
+    Landscape,
+    Columns,
+}
+
+impl Kind {
+    fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "keep-together" => Some(Kind::KeepTogether),
+            "landscape" => Some(Kind::Landscape),
+            "columns" => Some(Kind::Columns),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::KeepTogether => "keep-together",
+            Kind::Landscape => "landscape",
+            Kind::Columns => "columns",
+        }
+    }
+
+    fn end(self) -> &'static str {
+        match self {
+            Kind::KeepTogether => KEEP_TOGETHER_END,
+            Kind::Landscape => LANDSCAPE_END,
+            Kind::Columns => COLUMNS_END,
+        }
+    }
+}
+
+enum Directive {
+    PageBreak,
+    Open(Kind, ColumnLayout),
+    Close(Kind),
+}
+
+/// The layout if `html` opens a columns block.
+pub fn column_layout(html: &str) -> Option<ColumnLayout> {
+    let style = html
+        .strip_prefix(COLUMNS_OPEN)?
+        .strip_suffix(COLUMNS_OPEN_END)?;
+    let mut layout = ColumnLayout::default();
+    for declaration in style.split(';') {
+        let (property, value) = declaration.split_once(':')?;
+        let value = value.trim().to_string();
+        match property.trim() {
+            "column-count" => layout.count = value.parse().ok()?,
+            "column-gap" => layout.gap = Some(value),
+            "column-rule" => layout.rule = Some(value),
+            _ => return None,
+        }
+    }
+    Some(layout)
+}
+
+/// Replaces layout directives with the raw HTML they stand for.
+pub fn expand_directives(markdown: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(markdown.len());
+    let mut fences = CodeFences::default();
+    // Open blocks, with the line each was opened on.
+    let mut open: Vec<(Kind, usize)> = Vec::new();
+
+    for (index, line) in markdown.split_inclusive('\n').enumerate() {
+        let number = index + 1;
+        let directive = if fences.is_code(line) {
+            None
+        } else {
+            directive(line.trim())
+                .transpose()
+                .map_err(|e| format!("line {}: {}", number, e))?
+        };
+        let Some(directive) = directive else {
+            output.push_str(line);
+            continue;
+        };
+
+        // Blank lines around the HTML keep the markdown on either side of
+        // it from being swallowed by the HTML block.
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let html = match directive {
+            Directive::PageBreak => PAGE_BREAK.to_string(),
+            Directive::Open(kind, layout) => {
+                open.push((kind, number));
+                match kind {
+                    Kind::KeepTogether => KEEP_TOGETHER.to_string(),
+                    Kind::Landscape => LANDSCAPE.to_string(),
+                    Kind::Columns => layout.to_html(),
+                }
+            }
+            Directive::Close(kind) => match open.pop() {
+                Some((opened, _)) if opened == kind => kind.end().to_string(),
+                Some((opened, opened_on)) => {
+                    return Err(format!(
+                        "line {}: <!-- /{} --> closes the <!-- {} --> on line {}",
+                        number,
+                        kind.name(),
+                        opened.name(),
+                        opened_on
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "line {}: <!-- /{} --> without a matching <!-- {} -->",
+                        number,
+                        kind.name(),
+                        kind.name()
+                    ))
+                }
+            },
+        };
+        output.push('\n');
+        output.push_str(indent);
+        output.push_str(&html);
+        output.push('\n');
+    }
+
+    match open.last() {
+        Some((kind, line)) => Err(format!(
+            "line {}: <!-- {} --> is never closed with <!-- /{} -->",
+            line,
+            kind.name(),
+            kind.name()
+        )),
+        None => Ok(output),
+    }
+}
+
+/// The directive a trimmed line holds, if any.
+fn directive(line: &str) -> Option<Result<Directive, String>> {
+    if matches!(line, "\\newpage" | "\\pagebreak") {
+        return Some(Ok(Directive::PageBreak));
+    }
+    let inner = line.strip_prefix("<!--")?.strip_suffix("-->")?.trim();
+
+    if matches!(inner, "pagebreak" | "newpage") {
+        return Some(Ok(Directive::PageBreak));
+    }
+    if let Some(name) = inner.strip_prefix('/') {
+        return Kind::from_name(name.trim()).map(|kind| Ok(Directive::Close(kind)));
+    }
+
+    let (name, argument) = match inner.split_once(':') {
+        Some((name, argument)) => (name.trim(), argument.trim()),
+        None => (inner, ""),
+    };
+    let kind = Kind::from_name(name)?;
+    if kind != Kind::Columns {
+        return argument
+            .is_empty()
+            .then(|| Ok(Directive::Open(kind, ColumnLayout::default())));
+    }
+    Some(ColumnLayout::parse(argument).map(|layout| Directive::Open(kind, layout)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_page_breaks() {
+        for directive in [
+            "\\newpage",
+            "\\pagebreak",
+            "<!-- pagebreak -->",
+            "<!--newpage-->",
+        ] {
+            assert_eq!(
+                expand_directives(&format!("One\n{}\nTwo\n", directive)).unwrap(),
+                format!("One\n\n{}\nTwo\n", PAGE_BREAK)
+            );
+        }
+    }
+
+    #[test]
+    fn expands_blocks() {
+        let markdown = "<!-- keep-together -->\n- a\n  <!-- landscape -->\n  b\n  <!-- /landscape -->\n<!-- /keep-together -->\n";
+        assert_eq!(
+            expand_directives(markdown).unwrap(),
+            format!(
+                "\n{}\n- a\n\n  {}\n  b\n\n  {}\n\n{}\n",
+                KEEP_TOGETHER, LANDSCAPE, LANDSCAPE_END, KEEP_TOGETHER_END
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_other_lines_alone() {
+        let markdown = "```\n\\newpage\n<!-- keep-together -->\n```\nText \\newpage\n<!-- comment -->\n<!-- keep-together: yes -->\n";
+        assert_eq!(expand_directives(markdown).unwrap(), markdown);
+    }
+
+    #[test]
+    fn reports_unbalanced_blocks() {
+        let cases = [
+            (
+                "<!-- /landscape -->\n",
+                "line 1: <!-- /landscape --> without a matching <!-- landscape -->",
+            ),
+            (
+                "<!-- landscape -->\n<!-- keep-together -->\n<!-- /landscape -->\n",
+                "line 3: <!-- /landscape --> closes the <!-- keep-together --> on line 2",
+            ),
+            (
+                "x\n<!-- keep-together -->\n",
+                "line 2: <!-- keep-together --> is never closed with <!-- /keep-together -->",
+            ),
+        ];
+        for (markdown, error) in cases {
+            assert_eq!(expand_directives(markdown).unwrap_err(), error);
+        }
+    }
+
+    #[test]
+    fn parses_column_settings() {
+        assert_eq!(
+            ColumnLayout::parse("2, gap: 2em, rule: 1px solid #ccc").unwrap(),
+            ColumnLayout {
+                count: 2,
+                gap: Some("2em".to_string()),
+                rule: Some("1px solid #ccc".to_string()),
+            }
+        );
+        assert_eq!(
+            ColumnLayout::parse("").unwrap_err(),
+            "<!-- columns:  --> needs a number of columns"
+        );
+        assert!(ColumnLayout::parse("0").is_err());
+        assert_eq!(
+            ColumnLayout::parse("2, gap").unwrap_err(),
+            "'gap' is not a valid columns setting"
+        );
+        assert!(ColumnLayout::parse("2, rule: 1px\"><script>").is_err());
+        assert_eq!(
+            ColumnLayout::parse("2, width: 3em").unwrap_err(),
+            "unknown columns setting 'width' (expected gap or rule)"
+        );
+    }
+
+    #[test]
+    fn reads_column_layouts_back_from_html() {
+        let markdown = "<!-- columns: 3, gap: 1cm -->\nText\n<!-- /columns -->\n";
+        let html = expand_directives(markdown).unwrap();
+        assert_eq!(
+            html,
+            format!(
+                "\n{}column-count: 3; column-gap: 1cm{}\nText\n\n{}\n",
+                COLUMNS_OPEN, COLUMNS_OPEN_END, COLUMNS_END
+            )
+        );
+
+        let open = html.lines().nth(1).unwrap();
+        let layout = ColumnLayout {
+            count: 3,
+            gap: Some("1cm".to_string()),
+            rule: None,
+        };
+        assert_eq!(column_layout(&format!("{}\n", open)), Some(layout));
+        assert_eq!(column_layout(KEEP_TOGETHER), None);
+        assert_eq!(
+            column_layout("<div class=\"columns\" style=\"color: red\">\n"),
+            None
+        );
+        assert_eq!(
+            expand_directives("<!-- columns: two -->\n").unwrap_err(),
+            "line 1: <!-- columns: two --> needs a number of columns"
+        );
+    }
+}
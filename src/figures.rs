@@ -0,0 +1,593 @@
+//! Figure and table numbering, labels and cross-references.
+//!
+//! This runs over the parsed markdown before any backend sees it, so the HTML
+//! and native renderers number things identically:
+//!
+//! - A figure is an image standing on its own (in a paragraph holding only
+//!   images) that has alt text or a `{#fig:label}`. Its alt text gets the
+//!   "Figure N: " prefix and its attributes always carry an id.
+//! - A table is numbered when a `Table: caption {#tbl:label}` paragraph sits
+//!   right before or after it. That paragraph is removed from the document
+//!   and returned as the table's caption instead.
+//! - `@fig:label`, `@tbl:label` and `@sec:label` in text become links
+//!   reading "Figure N" or "Section 2.1" (the title for unnumbered headings).
+//! - A paragraph reading `[[list-of-figures]]` or `[[list-of-tables]]` is
+//!   replaced by a list linking to every figure or table.
+
+use crate::config::CaptionsConfig;
+use crate::markdown::ImageAttrs;
+use crate::sections::Section;
+use pulldown_cmark::{CowStr, Event, LinkType, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+const LIST_OF_FIGURES: &str = "[[list-of-figures]]";
+const LIST_OF_TABLES: &str = "[[list-of-tables]]";
+const REFERENCE_KINDS: [&str; 3] = ["fig", "tbl", "sec"];
+
+#[derive(Debug, Clone)]
+pub struct TableCaption {
+    pub id: String,
+    pub text: String,
+}
+
+/// A numbered figure or table, for cross-references and generated lists.
+struct Entry {
+    id: String,
+    name: String,
+    caption: String,
+}
+
+/// An image that forms a figure, by event index.
+struct FigureImage {
+    start: usize,
+    end: usize,
+    attrs_at: Option<usize>,
+    attrs: ImageAttrs,
+    rest: String,
+}
+
+/// Numbers figures and tables and resolves references to them and to
+/// `sections`. Returns the rewritten events and, for every table in document
+/// order, its caption if it has one.
+pub fn number_figures<'a>(
+    events: Vec<Event<'a>>,
+    config: &CaptionsConfig,
+    sections: &[Section],
+) -> (Vec<Event<'a>>, Vec<Option<TableCaption>>) {
+    let events = merge_text(events);
+
+    // Events to put in place of an event (empty to drop it), or after it.
+    let mut replace: HashMap<usize, Vec<Event<'a>>> = HashMap::new();
+    let mut insert_after: HashMap<usize, Vec<Event<'a>>> = HashMap::new();
+
+    let mut figures = Vec::new();
+    for image in figure_images(&events) {
+        let alt = plain_text(&events[image.start + 1..image.end]);
+        if alt.is_empty() && image.attrs.id.is_none() {
+            continue;
+        }
+
+        let number = figures.len() + 1;
+        let id = image
+            .attrs
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("figure-{}", number));
+        let name = format!("{} {}", config.figure_label, number);
+        let caption = caption_text(config, &name, &alt);
+
+        if config.numbering {
+            let prefix = if alt.is_empty() {
+                name.clone()
+            } else {
+                format!("{}{}", name, config.separator)
+            };
+            insert_after
+                .entry(image.start)
+                .or_default()
+                .push(Event::Text(prefix.into()));
+        }
+
+        let attrs = ImageAttrs {
+            id: Some(id.clone()),
+            ..image.attrs
+        };
+        let attrs_text = Event::Text(format!("{}{}", attrs, image.rest).into());
+        match image.attrs_at {
+            Some(index) => {
+                replace.insert(index, vec![attrs_text]);
+            }
+            None => insert_after.entry(image.end).or_default().push(attrs_text),
+        }
+
+        figures.push(Entry { id, name, caption });
+    }
+
+    let mut tables = Vec::new();
+    let mut table_captions = Vec::new();
+    let mut claimed = HashSet::new();
+    for (start, end) in table_ranges(&events) {
+        let caption = [
+            paragraph_before(&events, start),
+            paragraph_after(&events, end),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|range| !claimed.contains(&range.start))
+        .find_map(|range| {
+            let text = plain_text(&events[range.start + 1..range.end - 1]);
+            let text = text.trim().strip_prefix("Table:")?.trim().to_string();
+            Some((range, text))
+        });
+
+        let Some((range, text)) = caption else {
+            table_captions.push(None);
+            continue;
+        };
+        claimed.insert(range.start);
+        for index in range {
+            replace.insert(index, Vec::new());
+        }
+
+        let (text, label) = split_label(&text);
+        let number = tables.len() + 1;
+        let id = label.unwrap_or_else(|| format!("table-{}", number));
+        let name = format!("{} {}", config.table_label, number);
+        let caption = caption_text(config, &name, &text);
+
+        table_captions.push(Some(TableCaption {
+            id: id.clone(),
+            text: caption.clone(),
+        }));
+        tables.push(Entry { id, name, caption });
+    }
+
+    for range in paragraphs(&events) {
+        let entries = match plain_text(&events[range.start + 1..range.end - 1]).trim() {
+            LIST_OF_FIGURES => (&figures, "list-of-figures"),
+            LIST_OF_TABLES => (&tables, "list-of-tables"),
+            _ => continue,
+        };
+        for index in range.clone() {
+            replace.insert(index, Vec::new());
+        }
+        replace.insert(range.start, list_events(entries.0, entries.1));
+    }
+
+    let sections: Vec<Entry> = sections
+        .iter()
+        .map(|section| Entry {
+            id: section.id.clone(),
+            name: if section.number.is_empty() {
+                section.title.clone()
+            } else {
+                format!("{} {}", config.section_label, section.number)
+            },
+            caption: section.title.clone(),
+        })
+        .collect();
+    let labels: HashMap<&str, &str> = figures
+        .iter()
+        .chain(&tables)
+        .chain(&sections)
+        .map(|entry| (entry.id.as_str(), entry.name.as_str()))
+        .collect();
+
+    let mut output = Vec::with_capacity(events.len());
+    let mut warned = HashSet::new();
+    let mut link_depth = 0usize;
+    let mut in_code = false;
+    for (index, event) in events.into_iter().enumerate() {
+        if let Some(replacement) = replace.remove(&index) {
+            output.extend(replacement);
+        } else {
+            match &event {
+                Event::Start(Tag::Link { .. } | Tag::Image { .. }) => link_depth += 1,
+                Event::End(TagEnd::Link | TagEnd::Image) => {
+                    link_depth = link_depth.saturating_sub(1)
+                }
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(TagEnd::CodeBlock) => in_code = false,
+                _ => {}
+            }
+
+            match event {
+                // Inside links and alt text references become plain text.
+                Event::Text(text) if !in_code && text.contains('@') => output.extend(
+                    resolve_references(&text, &labels, link_depth == 0, &mut warned),
+                ),
+                event => output.push(event),
+            }
+        }
+
+        if let Some(extra) = insert_after.remove(&index) {
+            output.extend(extra);
+        }
+    }
+
+    (output, table_captions)
+}
+
+fn caption_text(config: &CaptionsConfig, name: &str, text: &str) -> String {
+    match (config.numbering, text.is_empty()) {
+        (true, true) => name.to_string(),
+        (true, false) => format!("{}{}{}", name, config.separator, text),
+        (false, _) => text.to_string(),
+    }
+}
+
+/// pulldown-cmark splits text at characters that might start markup, which
+/// would cut labels and `{...}` blocks in two.
+pub fn merge_text(events: Vec<Event>) -> Vec<Event> {
+    let mut merged: Vec<Event> = Vec::with_capacity(events.len());
+
+    for event in events {
+        match (merged.last_mut(), event) {
+            (Some(Event::Text(previous)), Event::Text(text)) => {
+                *previous = CowStr::from(format!("{}{}", previous, text));
+            }
+            (_, event) => merged.push(event),
+        }
+    }
+
+    merged
+}
+
+fn plain_text(events: &[Event]) -> String {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+            Event::SoftBreak | Event::HardBreak => Some(" "),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Index ranges of every paragraph, from its start event to one past its end.
+fn paragraphs(events: &[Event]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+
+    for (index, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::Paragraph) => start = Some(index),
+            Event::End(TagEnd::Paragraph) => {
+                if let Some(start) = start.take() {
+                    ranges.push(start..index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ranges
+}
+
+fn figure_images(events: &[Event]) -> Vec<FigureImage> {
+    paragraphs(events)
+        .into_iter()
+        .filter_map(|range| images_only(events, range.start + 1, range.end - 1))
+        .flatten()
+        .collect()
+}
+
+/// The images in `events[from..to]` if there is nothing else but whitespace.
+fn images_only(events: &[Event], from: usize, to: usize) -> Option<Vec<FigureImage>> {
+    let mut images = Vec::new();
+    let mut index = from;
+
+    while index < to {
+        match &events[index] {
+            Event::Start(Tag::Image { .. }) => {
+                let end = (index..to).find(|&i| events[i] == Event::End(TagEnd::Image))?;
+                let mut image = FigureImage {
+                    start: index,
+                    end,
+                    attrs_at: None,
+                    attrs: ImageAttrs::default(),
+                    rest: String::new(),
+                };
+                index = end + 1;
+
+                if let Some(Event::Text(text)) = events.get(index).filter(|_| index < to) {
+                    if let Some((attrs, rest)) = ImageAttrs::split_from(text) {
+                        if !rest.trim().is_empty() {
+                            return None;
+                        }
+                        image.attrs_at = Some(index);
+                        image.attrs = attrs;
+                        image.rest = rest.to_string();
+                        index += 1;
+                    }
+                }
+                images.push(image);
+            }
+            Event::Text(text) if text.trim().is_empty() => index += 1,
+            Event::SoftBreak => index += 1,
+            _ => return None,
+        }
+    }
+
+    (!images.is_empty()).then_some(images)
+}
+
+fn table_ranges(events: &[Event]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+
+    for (index, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::Table(_)) => start = Some(index),
+            Event::End(TagEnd::Table) => {
+                if let Some(start) = start.take() {
+                    ranges.push((start, index));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ranges
+}
+
+fn paragraph_before(events: &[Event], table_start: usize) -> Option<Range<usize>> {
+    let end = table_start.checked_sub(1)?;
+    if events[end] != Event::End(TagEnd::Paragraph) {
+        return None;
+    }
+    let start = events[..end]
+        .iter()
+        .rposition(|event| *event == Event::Start(Tag::Paragraph))?;
+    Some(start..end + 1)
+}
+
+fn paragraph_after(events: &[Event], table_end: usize) -> Option<Range<usize>> {
+    let start = table_end + 1;
+    if events.get(start) != Some(&Event::Start(Tag::Paragraph)) {
+        return None;
+    }
+    let end = events[start..]
+        .iter()
+        .position(|event| *event == Event::End(TagEnd::Paragraph))?;
+    Some(start..start + end + 1)
+}
+
+/// Splits a trailing `{#label}` off a caption.
+fn split_label(text: &str) -> (String, Option<String>) {
+    if let Some(open) = text.rfind("{#") {
+        if let Some(label) = text[open + 2..].strip_suffix('}') {
+            if !label.is_empty() && !label.contains(char::is_whitespace) {
+                return (text[..open].trim_end().to_string(), Some(label.to_string()));
+            }
+        }
+    }
+    (text.to_string(), None)
+}
+
+fn list_events<'a>(entries: &[Entry], class: &str) -> Vec<Event<'a>> {
+    if entries.is_empty() {
+        return Vec::new();
+    }
+
+    let mut events = vec![
+        Event::Html(format!("<nav class=\"{}\">\n", class).into()),
+        Event::Start(Tag::List(None)),
+    ];
+    for entry in entries {
+        events.extend([
+            Event::Start(Tag::Item),
+            Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url: format!("#{}", entry.id).into(),
+                title: "".into(),
+                id: "".into(),
+            }),
+            Event::Text(entry.caption.clone().into()),
+            Event::End(TagEnd::Link),
+            Event::End(TagEnd::Item),
+        ]);
+    }
+    events.push(Event::End(TagEnd::List(false)));
+    events.push(Event::Html("</nav>\n".into()));
+
+    events
+}
+
+/// Replaces `@fig:label` references in `text` with the figure's name, as a
+/// link to it when `link` is set. Unknown labels are left as written.
+fn resolve_references<'a>(
+    text: &str,
+    labels: &HashMap<&str, &str>,
+    link: bool,
+    warned: &mut HashSet<String>,
+) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(at) = rest.find('@') {
+        plain.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        let standalone = plain.chars().last().is_none_or(|c| !c.is_alphanumeric());
+
+        let Some(label) = reference_label(after).filter(|_| standalone) else {
+            plain.push('@');
+            rest = after;
+            continue;
+        };
+        rest = &after[label.len()..];
+
+        match labels.get(label) {
+            Some(name) if link => {
+                if !plain.is_empty() {
+                    events.push(Event::Text(std::mem::take(&mut plain).into()));
+                }
+                events.extend([
+                    Event::Start(Tag::Link {
+                        link_type: LinkType::Inline,
+                        dest_url: format!("#{}", label).into(),
+                        title: "".into(),
+                        id: "".into(),
+                    }),
+                    Event::Text(name.to_string().into()),
+                    Event::End(TagEnd::Link),
+                ]);
+            }
+            Some(name) => plain.push_str(name),
+            None => {
+                if warned.insert(label.to_string()) {
+                    eprintln!("Warning: reference to unknown label '@{}'", label);
+                }
+                plain.push('@');
+                plain.push_str(label);
+            }
+        }
+    }
+
+    plain.push_str(rest);
+    if !plain.is_empty() {
+        events.push(Event::Text(plain.into()));
+    }
+    events
+}
+
+/// `fig:arch` from `fig:arch, which ...`, if it starts with a known kind.
+fn reference_label(text: &str) -> Option<&str> {
+    let (kind, name) = text.split_once(':')?;
+    if !REFERENCE_KINDS.contains(&kind) {
+        return None;
+    }
+
+    let name_len = name
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(name.len());
+    (name_len > 0).then(|| &text[..kind.len() + 1 + name_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::parse_events;
+
+    fn numbered(markdown: &str) -> Vec<Event<'_>> {
+        numbered_with_captions(markdown).0
+    }
+
+    fn numbered_with_captions(markdown: &str) -> (Vec<Event<'_>>, Vec<Option<TableCaption>>) {
+        let sections = [Section {
+            id: "sec:intro".to_string(),
+            level: 1,
+            number: "1".to_string(),
+            title: "Intro".to_string(),
+        }];
+        number_figures(
+            parse_events(markdown),
+            &CaptionsConfig::default(),
+            &sections,
+        )
+    }
+
+    /// Each link's target and text.
+    fn links(events: &[Event]) -> Vec<(String, String)> {
+        events
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Event::Start(Tag::Link { dest_url, .. }), Event::Text(text)] => {
+                    Some((dest_url.to_string(), text.to_string()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn texts(events: &[Event]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolves_references_in_text() {
+        let events = numbered("See @sec:intro.\n");
+        assert_eq!(texts(&events), "See Section 1.");
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::Start(Tag::Link { dest_url, .. }) if dest_url.as_ref() == "#sec:intro"
+        )));
+    }
+
+    #[test]
+    fn leaves_references_in_code_blocks_alone() {
+        let events = numbered("```\nprintln!(\"@sec:intro\");\n```\n\nSee @sec:intro.\n");
+        assert_eq!(texts(&events), "println!(\"@sec:intro\");\nSee Section 1.");
+        let links = events
+            .iter()
+            .filter(|event| matches!(event, Event::Start(Tag::Link { .. })))
+            .count();
+        assert_eq!(links, 1);
+    }
+
+    #[test]
+    fn numbers_figures() {
+        let events = numbered("![A chart](chart.png){#fig:chart}\n\nSee @fig:chart.\n");
+        assert!(texts(&events).contains("See Figure 1."));
+    }
+
+    #[test]
+    fn captions_tables_from_above_or_below() {
+        let (events, captions) = numbered_with_captions(
+            "Table: Prices\n\n| a |\n|---|\n| 1 |\n\n\
+             | b |\n|---|\n| 2 |\n\nTable: Sizes {#tbl:sizes}\n\n\
+             | c |\n|---|\n| 3 |\n\nSee @tbl:sizes.\n",
+        );
+        let captions: Vec<_> = captions
+            .into_iter()
+            .map(|caption| caption.map(|caption| (caption.id, caption.text)))
+            .collect();
+        // The caption between two tables belongs to the one it follows.
+        assert_eq!(
+            captions,
+            [
+                Some(("table-1".to_string(), "Table 1: Prices".to_string())),
+                Some(("tbl:sizes".to_string(), "Table 2: Sizes".to_string())),
+                None
+            ]
+        );
+        assert_eq!(texts(&events), "a1b2c3See Table 2.");
+        assert_eq!(
+            links(&events),
+            [("#tbl:sizes".to_string(), "Table 2".to_string())]
+        );
+    }
+
+    #[test]
+    fn lists_figures_and_tables() {
+        let events = numbered(
+            "[[list-of-figures]]\n\n[[list-of-tables]]\n\n![Chart](chart.png)\n\n\
+             Table: Prices {#tbl:prices}\n\n| a |\n|---|\n| 1 |\n",
+        );
+        assert_eq!(
+            links(&events),
+            [
+                ("#figure-1".to_string(), "Figure 1: Chart".to_string()),
+                ("#tbl:prices".to_string(), "Table 1: Prices".to_string())
+            ]
+        );
+        assert!(events.contains(&Event::Html("<nav class=\"list-of-figures\">\n".into())));
+        assert!(events.contains(&Event::Html("<nav class=\"list-of-tables\">\n".into())));
+        assert!(!texts(&events).contains("[[list-of"));
+    }
+
+    #[test]
+    fn drops_empty_lists() {
+        let events = numbered("[[list-of-tables]]\n\nNo tables here.\n");
+        assert_eq!(texts(&events), "No tables here.");
+        assert!(!events.iter().any(|event| matches!(event, Event::Html(_))));
+    }
+}
@@ -0,0 +1,232 @@
+//! Locating font files by family name, among those declared in
+//! `fonts.files` and those installed on this machine.
+
+use crate::config::{Config, FontFaceConfig};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use ttf_parser::{name_id, Face};
+
+#[derive(Debug, Clone)]
+pub struct FontFile {
+    pub path: PathBuf,
+    pub family: String,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// Every face of `family` installed on this machine, matched case-insensitively
+/// against the font's own family names.
+pub fn find_family(family: &str) -> Vec<FontFile> {
+    let wanted = family.trim().to_lowercase();
+    let files = font_files();
+
+    // Font file names usually start with (an abbreviation of) the family's
+    // first word, so try those before parsing every installed font.
+    let prefix: String = wanted
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take(4)
+        .collect();
+    let likely: Vec<&PathBuf> = files
+        .iter()
+        .filter(|path| file_stem(path).starts_with(&prefix))
+        .collect();
+
+    let matches: Vec<FontFile> = likely
+        .into_iter()
+        .filter_map(|path| read_face(path))
+        .filter(|(names, _)| has_name(names, &wanted))
+        .map(|(_, face)| face)
+        .collect();
+    if !matches.is_empty() {
+        return matches;
+    }
+
+    all_faces()
+        .iter()
+        .filter(|(names, _)| has_name(names, &wanted))
+        .map(|(_, face)| face.clone())
+        .collect()
+}
+
+/// The faces of `family` declared in `fonts.files`.
+pub fn declared_faces(files: &[FontFaceConfig], family: &str) -> Vec<FontFile> {
+    let wanted = family.trim().to_lowercase();
+    files
+        .iter()
+        .filter(|file| file.family.trim().to_lowercase() == wanted)
+        .map(|file| FontFile {
+            path: file.file_path(),
+            family: file.family.clone(),
+            bold: file.is_bold(),
+            italic: file.is_italic(),
+        })
+        .collect()
+}
+
+/// Warns about body and code families that are neither declared in
+/// `fonts.files` nor installed, which the renderer would quietly replace.
+pub fn check_families(config: &Config) {
+    for family in [&config.fonts.body_family, &config.fonts.code_family] {
+        let generic = matches!(
+            family.trim().to_lowercase().as_str(),
+            "serif" | "sans-serif" | "monospace" | "cursive" | "fantasy" | "system-ui"
+        );
+        if generic
+            || declared_faces(&config.fonts.files, family)
+                .iter()
+                .any(|face| face.path.is_file())
+        {
+            continue;
+        }
+        if find_family(family).is_empty() {
+            eprintln!(
+                "Warning: font '{}' is not installed or declared in fonts.files",
+                family
+            );
+        }
+    }
+}
+
+fn has_name(names: &[String], wanted: &str) -> bool {
+    names.iter().any(|name| name.to_lowercase() == wanted)
+}
+
+/// Parsing every installed font is slow, so it happens at most once per run.
+fn all_faces() -> &'static [(Vec<String>, FontFile)] {
+    static FACES: OnceLock<Vec<(Vec<String>, FontFile)>> = OnceLock::new();
+    FACES.get_or_init(|| {
+        font_files()
+            .iter()
+            .filter_map(|path| read_face(path))
+            .collect()
+    })
+}
+
+fn read_face(path: &Path) -> Option<(Vec<String>, FontFile)> {
+    let data = fs::read(path).ok()?;
+    let face = Face::parse(&data, 0).ok()?;
+
+    let names: Vec<String> = face
+        .names()
+        .into_iter()
+        .filter(|name| {
+            name.name_id == name_id::FAMILY || name.name_id == name_id::TYPOGRAPHIC_FAMILY
+        })
+        .filter_map(|name| name.to_string())
+        .collect();
+    let family = names.first()?.clone();
+
+    Some((
+        names,
+        FontFile {
+            path: path.to_path_buf(),
+            family,
+            bold: face.is_bold(),
+            italic: face.is_italic() || face.is_oblique(),
+        },
+    ))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if cfg!(windows) {
+        let windir = env::var_os("WINDIR").unwrap_or_else(|| r"C:\Windows".into());
+        dirs.push(PathBuf::from(windir).join("Fonts"));
+        if let Some(local) = env::var_os("LOCALAPPDATA") {
+            dirs.push(PathBuf::from(local).join(r"Microsoft\Windows\Fonts"));
+        }
+    } else {
+        dirs.push(PathBuf::from("/usr/share/fonts"));
+        dirs.push(PathBuf::from("/usr/local/share/fonts"));
+        dirs.push(PathBuf::from("/Library/Fonts"));
+        dirs.push(PathBuf::from("/System/Library/Fonts"));
+        if let Some(home) = env::var_os("HOME") {
+            let home = PathBuf::from(home);
+            dirs.push(home.join(".fonts"));
+            dirs.push(home.join(".local/share/fonts"));
+            dirs.push(home.join("Library/Fonts"));
+        }
+    }
+
+    dirs
+}
+
+fn font_files() -> &'static [PathBuf] {
+    static FILES: OnceLock<Vec<PathBuf>> = OnceLock::new();
+    FILES.get_or_init(scan_font_dirs)
+}
+
+fn scan_font_dirs() -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = font_dirs();
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if matches!(
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .map(str::to_lowercase)
+                    .as_deref(),
+                Some("ttf" | "otf")
+            ) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_declared_faces_by_family() {
+        let face = |family: &str, path: &str, weight: &str| FontFaceConfig {
+            family: family.to_string(),
+            path: path.to_string(),
+            weight: weight.to_string(),
+            style: "normal".to_string(),
+        };
+        let files = [
+            face("Inter", "/fonts/Inter-Regular.ttf", "normal"),
+            face("Other", "/fonts/Other.ttf", "normal"),
+            face(" inter ", "/fonts/Inter-Bold.ttf", "700"),
+        ];
+
+        let faces = declared_faces(&files, "INTER");
+        let found: Vec<_> = faces
+            .iter()
+            .map(|face| (face.path.as_path(), face.bold))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Path::new("/fonts/Inter-Regular.ttf"), false),
+                (Path::new("/fonts/Inter-Bold.ttf"), true),
+            ]
+        );
+        assert!(declared_faces(&files, "Missing").is_empty());
+    }
+}
@@ -0,0 +1,284 @@
+//! Locating the local images a document refers to.
+//!
+//! Image sources are resolved against the markdown file's directory (then
+//! `images.search_paths`) and rewritten to absolute `file://` URLs, or inlined
+//! as data URIs, so rendering never depends on where the HTML is staged.
+
+use crate::backend::file_url;
+use crate::config::ImagesConfig;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct ImageResolver {
+    search_dirs: Vec<PathBuf>,
+    embed: bool,
+    missing: RefCell<Vec<String>>,
+}
+
+impl ImageResolver {
+    pub fn new(config: &ImagesConfig, base_dir: &Path) -> Self {
+        let mut search_dirs = vec![base_dir.to_path_buf()];
+        search_dirs.extend(config.search_paths.iter().map(|dir| base_dir.join(dir)));
+
+        ImageResolver {
+            search_dirs,
+            embed: config.embed,
+            missing: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The local file `src` refers to, if it is a local path that exists.
+    pub fn locate(&self, src: &str) -> Option<PathBuf> {
+        if is_external(src) {
+            return None;
+        }
+
+        let path = src.split(['?', '#']).next().unwrap_or_default();
+        let path = PathBuf::from(percent_decode(path));
+        if path.as_os_str().is_empty() {
+            return None;
+        }
+
+        self.search_dirs
+            .iter()
+            .map(|dir| dir.join(&path))
+            .find(|candidate| candidate.is_file())
+            .and_then(|found| fs::canonicalize(found).ok())
+    }
+
+    /// The `src` to put in the generated HTML. Remote and inline images are
+    /// left alone; missing local ones are reported and kept as written.
+    pub fn html_src(&self, src: &str) -> String {
+        if is_external(src) {
+            return src.to_string();
+        }
+
+        let Some(path) = self.locate(src) else {
+            let mut missing = self.missing.borrow_mut();
+            if !missing.iter().any(|seen| seen == src) {
+                eprintln!("Warning: image not found: '{}'", src);
+                missing.push(src.to_string());
+            }
+            return src.to_string();
+        };
+
+        if self.embed {
+            match data_uri(&path) {
+                Ok(uri) => return uri,
+                Err(e) => eprintln!("Warning: could not embed image {}: {}", path.display(), e),
+            }
+        }
+
+        file_url(&path)
+    }
+
+    /// Every local image that could not be found so far.
+    pub fn missing(&self) -> Vec<String> {
+        self.missing.borrow().clone()
+    }
+}
+
+/// Rewrites the `src` of every `<img>` tag in a chunk of raw HTML.
+pub fn rewrite_img_tags(html: &str, resolver: &ImageResolver) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut output = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut search = 0;
+
+    while let Some(offset) = lower[search..].find("<img") {
+        let tag_start = search + offset;
+        let tag_end = lower[tag_start..]
+            .find('>')
+            .map_or(html.len(), |end| tag_start + end);
+        search = tag_end;
+
+        let Some((value_start, value_end)) = src_value(&lower, tag_start, tag_end) else {
+            continue;
+        };
+
+        output.push_str(&html[copied..value_start]);
+        output.push_str(&resolver.html_src(&html[value_start..value_end]));
+        copied = value_end;
+    }
+
+    output.push_str(&html[copied..]);
+    output
+}
+
+/// Byte range of the `src` attribute's value within the tag `[start, end)`.
+fn src_value(lower: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let bytes = lower.as_bytes();
+    let mut i = start + "<img".len();
+
+    while let Some(offset) = lower[i..end].find("src") {
+        let name_start = i + offset;
+        i = name_start + "src".len();
+
+        // Skip attributes like `data-src` or `srcset`.
+        if !bytes[name_start - 1].is_ascii_whitespace() {
+            continue;
+        }
+        let mut j = i;
+        while j < end && bytes[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        if j >= end || bytes[j] != b'=' {
+            continue;
+        }
+        j += 1;
+        while j < end && bytes[j].is_ascii_whitespace() {
+            j += 1;
+        }
+
+        return match bytes.get(j) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let close = lower[j + 1..end].find(quote as char)? + j + 1;
+                Some((j + 1, close))
+            }
+            _ => {
+                // Paths hold slashes; only the one in a closing `/>` ends
+                // the value.
+                let mut value_end = lower[j..end]
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .map_or(end, |offset| j + offset);
+                if value_end == end && value_end > j && bytes[value_end - 1] == b'/' {
+                    value_end -= 1;
+                }
+                Some((j, value_end))
+            }
+        };
+    }
+
+    None
+}
+
+/// URLs with a scheme (`https:`, `data:`, ...) or protocol-relative ones.
+/// A single letter before the colon is a Windows drive, not a scheme.
+pub fn is_external(src: &str) -> bool {
+    if src.starts_with("//") {
+        return true;
+    }
+
+    match src.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+fn data_uri(path: &Path) -> std::io::Result<String> {
+    let mime = match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        _ => "application/octet-stream",
+    };
+
+    Ok(format!(
+        "data:{};base64,{}",
+        mime,
+        BASE64.encode(fs::read(path)?)
+    ))
+}
+
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src_of(tag: &str) -> Option<&str> {
+        let lower = tag.to_ascii_lowercase();
+        let end = lower.find('>').unwrap_or(tag.len());
+        src_value(&lower, 0, end).map(|(start, end)| &tag[start..end])
+    }
+
+    #[test]
+    fn finds_quoted_src() {
+        assert_eq!(
+            src_of("<img alt=\"x\" src=\"a b/c.png\">"),
+            Some("a b/c.png")
+        );
+        assert_eq!(src_of("<img src='images/c.png' />"), Some("images/c.png"));
+    }
+
+    #[test]
+    fn finds_unquoted_src_with_path() {
+        assert_eq!(
+            src_of("<img src=images/chart.png width=50>"),
+            Some("images/chart.png")
+        );
+        assert_eq!(
+            src_of("<IMG SRC=images/chart.png>"),
+            Some("images/chart.png")
+        );
+    }
+
+    #[test]
+    fn strips_self_closing_slash() {
+        assert_eq!(
+            src_of("<img src=images/chart.png/>"),
+            Some("images/chart.png")
+        );
+        assert_eq!(
+            src_of("<img src=images/chart.png />"),
+            Some("images/chart.png")
+        );
+    }
+
+    #[test]
+    fn skips_similar_attributes() {
+        assert_eq!(src_of("<img data-src=a.png srcset=b.png>"), None);
+        assert_eq!(src_of("<img data-src=a.png src=c.png>"), Some("c.png"));
+    }
+
+    #[test]
+    fn detects_external_sources() {
+        assert!(is_external("https://example.com/a.png"));
+        assert!(is_external("//cdn.example.com/a.png"));
+        assert!(is_external("data:image/png;base64,AAAA"));
+        assert!(!is_external("C:/images/a.png"));
+        assert!(!is_external("images/a.png"));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("my%20chart.png"), "my chart.png");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
@@ -0,0 +1,328 @@
+//! `{{#include path}}` directives, expanded before the markdown is parsed.
+//!
+//! The syntax follows mdBook's: `{{#include file.md}}` pastes a whole file,
+//! `{{#include src/main.rs:10:40}}` a range of lines (`:10`, `:10:` and
+//! `::40` also work), and `{{#include src/main.rs:setup}}` the lines between
+//! `ANCHOR: setup` and `ANCHOR_END: setup` comments. Paths in the document
+//! itself are relative to its base directory (its own directory unless
+//! `--base-dir` says otherwise), paths in included markdown files to the
+//! including file, and included files may include others in turn.
+//! A backslash in front (`\{{#include ...}}`) keeps the directive as text.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DIRECTIVE: &str = "{{#include ";
+const DIRECTIVE_END: &str = "}}";
+const ANCHOR: &str = "ANCHOR:";
+const ANCHOR_END: &str = "ANCHOR_END:";
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// Expands every include directive in `markdown`, which lives in `dir`.
+/// `source` is the file it was read from, if any, so that it cannot include
+/// itself.
+pub fn expand_includes(
+    markdown: &str,
+    dir: &Path,
+    source: Option<&Path>,
+) -> Result<String, String> {
+    let mut stack: Vec<PathBuf> = source.map(Path::to_path_buf).into_iter().collect();
+    expand(markdown, dir, &mut stack)
+}
+
+/// `stack` holds the markdown files currently being expanded, outermost
+/// first.
+fn expand(markdown: &str, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<String, String> {
+    let mut output = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+
+    while let Some(start) = rest.find(DIRECTIVE) {
+        let Some(length) = rest[start..].find(DIRECTIVE_END) else {
+            break;
+        };
+        let end = start + length + DIRECTIVE_END.len();
+        let argument = rest[start + DIRECTIVE.len()..start + length].trim();
+
+        if argument.contains('\n') {
+            // Not a directive, just braces that happen to follow one another.
+            output.push_str(&rest[..start + DIRECTIVE.len()]);
+            rest = &rest[start + DIRECTIVE.len()..];
+        } else if let Some(before) = rest[..start].strip_suffix('\\') {
+            output.push_str(before);
+            output.push_str(&rest[start..end]);
+            rest = &rest[end..];
+        } else {
+            output.push_str(&rest[..start]);
+            output.push_str(&include(argument, dir, stack)?);
+            rest = &rest[end..];
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn include(argument: &str, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<String, String> {
+    let (file, selector) = split_selector(argument);
+    let including = stack
+        .last()
+        .map_or("<stdin>".to_string(), |path| path.display().to_string());
+
+    let path = fs::canonicalize(dir.join(file))
+        .map_err(|e| format!("{}: cannot include '{}': {}", including, file, e))?;
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("{}: cannot include '{}': {}", including, file, e))?;
+
+    let selected = match selector {
+        None => strip_anchors(&content),
+        Some(selector) => select(&content, selector)
+            .map_err(|e| format!("{}: cannot include '{}': {}", including, file, e))?,
+    };
+
+    if !is_markdown(&path) {
+        return Ok(selected);
+    }
+
+    if stack.contains(&path) {
+        let cycle: Vec<String> = stack
+            .iter()
+            .chain([&path])
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(format!("include cycle: {}", cycle.join(" -> ")));
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    stack.push(path);
+    let expanded = expand(&selected, &dir, stack);
+    stack.pop();
+    expanded
+}
+
+/// Splits `src/main.rs:10:40` into the path and `10:40`. A colon right after
+/// a drive letter belongs to the path.
+fn split_selector(argument: &str) -> (&str, Option<&str>) {
+    let skip = match argument.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => 2,
+        _ => 0,
+    };
+    match argument[skip..].find(':') {
+        Some(colon) => {
+            let (file, selector) = argument.split_at(skip + colon);
+            (file.trim(), Some(selector[1..].trim()))
+        }
+        None => (argument, None),
+    }
+}
+
+/// The lines `selector` asks for: a 1-based line (`10`), an inclusive range
+/// with either end left open (`10:40`, `10:`, `:40`), or an anchor name. A
+/// range may run past the end of the file but must start inside it.
+fn select(content: &str, selector: &str) -> Result<String, String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let line = |number: &str| number.trim().parse::<usize>().ok();
+
+    let range = match selector.split_once(':') {
+        Some((start, end))
+            if (start.is_empty() || line(start).is_some())
+                && (end.is_empty() || line(end).is_some()) =>
+        {
+            Some((line(start).unwrap_or(1), line(end).unwrap_or(lines.len())))
+        }
+        None => line(selector).map(|number| (number, number)),
+        _ => None,
+    };
+
+    let selected = match range {
+        Some((0, _)) => return Err(format!("lines '{}' count from 1", selector)),
+        Some((start, end)) if end < start => {
+            return Err(format!("lines '{}' end before they start", selector))
+        }
+        Some((start, _)) if start > lines.len() => {
+            return Err(format!(
+                "lines '{}' start past the end of the file ({} lines)",
+                selector,
+                lines.len()
+            ))
+        }
+        Some((start, end)) => lines[start - 1..end.min(lines.len())].join("\n"),
+        None => {
+            let start = lines
+                .iter()
+                .position(|line| anchor_name(line, ANCHOR) == Some(selector))
+                .ok_or_else(|| format!("it has no anchor named '{}'", selector))?;
+            let end = lines[start..]
+                .iter()
+                .position(|line| anchor_name(line, ANCHOR_END) == Some(selector))
+                .map_or(lines.len(), |offset| start + offset);
+            lines[start + 1..end].join("\n")
+        }
+    };
+
+    Ok(strip_anchors(&selected))
+}
+
+/// Drops the lines that mark anchors, which are not part of the code.
+fn strip_anchors(content: &str) -> String {
+    let lines: Vec<&str> = content
+        .lines()
+        .filter(|line| {
+            anchor_name(line, ANCHOR).is_none() && anchor_name(line, ANCHOR_END).is_none()
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// The name after `ANCHOR:` (or `ANCHOR_END:`) on a line, if it marks one.
+fn anchor_name<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(marker)?;
+    let rest = rest.trim_start();
+    let length = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .unwrap_or(rest.len());
+    Some(&rest[..length]).filter(|name| !name.is_empty())
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            MARKDOWN_EXTENSIONS
+                .iter()
+                .any(|markdown| extension.eq_ignore_ascii_case(markdown))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::staging::StagingDir;
+
+    const CODE: &str =
+        "fn main() {\n    // ANCHOR: greet\n    println!(\"hi\");\n    // ANCHOR_END: greet\n}\n";
+
+    fn files(files: &[(&str, &str)]) -> StagingDir {
+        let dir = StagingDir::create().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn expand_in(dir: &StagingDir, markdown: &str) -> Result<String, String> {
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        expand_includes(markdown, &dir, None)
+    }
+
+    #[test]
+    fn includes_whole_files_relative_to_the_including_file() {
+        let dir = files(&[
+            ("doc/part.md", "Part with {{#include shared/note.md}}"),
+            ("doc/shared/note.md", "a note"),
+        ]);
+        assert_eq!(
+            expand_in(&dir, "Before\n{{#include doc/part.md}}\nAfter").unwrap(),
+            "Before\nPart with a note\nAfter"
+        );
+    }
+
+    #[test]
+    fn selects_lines_and_anchors() {
+        let dir = files(&[("main.rs", CODE)]);
+        assert_eq!(
+            expand_in(&dir, "{{#include main.rs:1}}").unwrap(),
+            "fn main() {"
+        );
+        assert_eq!(
+            expand_in(&dir, "{{#include main.rs:3:5}}").unwrap(),
+            "    println!(\"hi\");\n}"
+        );
+        assert_eq!(expand_in(&dir, "{{#include main.rs:5:}}").unwrap(), "}");
+        assert_eq!(
+            expand_in(&dir, "{{#include main.rs::1}}").unwrap(),
+            "fn main() {"
+        );
+        assert_eq!(
+            expand_in(&dir, "{{#include main.rs:greet}}").unwrap(),
+            "    println!(\"hi\");"
+        );
+        assert_eq!(expand_in(&dir, "{{#include main.rs:4:99}}").unwrap(), "}");
+    }
+
+    #[test]
+    fn rejects_empty_line_selections() {
+        let dir = files(&[("main.rs", CODE)]);
+        for (selector, message) in [
+            ("5:3", "lines '5:3' end before they start"),
+            ("9", "lines '9' start past the end of the file (5 lines)"),
+            (
+                "9:12",
+                "lines '9:12' start past the end of the file (5 lines)",
+            ),
+            ("0:2", "lines '0:2' count from 1"),
+            ("missing", "it has no anchor named 'missing'"),
+        ] {
+            let error =
+                expand_in(&dir, &format!("{{{{#include main.rs:{}}}}}", selector)).unwrap_err();
+            assert_eq!(
+                error,
+                format!("<stdin>: cannot include 'main.rs': {}", message)
+            );
+        }
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        let dir = files(&[]);
+        let error = expand_in(&dir, "{{#include nowhere.md}}").unwrap_err();
+        assert!(error.starts_with("<stdin>: cannot include 'nowhere.md': "));
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        let dir = files(&[("a.md", "{{#include b.md}}"), ("b.md", "{{#include a.md}}")]);
+        let a = fs::canonicalize(dir.path().join("a.md")).unwrap();
+        let b = fs::canonicalize(dir.path().join("b.md")).unwrap();
+        assert_eq!(
+            expand_in(&dir, "{{#include a.md}}").unwrap_err(),
+            format!(
+                "include cycle: {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+    }
+
+    #[test]
+    fn reports_files_including_themselves() {
+        let dir = files(&[("doc.md", "{{#include doc.md}}")]);
+        let source = fs::canonicalize(dir.path().join("doc.md")).unwrap();
+        let error = expand_includes(
+            "{{#include doc.md}}",
+            source.parent().unwrap(),
+            Some(&source),
+        )
+        .unwrap_err();
+        assert!(error.starts_with("include cycle: "), "{}", error);
+    }
+
+    #[test]
+    fn keeps_escaped_directives() {
+        assert_eq!(
+            expand_includes("\\{{#include a.md}} and {{ x }}", Path::new("."), None).unwrap(),
+            "{{#include a.md}} and {{ x }}"
+        );
+    }
+
+    #[test]
+    fn splits_drive_letters_from_selectors() {
+        assert_eq!(
+            split_selector(r"C:\src\main.rs:10:20"),
+            (r"C:\src\main.rs", Some("10:20"))
+        );
+        assert_eq!(split_selector("main.rs"), ("main.rs", None));
+    }
+}
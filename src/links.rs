@@ -0,0 +1,468 @@
+//! Rewriting and checking the links in a document.
+//!
+//! Relative links to other markdown files are pointed at the PDFs made from
+//! them, or at anchors when they are chapters of the same book. Links to
+//! `#fragment`s only work in the PDF if something in the document carries
+//! that id: a heading, a labelled figure or table, or an element in raw HTML.
+//! Broken ones are reported rather than silently left dead. Since a reader of
+//! the printed page cannot follow a link, its address can be printed too.
+
+use crate::config::LinksConfig;
+use crate::images::{is_external, percent_decode};
+use crate::markdown::{Document, ImageAttrs};
+use pulldown_cmark::{Event, LinkType, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MARKDOWN_EXTENSIONS: [&str; 2] = [".md", ".markdown"];
+
+/// Where links to a markdown file that is part of the run lead.
+pub enum LinkTarget {
+    /// The PDF generated next to it.
+    Pdf,
+    /// A chapter merged into the same PDF: the id of its first heading, and
+    /// the ids its headings got, keyed by the ids they had in the file alone.
+    Chapter {
+        anchor: Option<String>,
+        ids: HashMap<String, String>,
+    },
+}
+
+/// Points relative links to markdown files at the PDF generated from them,
+/// or at the right anchor when the file is a chapter of the same PDF. Links
+/// to files that are not in `targets` follow `links.unconverted`: `pdf`
+/// assumes they are converted separately, `keep` leaves the link to the
+/// markdown file, and `text` drops the link but keeps its text. `source` is
+/// the file the events came from, whose own `#fragment` links are renamed
+/// along with its headings.
+pub fn rewrite_markdown_links<'a>(
+    events: Vec<Event<'a>>,
+    config: &LinksConfig,
+    base_dir: &Path,
+    targets: &HashMap<PathBuf, LinkTarget>,
+    source: Option<&Path>,
+) -> Vec<Event<'a>> {
+    let own_ids = match source.and_then(|source| targets.get(source)) {
+        Some(LinkTarget::Chapter { ids, .. }) => Some(ids),
+        _ => None,
+    };
+
+    let mut output = Vec::with_capacity(events.len());
+    // Whether each open link is being dropped.
+    let mut dropped = Vec::new();
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                let mut drop = false;
+                let dest_url = if let Some(fragment) = dest_url.strip_prefix('#') {
+                    match own_ids {
+                        Some(ids) => chapter_url(None, ids, fragment).into(),
+                        None => dest_url,
+                    }
+                } else if let Some((path, stem, suffix)) = markdown_target(&dest_url) {
+                    let target = fs::canonicalize(base_dir.join(percent_decode(path)))
+                        .ok()
+                        .and_then(|target| targets.get(&target));
+
+                    match (target, config.unconverted.as_str()) {
+                        (Some(LinkTarget::Chapter { anchor, ids }), _) => {
+                            let fragment =
+                                suffix.split_once('#').map_or("", |(_, fragment)| fragment);
+                            chapter_url(anchor.as_deref(), ids, fragment).into()
+                        }
+                        _ if !config.rewrite_markdown => dest_url,
+                        (Some(LinkTarget::Pdf), _) => format!("{}.pdf{}", stem, suffix).into(),
+                        (None, "keep") => dest_url,
+                        (None, "text") => {
+                            drop = true;
+                            dest_url
+                        }
+                        (None, _) => format!("{}.pdf{}", stem, suffix).into(),
+                    }
+                } else {
+                    dest_url
+                };
+
+                dropped.push(drop);
+                if !drop {
+                    output.push(Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    }));
+                }
+            }
+            Event::End(TagEnd::Link) => {
+                if !dropped.pop().unwrap_or(false) {
+                    output.push(Event::End(TagEnd::Link));
+                }
+            }
+            event => output.push(event),
+        }
+    }
+
+    output
+}
+
+/// The internal link for `#fragment` in a merged chapter, or for the chapter
+/// itself when there is no fragment.
+fn chapter_url(anchor: Option<&str>, ids: &HashMap<String, String>, fragment: &str) -> String {
+    if fragment.is_empty() {
+        return format!("#{}", anchor.unwrap_or_default());
+    }
+    let fragment = percent_decode(fragment);
+    format!("#{}", ids.get(&fragment).unwrap_or(&fragment))
+}
+
+/// Splits `guide/api.md#auth` into the file path, the path without its
+/// extension and the `#auth` suffix, if it is a relative link to markdown.
+pub fn markdown_target(url: &str) -> Option<(&str, &str, &str)> {
+    if url.starts_with(['#', '/']) || is_external(url) {
+        return None;
+    }
+
+    let (path, suffix) = url.split_at(url.find(['#', '?']).unwrap_or(url.len()));
+    let lower = path.to_ascii_lowercase();
+    let extension = MARKDOWN_EXTENSIONS
+        .iter()
+        .find(|extension| lower.ends_with(*extension))?;
+
+    Some((path, &path[..path.len() - extension.len()], suffix))
+}
+
+/// Prints the address of every external link, as `links.show_url` asks:
+/// in parentheses after the link text (`inline`), or as a numbered note
+/// listed at the end of the document (`footnote`). Links whose text already
+/// reads as the address are left alone.
+pub fn print_link_urls<'a>(events: Vec<Event<'a>>, config: &LinksConfig) -> Vec<Event<'a>> {
+    let as_notes = match config.show_url.as_str() {
+        "inline" => false,
+        "footnote" => true,
+        _ => return events,
+    };
+
+    let mut output = Vec::with_capacity(events.len());
+    // Noted addresses, numbered in order of first appearance.
+    let mut notes: Vec<String> = Vec::new();
+    // The address and text so far of each open link.
+    let mut open: Vec<(String, String)> = Vec::new();
+
+    for event in events {
+        match &event {
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                open.push((dest_url.to_string(), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, link_text)) = open.last_mut() {
+                    link_text.push_str(text);
+                }
+            }
+            _ => {}
+        }
+
+        let closes_link = event == Event::End(TagEnd::Link);
+        output.push(event);
+        if !closes_link {
+            continue;
+        }
+        let Some((url, text)) = open.pop() else {
+            continue;
+        };
+        if !is_external(&url) || url.trim_start_matches("mailto:") == text.trim() {
+            continue;
+        }
+
+        if as_notes {
+            let number = match notes.iter().position(|note| *note == url) {
+                Some(index) => index + 1,
+                None => {
+                    notes.push(url);
+                    notes.len()
+                }
+            };
+            output.extend([
+                Event::Start(Tag::Link {
+                    link_type: LinkType::Inline,
+                    dest_url: format!("#{}", note_anchor(number)).into(),
+                    title: "".into(),
+                    id: "".into(),
+                }),
+                Event::Text(format!("[{}]", number).into()),
+                Event::End(TagEnd::Link),
+            ]);
+        } else {
+            output.push(Event::Text(format!(" ({})", url).into()));
+        }
+    }
+
+    if !notes.is_empty() {
+        output.push(Event::Rule);
+        output.push(Event::Html("<div class=\"link-notes\">\n".into()));
+        for (index, url) in notes.into_iter().enumerate() {
+            output.extend([
+                Event::Start(Tag::Paragraph),
+                Event::InlineHtml(
+                    format!("<span id=\"{}\"></span>", note_anchor(index + 1)).into(),
+                ),
+                Event::Text(format!("[{}] ", index + 1).into()),
+                Event::Start(Tag::Link {
+                    link_type: LinkType::Autolink,
+                    dest_url: url.clone().into(),
+                    title: "".into(),
+                    id: "".into(),
+                }),
+                Event::Text(url.into()),
+                Event::End(TagEnd::Link),
+                Event::End(TagEnd::Paragraph),
+            ]);
+        }
+        output.push(Event::Html("</div>\n".into()));
+    }
+
+    output
+}
+
+fn note_anchor(number: usize) -> String {
+    format!("link-note-{}", number)
+}
+
+/// Warns about every `#fragment` link that points at no anchor.
+pub fn check_internal_links(document: &Document) {
+    let anchors = anchors(document);
+    let mut reported = HashSet::new();
+
+    for event in &document.events {
+        let Event::Start(Tag::Link { dest_url, .. }) = event else {
+            continue;
+        };
+        let Some(fragment) = dest_url.strip_prefix('#') else {
+            continue;
+        };
+        if fragment.is_empty() || anchors.contains(&percent_decode(fragment)) {
+            continue;
+        }
+        if reported.insert(fragment.to_string()) {
+            eprintln!(
+                "Warning: broken internal link '#{}' (no heading or label has that id)",
+                fragment
+            );
+        }
+    }
+}
+
+fn anchors(document: &Document) -> HashSet<String> {
+    let mut anchors: HashSet<String> = document
+        .table_captions
+        .iter()
+        .flatten()
+        .map(|caption| caption.id.clone())
+        .collect();
+
+    let mut after_image = false;
+    for event in &document.events {
+        match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => {
+                anchors.insert(id.to_string());
+            }
+            Event::Text(text) if after_image => {
+                if let Some((ImageAttrs { id: Some(id), .. }, _)) = ImageAttrs::split_from(text) {
+                    anchors.insert(id);
+                }
+            }
+            Event::Html(html) | Event::InlineHtml(html) => anchors.extend(html_ids(html)),
+            _ => {}
+        }
+        after_image = *event == Event::End(TagEnd::Image);
+    }
+
+    anchors
+}
+
+/// Values of `id` and `name` attributes in a chunk of raw HTML.
+fn html_ids(html: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+
+    for attribute in [" id=", " name="] {
+        let mut rest = html;
+        while let Some(offset) = rest.find(attribute) {
+            rest = &rest[offset + attribute.len()..];
+            let value = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => rest[1..].split(quote).next(),
+                _ => rest.split(|c: char| c.is_whitespace() || c == '>').next(),
+            };
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                ids.push(value.to_string());
+            }
+        }
+    }
+
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use crate::markdown::{parse_document, parse_events};
+    use crate::staging::StagingDir;
+
+    #[test]
+    fn recognises_relative_markdown_links() {
+        assert_eq!(
+            markdown_target("guide/api.md#auth"),
+            Some(("guide/api.md", "guide/api", "#auth"))
+        );
+        assert_eq!(
+            markdown_target("Notes.MARKDOWN?x=1"),
+            Some(("Notes.MARKDOWN", "Notes", "?x=1"))
+        );
+        assert_eq!(markdown_target("https://example.com/a.md"), None);
+        assert_eq!(markdown_target("/abs/a.md"), None);
+        assert_eq!(markdown_target("#a.md"), None);
+        assert_eq!(markdown_target("image.png"), None);
+    }
+
+    /// The destination of every link left in `markdown` after rewriting, or
+    /// its text if the link was dropped.
+    fn rewritten(markdown: &str, unconverted: &str, chapter: bool) -> Vec<String> {
+        let staging = StagingDir::create().unwrap();
+        let dir = fs::canonicalize(staging.path()).unwrap();
+        for name in ["guide.md", "other.md"] {
+            fs::write(dir.join(name), "# Title\n").unwrap();
+        }
+        let target = if chapter {
+            LinkTarget::Chapter {
+                anchor: Some("guide".to_string()),
+                ids: HashMap::from([("setup".to_string(), "setup-1".to_string())]),
+            }
+        } else {
+            LinkTarget::Pdf
+        };
+        let targets = HashMap::from([(dir.join("guide.md"), target)]);
+        let config = LinksConfig {
+            unconverted: unconverted.to_string(),
+            ..LinksConfig::default()
+        };
+
+        let events = rewrite_markdown_links(parse_events(markdown), &config, &dir, &targets, None);
+        let mut links = Vec::new();
+        let mut in_link = false;
+        for event in events {
+            match event {
+                Event::Start(Tag::Link { dest_url, .. }) => {
+                    links.push(dest_url.to_string());
+                    in_link = true;
+                }
+                Event::End(TagEnd::Link) => in_link = false,
+                Event::Text(text) if !in_link && text.starts_with("dropped") => {
+                    links.push(text.to_string())
+                }
+                _ => {}
+            }
+        }
+        links
+    }
+
+    #[test]
+    fn points_markdown_links_at_pdfs() {
+        let markdown = "[a](guide.md#setup) [dropped](other.md) [c](https://x.org/a.md)\n";
+        assert_eq!(
+            rewritten(markdown, "pdf", false),
+            ["guide.pdf#setup", "other.pdf", "https://x.org/a.md"]
+        );
+        assert_eq!(
+            rewritten(markdown, "keep", false),
+            ["guide.pdf#setup", "other.md", "https://x.org/a.md"]
+        );
+        assert_eq!(
+            rewritten(markdown, "text", false),
+            ["guide.pdf#setup", "dropped", "https://x.org/a.md"]
+        );
+    }
+
+    #[test]
+    fn points_chapter_links_at_anchors() {
+        assert_eq!(
+            rewritten(
+                "[a](guide.md#setup) [b](guide.md) [c](guide.md#other)\n",
+                "pdf",
+                true
+            ),
+            ["#setup-1", "#guide", "#other"]
+        );
+    }
+
+    #[test]
+    fn collects_anchors_from_headings_labels_and_html() {
+        let markdown = "# Intro\n\n![Chart](a.png){#fig:chart}\n\n<a name=\"old\"></a>\n\n<span id='raw'>x</span>\n";
+        let document = parse_document(markdown, &get_default_config(), None);
+        let anchors = anchors(&document);
+        for id in ["intro", "fig:chart", "old", "raw"] {
+            assert!(anchors.contains(id), "{} missing from {:?}", id, anchors);
+        }
+    }
+
+    #[test]
+    fn reads_ids_from_raw_html() {
+        assert_eq!(
+            html_ids("<div id=\"a\" class=x><a name='b'></a><p id=c>"),
+            ["a", "c", "b"]
+        );
+        assert!(html_ids("<div data-id=\"no\">").is_empty());
+    }
+
+    fn printed(markdown: &str, show_url: &str) -> String {
+        let config = LinksConfig {
+            show_url: show_url.to_string(),
+            ..LinksConfig::default()
+        };
+        let mut html = String::new();
+        let events = print_link_urls(parse_events(markdown), &config);
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        html
+    }
+
+    #[test]
+    fn prints_link_addresses_inline() {
+        let markdown = "[Site](https://example.com) <https://example.com> [local](#intro)\n";
+        assert_eq!(
+            printed(markdown, "inline"),
+            "<p><a href=\"https://example.com\">Site</a> (https://example.com) \
+             <a href=\"https://example.com\">https://example.com</a> \
+             <a href=\"#intro\">local</a></p>\n"
+        );
+        assert_eq!(
+            printed(markdown, "none"),
+            "<p><a href=\"https://example.com\">Site</a> \
+             <a href=\"https://example.com\">https://example.com</a> \
+             <a href=\"#intro\">local</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn prints_link_addresses_as_notes() {
+        let html = printed(
+            "[One](https://a.org) [Two](https://b.org) [Again](https://a.org)\n",
+            "footnote",
+        );
+        assert!(
+            html.starts_with(
+                "<p><a href=\"https://a.org\">One</a><a href=\"#link-note-1\">[1]</a> \
+                 <a href=\"https://b.org\">Two</a><a href=\"#link-note-2\">[2]</a> \
+                 <a href=\"https://a.org\">Again</a><a href=\"#link-note-1\">[1]</a></p>\n<hr />\n"
+            ),
+            "{}",
+            html
+        );
+        assert!(html.contains("<p><span id=\"link-note-2\"></span>[2] <a href=\"https://b.org\">https://b.org</a></p>"), "{}", html);
+        assert_eq!(html.matches("link-note-").count(), 5, "{}", html);
+    }
+}
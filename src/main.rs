@@ -12,25 +12,55 @@ const CONFIG_FILE: &str = "config.json";
 const DEFAULT_CONFIG: &str = r##"{
     "page": {
         "margin": "1in",
-        "first_page_top_margin": "2in"
+        "first_page_top_margin": "2in",
+        "columns": {
+            "count": 1,
+            "gap": "1em",
+            "rule": "none",
+            "span_headings": 2
+        }
     },
     "fonts": {
         "body_family": "Times New Roman",
         "body_size": "12pt",
         "code_family": "Courier New",
         "inline_code_size": "12pt",
-        "block_code_size": "9pt"
+        "block_code_size": "9pt",
+        "files": []
     },
     "headings": {
         "h1_size": "24pt",
         "h1_align": "center",
         "h1_page_break_before": true,
+        "h1_page_break_after": false,
+        "h1_keep_with_next": true,
         "h2_size": "16pt",
         "h2_page_break_before": true,
+        "h2_page_break_after": false,
+        "h2_keep_with_next": true,
         "h3_size": "14pt",
+        "h3_page_break_before": false,
+        "h3_page_break_after": false,
+        "h3_keep_with_next": true,
         "h4_size": "13pt",
+        "h4_page_break_before": false,
+        "h4_page_break_after": false,
+        "h4_keep_with_next": true,
         "h5_size": "12pt",
-        "h6_size": "12pt"
+        "h5_page_break_before": false,
+        "h5_page_break_after": false,
+        "h5_keep_with_next": true,
+        "h6_size": "12pt",
+        "h6_page_break_before": false,
+        "h6_page_break_after": false,
+        "h6_keep_with_next": true,
+        "numbering": {
+            "enabled": false,
+            "h1_chapter": false,
+            "max_level": 3,
+            "format": "1.1.1",
+            "separator": " "
+        }
     },
     "spacing": {
         "line_height": "1.25",
@@ -48,7 +78,15 @@ const DEFAULT_CONFIG: &str = r##"{
         "padding": "0",
         "margin": "6pt 0",
         "word_wrap": true,
-        "page_break_inside": false
+        "page_break_inside": false,
+        "line_numbers": false,
+        "line_number_color": "#999",
+        "highlight_background": "#fff5b1",
+        "title_background": "#e8e8e8",
+        "title_color": "#333",
+        "diff_added_background": "#e6ffed",
+        "diff_removed_background": "#ffeef0",
+        "diff_header_background": "#f1f8ff"
     },
     "syntax_highlighting": {
         "theme": "monokai",
@@ -60,11 +98,113 @@ const DEFAULT_CONFIG: &str = r##"{
         "caption_size": "10pt",
         "caption_style": "italic",
         "caption_align": "center",
-        "caption_color": "#666"
+        "caption_color": "#666",
+        "embed": false,
+        "strict": false,
+        "search_paths": [],
+        "max_width": "100%",
+        "max_height": "80vh"
+    },
+    "captions": {
+        "numbering": true,
+        "figure_label": "Figure",
+        "table_label": "Table",
+        "section_label": "Section",
+        "separator": ": ",
+        "table_position": "above"
+    },
+    "links": {
+        "rewrite_markdown": true,
+        "unconverted": "pdf",
+        "color": "#0000ee",
+        "underline": true,
+        "show_url": "none"
+    },
+    "toc": {
+        "title": "Contents",
+        "max_level": 3
+    },
+    "book": {
+        "files": [],
+        "title": "",
+        "toc": true,
+        "chapter_page_break": true
+    },
+    "variables": {
+        "values": {},
+        "in_code": false
+    },
+    "profiles": {
+        "active": [],
+        "overrides": {}
+    },
+    "citations": {
+        "bibliography": "",
+        "style": "numeric",
+        "title": "References"
+    },
+    "tables": {
+        "border": "1px solid #000",
+        "padding": "6pt",
+        "compact": false,
+        "font_size": "100%",
+        "align": "left",
+        "markdown_alignment": true,
+        "header_background": "#f5f5f5",
+        "header_color": "#000000",
+        "repeat_header": true,
+        "zebra": false,
+        "zebra_background": "#fafafa",
+        "wide_columns": 6,
+        "wide": "wrap",
+        "shrink_font_size": "80%"
+    },
+    "blockquote": {
+        "border_width": "3px",
+        "border_color": "#ccc",
+        "color": "#666",
+        "padding": "12pt",
+        "italic": false
+    },
+    "lists": {
+        "indent": "24pt",
+        "item_spacing": "6pt",
+        "bullets": ["disc", "circle", "square"],
+        "numbering": ["decimal"],
+        "legal": false
     },
     "title_page": {
         "extract_header": true,
-        "first_paragraph_size": "16pt"
+        "first_paragraph_size": "16pt",
+        "cover": {
+            "enabled": false,
+            "title": "",
+            "subtitle": "",
+            "authors": [],
+            "date": "",
+            "version": "",
+            "logo": "",
+            "abstract": "",
+            "notice": "",
+            "align": "center",
+            "title_size": "32pt",
+            "subtitle_size": "18pt",
+            "logo_width": "2in",
+            "logo_position": "top"
+        }
+    },
+    "print": {
+        "backend": "auto",
+        "paper_size": "A4",
+        "landscape": false,
+        "print_background": true,
+        "outline": true,
+        "header_template": "",
+        "footer_template": "",
+        "settle_ms": 250,
+        "timeout_secs": 120,
+        "retries": 0,
+        "retry_backoff_ms": 1000
     }
 }"##;
 
@@ -0,0 +1,683 @@
+use crate::bibliography::Bibliography;
+use crate::citations::cite;
+use crate::code_blocks::{code_block_html, CodeAttrs};
+use crate::config::Config;
+use crate::figures::{number_figures, TableCaption};
+use crate::images::{rewrite_img_tags, ImageResolver};
+use crate::links::print_link_urls;
+use crate::sections::{assign_heading_ids, insert_toc, number_headings};
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+
+pub fn extract_header(markdown: &str) -> (String, String) {
+    let lines: Vec<&str> = markdown.lines().collect();
+
+    if lines.len() >= 2 {
+        let first_line = lines[0].trim();
+        let second_line = lines[1].trim();
+
+        if second_line.chars().all(|c| c == '-') && second_line.len() >= 3 {
+            let header_text = first_line.to_string();
+            let remaining_markdown = lines[2..].join("\n");
+            return (header_text, remaining_markdown);
+        }
+    }
+
+    (String::new(), markdown.to_string())
+}
+
+/// Tracks fenced code blocks while markdown is scanned line by line, for the
+/// text-level passes that must leave code alone.
+#[derive(Default)]
+pub struct CodeFences {
+    /// Character and length of the fence of the block we are in.
+    open: Option<(char, usize)>,
+}
+
+impl CodeFences {
+    /// Whether `line`, the next line of the markdown, is part of a fenced
+    /// code block, fences included.
+    pub fn is_code(&mut self, line: &str) -> bool {
+        let marker = fence_marker(line);
+        match (self.open, marker) {
+            (Some((c, length)), Some((marker_c, marker_length, info)))
+                if marker_c == c && marker_length >= length && info.trim().is_empty() =>
+            {
+                self.open = None;
+            }
+            (Some(_), _) => {}
+            (None, Some((c, length, _))) => self.open = Some((c, length)),
+            (None, None) => return false,
+        }
+        true
+    }
+}
+
+/// The fence character, its length and the info string, if `line` opens or
+/// closes a fenced code block.
+fn fence_marker(line: &str) -> Option<(char, usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let c = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let length = trimmed.len() - trimmed.trim_start_matches(c).len();
+    let info = &trimmed[length..];
+    // Backtick fences cannot have backticks in their info string.
+    (length >= 3 && !(c == '`' && info.contains('`'))).then_some((c, length, info))
+}
+
+/// Raw HTML that starts a new page, understood by the native backend too.
+pub const PAGE_BREAK: &str = "<div class=\"page-break\"></div>\n";
+
+/// Markdown extensions shared by the HTML and native rendering paths.
+pub fn parser_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    options
+}
+
+/// Label, size and placement given in braces right after an image, as in
+/// `![alt](pic.png){#fig:arch width=50% align=right}` or
+/// `![alt](pic.png){=300x200}`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageAttrs {
+    pub id: Option<String>,
+    pub width: Option<String>,
+    pub height: Option<String>,
+    pub align: Option<String>,
+}
+
+impl ImageAttrs {
+    /// Parses a `{...}` block at the start of `text`, returning the attributes
+    /// and the text after it. Anything unrecognised means the braces were
+    /// just text, so `None` is returned.
+    pub fn split_from(text: &str) -> Option<(ImageAttrs, &str)> {
+        let (inner, rest) = text.strip_prefix('{')?.split_once('}')?;
+        let mut attrs = ImageAttrs::default();
+
+        for token in inner.split_whitespace() {
+            if let Some(id) = token.strip_prefix('#') {
+                attrs.id = Some(id.to_string());
+                continue;
+            }
+            if let Some(size) = token.strip_prefix('=') {
+                let (width, height) = size.split_once('x')?;
+                if !width.is_empty() {
+                    attrs.width = Some(css_length(width)?);
+                }
+                if !height.is_empty() {
+                    attrs.height = Some(css_length(height)?);
+                }
+                continue;
+            }
+
+            let (key, value) = token.split_once('=')?;
+            let value = value.trim_matches(['"', '\'']);
+            match key {
+                "width" => attrs.width = Some(css_length(value)?),
+                "height" => attrs.height = Some(css_length(value)?),
+                "align" if matches!(value, "left" | "center" | "right") => {
+                    attrs.align = Some(value.to_string())
+                }
+                _ => return None,
+            }
+        }
+
+        Some((attrs, rest))
+    }
+
+    pub fn style(&self) -> String {
+        let mut style = String::new();
+        if let Some(width) = &self.width {
+            style.push_str(&format!("width: {};", width));
+        }
+        if let Some(height) = &self.height {
+            style.push_str(&format!(" height: {};", height));
+        }
+        style.trim_start().to_string()
+    }
+}
+
+/// Writes the attributes back in `{...}` form, as `split_from` reads them.
+impl fmt::Display for ImageAttrs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens: Vec<String> = [
+            self.id.as_ref().map(|id| format!("#{}", id)),
+            self.width.as_ref().map(|width| format!("width={}", width)),
+            self.height
+                .as_ref()
+                .map(|height| format!("height={}", height)),
+            self.align.as_ref().map(|align| format!("align={}", align)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{{{}}}", tokens.join(" "))
+    }
+}
+
+/// A bare number is in pixels, like HTML's `width` attribute.
+fn css_length(value: &str) -> Option<String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    number.parse::<f64>().ok()?;
+
+    match unit {
+        "" => Some(format!("{}px", number)),
+        "%" | "px" | "pt" | "pc" | "in" | "cm" | "mm" | "em" | "rem" | "vw" | "vh" => {
+            Some(value.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Rewrites the `![alt](pic.png =300x200)` shorthand, which CommonMark
+/// doesn't recognise as an image, to `![alt](pic.png){=300x200}`. Code spans
+/// and code blocks are left alone.
+pub fn expand_image_sizes(markdown: &str) -> String {
+    let code: Vec<Range<usize>> = Parser::new_ext(markdown, parser_options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None,
+        })
+        .collect();
+
+    let mut output = String::with_capacity(markdown.len());
+    let mut copied = 0;
+    let mut search = 0;
+
+    while let Some(offset) = markdown[search..].find("](") {
+        let open = search + offset + 2;
+        search = open;
+        if code.iter().any(|range| range.contains(&open)) {
+            continue;
+        }
+
+        let Some(close) = markdown[open..].find([')', '\n']).map(|i| open + i) else {
+            break;
+        };
+        let Some((dest, size)) = markdown[open..close].rsplit_once(" =") else {
+            continue;
+        };
+        if markdown.as_bytes()[close] != b')' || !is_size(size) {
+            continue;
+        }
+
+        output.push_str(&markdown[copied..open]);
+        output.push_str(dest.trim_end());
+        output.push_str("){=");
+        output.push_str(size);
+        output.push('}');
+        copied = close + 1;
+        search = copied;
+    }
+
+    output.push_str(&markdown[copied..]);
+    output
+}
+
+fn is_size(value: &str) -> bool {
+    value.split_once('x').is_some_and(|(width, height)| {
+        !(width.is_empty() && height.is_empty())
+            && width.chars().all(|c| c.is_ascii_digit())
+            && height.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+/// Parsed markdown with sections, figures and tables numbered and references
+/// resolved, shared by the HTML and native rendering paths.
+pub struct Document<'a> {
+    pub events: Vec<Event<'a>>,
+    /// Caption of every table in document order, if it has one.
+    pub table_captions: Vec<Option<TableCaption>>,
+}
+
+impl<'a> Document<'a> {
+    /// Prints link addresses, resolves citations, numbers sections, figures
+    /// and tables in events whose headings already have ids, and fills in
+    /// tables of contents.
+    pub fn new(
+        events: Vec<Event<'a>>,
+        config: &Config,
+        bibliography: Option<&Bibliography>,
+    ) -> Self {
+        let events = print_link_urls(events, &config.links);
+        let events = match bibliography {
+            Some(bibliography) => cite(events, bibliography, &config.citations),
+            None => events,
+        };
+        let (events, sections) = number_headings(events, config);
+        let events = insert_toc(events, &sections, config);
+        let (events, table_captions) = number_figures(events, &config.captions, &sections);
+        Document {
+            events,
+            table_captions,
+        }
+    }
+}
+
+pub fn parse_events(markdown: &str) -> Vec<Event<'_>> {
+    Parser::new_ext(markdown, parser_options()).collect()
+}
+
+pub fn parse_document<'a>(
+    markdown: &'a str,
+    config: &Config,
+    bibliography: Option<&Bibliography>,
+) -> Document<'a> {
+    let mut events = parse_events(markdown);
+    assign_heading_ids(&mut events, &mut HashSet::new());
+    Document::new(events, config, bibliography)
+}
+
+/// An image read out of the event stream, rendered once its paragraph is
+/// known to be a figure or not.
+struct Picture {
+    src: String,
+    alt: String,
+    title: String,
+    attrs: ImageAttrs,
+}
+
+/// Converts a document to an HTML fragment, pointing every image (markdown
+/// or raw `<img>`) at the file `images` resolves it to. Images standing on
+/// their own become `<figure>`s with their alt text as caption, several in
+/// one paragraph become an image row, and captioned tables are wrapped in a
+/// `<figure>` as well. Wide tables are wrapped in a `wide-table` div for the
+/// stylesheet to shrink or turn.
+pub fn markdown_to_html(document: &Document, config: &Config, images: &ImageResolver) -> String {
+    let mut source = document.events.iter().cloned().peekable();
+    let mut events = Vec::new();
+    let mut pictures = HashMap::new();
+    let mut tables = document.table_captions.iter();
+    let mut table_caption = None;
+    let mut wide_table = false;
+    let caption_above = config.captions.table_caption_above();
+
+    while let Some(event) = source.next() {
+        match event {
+            Event::Start(Tag::Image {
+                dest_url, title, ..
+            }) => {
+                let mut alt = String::new();
+                for event in source.by_ref() {
+                    match event {
+                        Event::End(TagEnd::Image) => break,
+                        Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                        _ => {}
+                    }
+                }
+
+                // Attributes arrive as the text right after the image.
+                let mut attrs = ImageAttrs::default();
+                let mut rest = None;
+                if let Some(Event::Text(text)) = source.peek() {
+                    if let Some((parsed, after)) = ImageAttrs::split_from(text) {
+                        attrs = parsed;
+                        rest = Some(after.to_string());
+                    }
+                }
+                if rest.is_some() {
+                    source.next();
+                }
+
+                pictures.insert(
+                    events.len(),
+                    Picture {
+                        src: images.html_src(&dest_url),
+                        alt,
+                        title: title.to_string(),
+                        attrs,
+                    },
+                );
+                events.push(Event::InlineHtml("".into()));
+                if let Some(rest) = rest.filter(|rest| !rest.is_empty()) {
+                    events.push(Event::Text(rest.into()));
+                }
+            }
+            Event::Start(Tag::Table(alignments)) => {
+                table_caption = tables.next().cloned().flatten();
+                wide_table = config.tables.is_wide(alignments.len());
+                if wide_table {
+                    events.push(Event::Html("<div class=\"wide-table\">\n".into()));
+                }
+                if let Some(caption) = &table_caption {
+                    let mut html = format!(
+                        "<figure class=\"table\" id=\"{}\">\n",
+                        escape_html(&caption.id)
+                    );
+                    if caption_above {
+                        html.push_str(&figcaption(&caption.text));
+                    }
+                    events.push(Event::Html(html.into()));
+                }
+                events.push(Event::Start(Tag::Table(alignments)));
+            }
+            Event::End(TagEnd::Table) => {
+                events.push(Event::End(TagEnd::Table));
+                if let Some(caption) = table_caption.take() {
+                    let mut html = String::new();
+                    if !caption_above {
+                        html.push_str(&figcaption(&caption.text));
+                    }
+                    html.push_str("</figure>\n");
+                    events.push(Event::Html(html.into()));
+                }
+                if std::mem::take(&mut wide_table) {
+                    events.push(Event::Html("</div>\n".into()));
+                }
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let info = match &kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                let attrs = CodeAttrs::parse(&info, &config.code_blocks);
+                if attrs.title.is_empty() && !attrs.has_lines() {
+                    events.push(Event::Start(Tag::CodeBlock(kind)));
+                    continue;
+                }
+                let mut code = String::new();
+                for event in source.by_ref() {
+                    match event {
+                        Event::Text(text) => code.push_str(&text),
+                        Event::End(TagEnd::CodeBlock) => break,
+                        // pulldown-cmark gives code blocks only text, and
+                        // the passes before this one leave them alone.
+                        other => unreachable!("{:?} in a code block", other),
+                    }
+                }
+                events.push(Event::Html(code_block_html(&code, &attrs).into()));
+            }
+            Event::Html(html) => events.push(Event::Html(rewrite_img_tags(&html, images).into())),
+            Event::InlineHtml(html) => {
+                events.push(Event::InlineHtml(rewrite_img_tags(&html, images).into()))
+            }
+            event => events.push(event),
+        }
+    }
+
+    let figures = mark_figures(&mut events, &pictures);
+    let show_captions = config.images.show_captions;
+    for (index, picture) in pictures {
+        let html = if figures.contains(&index) {
+            figure_tag(&picture, show_captions)
+        } else {
+            img_tag(&picture)
+        };
+        events[index] = Event::InlineHtml(html.into());
+    }
+
+    let mut html_output = String::new();
+    html::push_html(&mut html_output, events.into_iter());
+
+    html_output
+}
+
+fn img_tag(picture: &Picture) -> String {
+    let mut tag = format!(
+        r#"<img src="{}" alt="{}""#,
+        escape_html(&picture.src),
+        escape_html(&picture.alt)
+    );
+    if !picture.title.is_empty() {
+        tag.push_str(&format!(r#" title="{}""#, escape_html(&picture.title)));
+    }
+    if let Some(id) = &picture.attrs.id {
+        tag.push_str(&format!(r#" id="{}""#, escape_html(id)));
+    }
+    if let Some(align) = &picture.attrs.align {
+        tag.push_str(&format!(r#" class="align-{}""#, align));
+    }
+    let style = picture.attrs.style();
+    if !style.is_empty() {
+        tag.push_str(&format!(r#" style="{}""#, style));
+    }
+    tag.push_str(" />");
+    tag
+}
+
+/// An image standing on its own, wrapped in a `<figure>` with its alt text as
+/// caption. The figure takes the image's id, alignment and percentage width,
+/// so a floated image keeps its caption underneath it.
+fn figure_tag(picture: &Picture, show_captions: bool) -> String {
+    let caption = show_captions && !picture.alt.is_empty();
+    if !caption && picture.attrs.id.is_none() {
+        return img_tag(picture);
+    }
+
+    let mut figure = String::from("<figure");
+    if let Some(id) = &picture.attrs.id {
+        figure.push_str(&format!(r#" id="{}""#, escape_html(id)));
+    }
+    if let Some(align) = &picture.attrs.align {
+        figure.push_str(&format!(r#" class="align-{}""#, align));
+    }
+
+    let mut attrs = ImageAttrs {
+        id: None,
+        align: None,
+        ..picture.attrs.clone()
+    };
+    if let Some(width) = attrs.width.take_if(|width| width.ends_with('%')) {
+        figure.push_str(&format!(r#" style="width: {};""#, width));
+        attrs.width = Some("100%".to_string());
+    }
+    figure.push('>');
+
+    figure.push_str(&img_tag(&Picture {
+        src: picture.src.clone(),
+        alt: picture.alt.clone(),
+        title: picture.title.clone(),
+        attrs,
+    }));
+    if caption {
+        figure.push_str(&figcaption(&picture.alt));
+    }
+    figure.push_str("</figure>");
+    figure
+}
+
+fn figcaption(text: &str) -> String {
+    format!("<figcaption>{}</figcaption>", escape_html(text))
+}
+
+/// Paragraphs made of nothing but images (and whitespace) become figures: a
+/// single one is unwrapped from its `<p>`, several are laid out side by side.
+/// Returns the event indices of those images.
+fn mark_figures(events: &mut [Event], pictures: &HashMap<usize, Picture>) -> HashSet<usize> {
+    let mut figures = HashSet::new();
+    let mut start = None;
+
+    for index in 0..events.len() {
+        match &events[index] {
+            Event::Start(Tag::Paragraph) => start = Some(index),
+            Event::End(TagEnd::Paragraph) => {
+                let Some(start) = start.take() else {
+                    continue;
+                };
+                let contents = start + 1..index;
+                let images: Vec<usize> = contents
+                    .clone()
+                    .filter(|i| pictures.contains_key(i))
+                    .collect();
+                let only_images = contents.clone().all(|i| {
+                    pictures.contains_key(&i)
+                        || match &events[i] {
+                            Event::Text(text) => text.trim().is_empty(),
+                            Event::SoftBreak => true,
+                            _ => false,
+                        }
+                });
+                if images.is_empty() || !only_images {
+                    continue;
+                }
+
+                if images.len() >= 2 {
+                    events[start] = Event::Html("<div class=\"image-row\">".into());
+                    events[index] = Event::Html("</div>\n".into());
+                } else {
+                    events[start] = Event::Html("".into());
+                    events[index] = Event::Html("\n".into());
+                }
+                figures.extend(images);
+            }
+            _ => {}
+        }
+    }
+
+    figures
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use std::path::Path;
+
+    fn to_html(markdown: &str) -> String {
+        let config = get_default_config();
+        let images = ImageResolver::new(&config.images, Path::new("."));
+        markdown_to_html(&parse_document(markdown, &config, None), &config, &images)
+    }
+
+    #[test]
+    fn parses_image_attributes() {
+        let (attrs, rest) =
+            ImageAttrs::split_from("{#fig:arch width=50% align=right} after").unwrap();
+        assert_eq!(attrs.id.as_deref(), Some("fig:arch"));
+        assert_eq!(attrs.width.as_deref(), Some("50%"));
+        assert_eq!(attrs.height, None);
+        assert_eq!(attrs.align.as_deref(), Some("right"));
+        assert_eq!(rest, " after");
+        assert_eq!(attrs.to_string(), "{#fig:arch width=50% align=right}");
+
+        let (attrs, _) = ImageAttrs::split_from("{=300x}").unwrap();
+        assert_eq!(attrs.width.as_deref(), Some("300px"));
+        assert_eq!(attrs.style(), "width: 300px;");
+        let (attrs, _) = ImageAttrs::split_from("{height='2.5cm'}").unwrap();
+        assert_eq!(attrs.style(), "height: 2.5cm;");
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        assert!(ImageAttrs::split_from("{not attributes}").is_none());
+        assert!(ImageAttrs::split_from("{width=huge}").is_none());
+        assert!(ImageAttrs::split_from("{align=middle}").is_none());
+        assert!(ImageAttrs::split_from("no braces").is_none());
+    }
+
+    #[test]
+    fn expands_the_size_shorthand() {
+        assert_eq!(
+            expand_image_sizes("![a](pic.png =300x200) and ![b](b.png =x40)\n"),
+            "![a](pic.png){=300x200} and ![b](b.png){=x40}\n"
+        );
+        // Only pixel sizes have a shorthand.
+        let percent = "![b](b.png =50%x)\n";
+        assert_eq!(expand_image_sizes(percent), percent);
+        let code = "`![a](pic.png =300x200)`\n\n```\n![a](pic.png =300x200)\n```\n";
+        assert_eq!(expand_image_sizes(code), code);
+    }
+
+    #[test]
+    fn renders_sized_and_aligned_images() {
+        let html = to_html("Inline ![icon](icon.png){width=16} here.\n");
+        assert!(html.contains("style=\"width: 16px;\""), "{}", html);
+        let html = to_html("![A chart](a.png){align=right}\n\n![](b.png) ![](c.png)\n");
+        assert_eq!(
+            html,
+            "<figure id=\"figure-1\" class=\"align-right\"><img src=\"a.png\" alt=\"Figure 1: A chart\" /><figcaption>Figure 1: A chart</figcaption></figure>\n\
+             <div class=\"image-row\"><img src=\"b.png\" alt=\"\" /> <img src=\"c.png\" alt=\"\" /></div>\n"
+        );
+    }
+
+    #[test]
+    fn keeps_plain_code_blocks_as_pulldown_writes_them() {
+        assert_eq!(
+            to_html("```rust\nlet a = 1;\n```\n"),
+            "<pre><code class=\"language-rust\">let a = 1;\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn renders_code_block_attributes() {
+        let html = to_html("```rust title=\"main.rs\" linenos\nfn main() {}\n```\n");
+        assert!(
+            html.starts_with("<div class=\"code-block\">\n<div class=\"code-title\">main.rs</div>")
+        );
+        assert!(html.contains(
+            "<code class=\"language-rust\"><span class=\"code-line\"><span class=\"line-number\">1</span><span class=\"line-text\">fn main() {}</span>"
+        ));
+    }
+
+    #[test]
+    fn leaves_references_in_titled_code_blocks_alone() {
+        let html = to_html("# Intro {#sec:intro}\n\n```text title=\"refs\"\nsee @sec:intro\n```\n");
+        assert!(html.contains("<code class=\"language-text\">see @sec:intro\n</code>"));
+        assert!(!html.contains("<a "));
+    }
+
+    #[test]
+    fn puts_table_captions_where_configured() {
+        let markdown = "| a |\n|---|\n| 1 |\n\nTable: Prices\n";
+        let above = to_html(markdown);
+        assert!(
+            above.starts_with("<figure class=\"table\" id=\"table-1\">\n<figcaption>Table 1: Prices</figcaption><table>"),
+            "{}",
+            above
+        );
+
+        let mut config = get_default_config();
+        config.captions.table_position = "below".to_string();
+        let images = ImageResolver::new(&config.images, Path::new("."));
+        let below = markdown_to_html(&parse_document(markdown, &config, None), &config, &images);
+        assert!(
+            below.starts_with("<figure class=\"table\" id=\"table-1\">\n<table>"),
+            "{}",
+            below
+        );
+        assert!(
+            below.ends_with("</table>\n<figcaption>Table 1: Prices</figcaption></figure>\n"),
+            "{}",
+            below
+        );
+    }
+
+    #[test]
+    fn wraps_wide_tables() {
+        let mut config = get_default_config();
+        config.tables.wide = "shrink".to_string();
+        config.tables.wide_columns = 2;
+        let images = ImageResolver::new(&config.images, Path::new("."));
+        let html =
+            |markdown| markdown_to_html(&parse_document(markdown, &config, None), &config, &images);
+
+        let wide = html("| a | b | c |\n|---|---|---|\n| 1 | 2 | 3 |\n");
+        assert!(
+            wide.starts_with("<div class=\"wide-table\">\n<table>"),
+            "{}",
+            wide
+        );
+        assert!(wide.ends_with("</table>\n</div>\n"), "{}", wide);
+
+        let narrow = html("| a | b |\n|---|---|\n| 1 | 2 |\n");
+        assert!(!narrow.contains("wide-table"), "{}", narrow);
+    }
+}
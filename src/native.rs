@@ -0,0 +1,2763 @@
+//! Browser-free rendering: lays the pulldown-cmark event stream out directly
+//! into a PDF, using the same `Config` values as the HTML stylesheet.
+//!
+//! This is a deliberately simple typesetter: greedy line breaking, no
+//! hyphenation and no syntax highlighting. It exists so mandy can run on
+//! machines without Edge or Chrome.
+
+use crate::backend::{PdfBackend, RenderJob};
+use crate::code_blocks::{CodeAttrs, DiffLine};
+use crate::config::{length_to_inches, Config, FontFaceConfig};
+use crate::cover::Cover;
+use crate::directives::{
+    column_layout, ColumnLayout, COLUMNS_END, KEEP_TOGETHER, KEEP_TOGETHER_END, LANDSCAPE,
+    LANDSCAPE_END,
+};
+use crate::figures::TableCaption;
+use crate::fonts::{declared_faces, find_family, FontFile};
+use crate::images::{is_external, ImageResolver};
+use crate::markdown::{ImageAttrs, PAGE_BREAK};
+use crate::sections::{letters, roman};
+use printpdf::image_crate::{DynamicImage, GenericImageView};
+use printpdf::path::PaintMode;
+use printpdf::{
+    Actions, BuiltinFont, Color, Image, ImageTransform, IndirectFontRef, Line, LinkAnnotation, Mm,
+    PdfDocument, PdfDocumentReference, PdfLayerReference, PdfPageIndex, Point, Pt, Rect, Rgb,
+};
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::iter::Peekable;
+
+const SERIF_FALLBACKS: [&str; 6] = [
+    "Times New Roman",
+    "Liberation Serif",
+    "DejaVu Serif",
+    "Noto Serif",
+    "Georgia",
+    "DejaVu Sans",
+];
+const MONO_FALLBACKS: [&str; 5] = [
+    "Courier New",
+    "Liberation Mono",
+    "DejaVu Sans Mono",
+    "Consolas",
+    "Noto Sans Mono",
+];
+
+pub struct NativeBackend {
+    config: Config,
+}
+
+impl NativeBackend {
+    pub fn new(config: Config) -> Self {
+        NativeBackend { config }
+    }
+}
+
+impl PdfBackend for NativeBackend {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn render(&self, job: &RenderJob) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.render_with(job, |doc| Fonts::load(doc, &self.config))
+    }
+}
+
+impl NativeBackend {
+    /// Lays the document out with the fonts `load_fonts` adds to it.
+    fn render_with(
+        &self,
+        job: &RenderJob,
+        load_fonts: impl FnOnce(&PdfDocumentReference) -> Result<Fonts, Box<dyn Error>>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let blocks = parse_blocks(&mut job.document.events.iter().cloned().peekable(), None);
+
+        let title = blocks
+            .iter()
+            .find_map(|block| match block {
+                Block::Heading(_, spans) => Some(plain_text(spans)),
+                _ => None,
+            })
+            .unwrap_or_else(|| job.header_text.to_string());
+
+        let width = job.options.paper_width as f32 * 72.0;
+        let height = job.options.paper_height as f32 * 72.0;
+        let (doc, page, layer) = PdfDocument::new(title, pt_mm(width), pt_mm(height), "Layer 1");
+
+        let fonts = load_fonts(&doc)?;
+        let layer = doc.get_page(page).get_layer(layer);
+        let mut layout = Layout::new(
+            &doc,
+            &self.config,
+            fonts,
+            job,
+            (width, height),
+            (page, layer),
+        );
+
+        if let Some(cover) = job.cover {
+            layout.cover(cover);
+        } else if !job.header_text.is_empty() {
+            layout.draw_running_header(job.header_text);
+        }
+        let columns = layout.column_set(&ColumnLayout {
+            count: self.config.page.columns.count,
+            ..ColumnLayout::default()
+        });
+        layout.set_columns(columns);
+
+        for block in &blocks {
+            layout.block(block, &Context::default());
+        }
+
+        layout.draw_page_numbers();
+
+        Ok(doc.save_to_bytes()?)
+    }
+}
+
+// ----- Document model -------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    code: bool,
+    strike: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    text: String,
+    style: Style,
+    link: Option<String>,
+    image: Option<(String, ImageAttrs)>,
+}
+
+#[derive(Debug)]
+struct Picture {
+    src: String,
+    alt: String,
+    attrs: ImageAttrs,
+}
+
+#[derive(Debug)]
+enum Block {
+    Paragraph(Vec<Span>),
+    /// Text of a tight list item: a paragraph without margins.
+    Text(Vec<Span>),
+    Heading(u8, Vec<Span>),
+    /// The fence's info string and the code.
+    Code(String, String),
+    List(Option<u64>, Vec<Vec<Block>>),
+    Quote(Vec<Block>),
+    Table(Vec<Alignment>, Vec<Vec<Span>>, Vec<Vec<Vec<Span>>>),
+    Image(Picture),
+    /// Several images in one paragraph, laid out side by side.
+    ImageRow(Vec<Picture>),
+    Rule,
+    PageBreak,
+    /// Blocks moved to the next page together rather than split.
+    KeepTogether(Vec<Block>),
+    /// Blocks on landscape pages of their own.
+    Landscape(Vec<Block>),
+    Columns(ColumnLayout, Vec<Block>),
+}
+
+fn plain_text(spans: &[Span]) -> String {
+    spans.iter().map(|span| span.text.as_str()).collect()
+}
+
+fn is_inline(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Text(_)
+            | Event::Code(_)
+            | Event::SoftBreak
+            | Event::HardBreak
+            | Event::TaskListMarker(_)
+            | Event::InlineHtml(_)
+            | Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough)
+            | Event::Start(Tag::Link { .. } | Tag::Image { .. })
+            | Event::End(
+                TagEnd::Emphasis
+                    | TagEnd::Strong
+                    | TagEnd::Strikethrough
+                    | TagEnd::Link
+                    | TagEnd::Image
+            )
+    )
+}
+
+#[derive(Default)]
+struct InlineBuilder {
+    spans: Vec<Span>,
+    style: Style,
+    styles: Vec<Style>,
+    links: Vec<String>,
+    image: Option<(String, String)>,
+    /// Set right after an image, whose `{...}` attributes may follow.
+    after_image: bool,
+}
+
+impl InlineBuilder {
+    fn push_text(&mut self, text: &str, style: Style) {
+        if let Some((_, alt)) = &mut self.image {
+            alt.push_str(text);
+            return;
+        }
+
+        self.spans.push(Span {
+            text: text.to_string(),
+            style,
+            link: self.links.last().cloned(),
+            image: None,
+        });
+    }
+
+    fn event(&mut self, event: Event) {
+        let after_image = std::mem::take(&mut self.after_image);
+
+        match event {
+            Event::Text(text) => {
+                let attrs = after_image.then(|| ImageAttrs::split_from(&text)).flatten();
+                match attrs {
+                    Some((attrs, rest)) => {
+                        if let Some(span) = self.spans.last_mut() {
+                            if let Some((_, image_attrs)) = &mut span.image {
+                                *image_attrs = attrs;
+                            }
+                        }
+                        if !rest.is_empty() {
+                            self.push_text(rest, self.style);
+                        }
+                    }
+                    None => self.push_text(&text, self.style),
+                }
+            }
+            Event::Code(text) => self.push_text(
+                &text,
+                Style {
+                    code: true,
+                    ..self.style
+                },
+            ),
+            Event::SoftBreak => self.push_text(" ", self.style),
+            Event::HardBreak => self.push_text("\n", self.style),
+            Event::TaskListMarker(done) => {
+                self.push_text(if done { "[x] " } else { "[ ] " }, self.style)
+            }
+            Event::Start(tag) => {
+                self.styles.push(self.style);
+                match tag {
+                    Tag::Emphasis => self.style.italic = true,
+                    Tag::Strong => self.style.bold = true,
+                    Tag::Strikethrough => self.style.strike = true,
+                    Tag::Link { dest_url, .. } => self.links.push(dest_url.to_string()),
+                    Tag::Image { dest_url, .. } => {
+                        self.image = Some((dest_url.to_string(), String::new()))
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(tag) => {
+                self.style = self.styles.pop().unwrap_or_default();
+                match tag {
+                    TagEnd::Link => {
+                        self.links.pop();
+                    }
+                    TagEnd::Image => {
+                        if let Some((src, alt)) = self.image.take() {
+                            self.spans.push(Span {
+                                text: alt,
+                                style: self.style,
+                                link: self.links.last().cloned(),
+                                image: Some((src, ImageAttrs::default())),
+                            });
+                            self.after_image = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// A paragraph holding nothing but images becomes an image (or a row of them).
+    fn finish(self, tight: bool) -> Vec<Block> {
+        let images_only = self.spans.iter().any(|span| span.image.is_some())
+            && self
+                .spans
+                .iter()
+                .all(|span| span.image.is_some() || span.text.trim().is_empty());
+
+        if images_only {
+            let mut pictures: Vec<Picture> = self
+                .spans
+                .into_iter()
+                .filter_map(|span| {
+                    span.image.map(|(src, attrs)| Picture {
+                        src,
+                        alt: span.text,
+                        attrs,
+                    })
+                })
+                .collect();
+            return if pictures.len() == 1 {
+                vec![Block::Image(pictures.remove(0))]
+            } else {
+                vec![Block::ImageRow(pictures)]
+            };
+        }
+
+        // Aligned images become blocks of their own ahead of the text; other
+        // inline images are replaced by their alt text.
+        let mut blocks = Vec::new();
+        let mut spans = Vec::new();
+        for mut span in self.spans {
+            match span.image.take() {
+                Some((src, attrs)) if attrs.align.is_some() => {
+                    blocks.push(Block::Image(Picture {
+                        src,
+                        alt: span.text,
+                        attrs,
+                    }));
+                }
+                Some(_) => {
+                    span.text = format!("[{}]", span.text);
+                    spans.push(span);
+                }
+                None => spans.push(span),
+            }
+        }
+
+        // Skip what is left if it was only whitespace around extracted images.
+        if spans.iter().any(|span| !span.text.trim().is_empty()) {
+            blocks.push(if tight {
+                Block::Text(spans)
+            } else {
+                Block::Paragraph(spans)
+            });
+        }
+        blocks
+    }
+}
+
+fn parse_inline<'a>(
+    events: &mut Peekable<impl Iterator<Item = Event<'a>>>,
+    end: TagEnd,
+) -> Vec<Span> {
+    let mut builder = InlineBuilder::default();
+    for event in events.by_ref() {
+        if event == Event::End(end) {
+            break;
+        }
+        builder.event(event);
+    }
+    builder.spans
+}
+
+/// Blocks up to the `until` event, or to the end.
+fn parse_blocks<'a>(
+    events: &mut Peekable<impl Iterator<Item = Event<'a>>>,
+    until: Option<&Event<'a>>,
+) -> Vec<Block> {
+    let mut blocks = Vec::new();
+
+    while let Some(event) = events.next() {
+        if until == Some(&event) {
+            break;
+        }
+
+        if is_inline(&event) {
+            // Tight list items carry their text without a paragraph wrapper.
+            let mut builder = InlineBuilder::default();
+            builder.event(event);
+            while events.peek().is_some_and(is_inline) {
+                builder.event(events.next().unwrap());
+            }
+            blocks.extend(builder.finish(true));
+            continue;
+        }
+
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                let mut builder = InlineBuilder::default();
+                for event in events.by_ref() {
+                    if event == Event::End(TagEnd::Paragraph) {
+                        break;
+                    }
+                    builder.event(event);
+                }
+                blocks.extend(builder.finish(false));
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                let spans = parse_inline(events, TagEnd::Heading(level));
+                blocks.push(Block::Heading(heading_number(level), spans));
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                let mut code = String::new();
+                for event in events.by_ref() {
+                    match event {
+                        Event::Text(text) => code.push_str(&text),
+                        Event::End(TagEnd::CodeBlock) => break,
+                        _ => {}
+                    }
+                }
+                blocks.push(Block::Code(
+                    info,
+                    code.strip_suffix('\n').unwrap_or(&code).to_string(),
+                ));
+            }
+            Event::Start(Tag::List(start)) => {
+                let mut items = Vec::new();
+                while let Some(event) = events.next() {
+                    match event {
+                        Event::Start(Tag::Item) => {
+                            items.push(parse_blocks(events, Some(&Event::End(TagEnd::Item))))
+                        }
+                        Event::End(TagEnd::List(_)) => break,
+                        _ => {}
+                    }
+                }
+                blocks.push(Block::List(start, items));
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                let end = Event::End(TagEnd::BlockQuote);
+                blocks.push(Block::Quote(parse_blocks(events, Some(&end))));
+            }
+            Event::Start(Tag::Table(alignments)) => {
+                let mut head = Vec::new();
+                let mut rows = Vec::new();
+                let mut row = Vec::new();
+                while let Some(event) = events.next() {
+                    match event {
+                        Event::Start(Tag::TableCell) => {
+                            row.push(parse_inline(events, TagEnd::TableCell))
+                        }
+                        Event::End(TagEnd::TableHead) => head = std::mem::take(&mut row),
+                        Event::End(TagEnd::TableRow) => rows.push(std::mem::take(&mut row)),
+                        Event::End(TagEnd::Table) => break,
+                        _ => {}
+                    }
+                }
+                blocks.push(Block::Table(alignments, head, rows));
+            }
+            Event::Rule => blocks.push(Block::Rule),
+            Event::Html(html) => match html.as_ref() {
+                PAGE_BREAK => blocks.push(Block::PageBreak),
+                KEEP_TOGETHER => {
+                    let end = Event::Html(KEEP_TOGETHER_END.into());
+                    blocks.push(Block::KeepTogether(parse_blocks(events, Some(&end))));
+                }
+                LANDSCAPE => {
+                    let end = Event::Html(LANDSCAPE_END.into());
+                    blocks.push(Block::Landscape(parse_blocks(events, Some(&end))));
+                }
+                html => {
+                    if let Some(layout) = column_layout(html) {
+                        let end = Event::Html(COLUMNS_END.into());
+                        blocks.push(Block::Columns(layout, parse_blocks(events, Some(&end))));
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+fn heading_number(level: HeadingLevel) -> u8 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+// ----- Fonts ----------------------------------------------------------------
+
+/// The advance of a character a font has no glyph for, in ems.
+const MISSING_ADVANCE: f32 = 0.5;
+
+enum Metrics {
+    /// TrueType and OpenType fonts: every mapped character's advance, in
+    /// ems, read once when the font is loaded.
+    TrueType(HashMap<char, f32>),
+    /// Built-in PDF fonts: a flat average advance, in ems.
+    Builtin(f32),
+}
+
+impl Metrics {
+    fn true_type(data: &[u8]) -> Result<Metrics, ttf_parser::FaceParsingError> {
+        let face = ttf_parser::Face::parse(data, 0)?;
+        let units = face.units_per_em() as f32;
+        let mut advances = HashMap::new();
+
+        let subtables = face.tables().cmap.iter().flat_map(|cmap| cmap.subtables);
+        for subtable in subtables.filter(|subtable| subtable.is_unicode()) {
+            subtable.codepoints(|codepoint| {
+                let advance = subtable
+                    .glyph_index(codepoint)
+                    .and_then(|glyph| face.glyph_hor_advance(glyph));
+                if let (Some(c), Some(advance)) = (char::from_u32(codepoint), advance) {
+                    advances.entry(c).or_insert(advance as f32 / units);
+                }
+            });
+        }
+        Ok(Metrics::TrueType(advances))
+    }
+
+    fn width(&self, text: &str, size: f32) -> f32 {
+        match self {
+            Metrics::TrueType(advances) => {
+                text.chars()
+                    .map(|c| advances.get(&c).copied().unwrap_or(MISSING_ADVANCE))
+                    .sum::<f32>()
+                    * size
+            }
+            Metrics::Builtin(advance) => text.chars().count() as f32 * size * advance,
+        }
+    }
+}
+
+struct Font {
+    pdf: IndirectFontRef,
+    metrics: Metrics,
+}
+
+impl Font {
+    fn external(doc: &PdfDocumentReference, file: &FontFile) -> Result<Font, Box<dyn Error>> {
+        let metrics = Metrics::true_type(&fs::read(&file.path)?)?;
+        let pdf = doc.add_external_font(File::open(&file.path)?)?;
+        Ok(Font { pdf, metrics })
+    }
+
+    fn builtin(
+        doc: &PdfDocumentReference,
+        font: BuiltinFont,
+        advance: f32,
+    ) -> Result<Font, Box<dyn Error>> {
+        Ok(Font {
+            pdf: doc.add_builtin_font(font)?,
+            metrics: Metrics::Builtin(advance),
+        })
+    }
+
+    fn width(&self, text: &str, size: f32) -> f32 {
+        self.metrics.width(text, size)
+    }
+}
+
+struct Fonts {
+    regular: Font,
+    bold: Font,
+    italic: Font,
+    bold_italic: Font,
+    mono: Font,
+}
+
+impl Fonts {
+    fn load(doc: &PdfDocumentReference, config: &Config) -> Result<Fonts, Box<dyn Error>> {
+        let files = &config.fonts.files;
+        let body = resolve_family(files, &config.fonts.body_family, &SERIF_FALLBACKS);
+        let mono = resolve_family(files, &config.fonts.code_family, &MONO_FALLBACKS);
+        Fonts::from_faces(doc, &body, &mono)
+    }
+
+    /// The built-in PDF fonts, whatever is installed.
+    #[cfg(test)]
+    fn builtin(doc: &PdfDocumentReference) -> Result<Fonts, Box<dyn Error>> {
+        Fonts::from_faces(doc, &[], &[])
+    }
+
+    /// The best of `body` and `mono` for each style, or a built-in font.
+    fn from_faces(
+        doc: &PdfDocumentReference,
+        body: &[FontFile],
+        mono: &[FontFile],
+    ) -> Result<Fonts, Box<dyn Error>> {
+        let pick = |faces: &[FontFile], bold: bool, italic: bool| -> Option<FontFile> {
+            faces
+                .iter()
+                .find(|f| f.bold == bold && f.italic == italic)
+                .or_else(|| faces.iter().find(|f| f.bold == bold))
+                .or_else(|| faces.iter().find(|f| !f.bold && !f.italic))
+                .or_else(|| faces.first())
+                .cloned()
+        };
+
+        let load =
+            |faces: &[FontFile], bold: bool, italic: bool, builtin: BuiltinFont, advance: f32| {
+                match pick(faces, bold, italic) {
+                    Some(file) => Font::external(doc, &file),
+                    None => Font::builtin(doc, builtin, advance),
+                }
+            };
+
+        Ok(Fonts {
+            regular: load(body, false, false, BuiltinFont::TimesRoman, 0.45)?,
+            bold: load(body, true, false, BuiltinFont::TimesBold, 0.48)?,
+            italic: load(body, false, true, BuiltinFont::TimesItalic, 0.45)?,
+            bold_italic: load(body, true, true, BuiltinFont::TimesBoldItalic, 0.48)?,
+            mono: load(mono, false, false, BuiltinFont::Courier, 0.6)?,
+        })
+    }
+
+    fn get(&self, style: Style) -> &Font {
+        match (style.code, style.bold, style.italic) {
+            (true, _, _) => &self.mono,
+            (false, true, true) => &self.bold_italic,
+            (false, true, false) => &self.bold,
+            (false, false, true) => &self.italic,
+            (false, false, false) => &self.regular,
+        }
+    }
+}
+
+/// Faces declared in `fonts.files` come first, then installed ones, then the
+/// first installed fallback.
+fn resolve_family(files: &[FontFaceConfig], family: &str, fallbacks: &[&str]) -> Vec<FontFile> {
+    let declared: Vec<FontFile> = declared_faces(files, family)
+        .into_iter()
+        .filter(|face| {
+            let extension = face
+                .path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(str::to_lowercase);
+            if !matches!(extension.as_deref(), Some("ttf" | "otf")) {
+                eprintln!(
+                    "Warning: the native backend can only embed TTF and OTF fonts, skipping '{}'",
+                    face.path.display()
+                );
+                return false;
+            }
+            face.path.is_file()
+        })
+        .collect();
+    if !declared.is_empty() {
+        return declared;
+    }
+
+    let faces = find_family(family);
+    if !faces.is_empty() {
+        return faces;
+    }
+
+    for fallback in fallbacks {
+        let faces = find_family(fallback);
+        if let Some(face) = faces.first() {
+            eprintln!(
+                "Warning: font '{}' is not installed, using '{}'",
+                family, face.family
+            );
+            return faces;
+        }
+    }
+
+    eprintln!(
+        "Warning: font '{}' is not installed, using a built-in PDF font",
+        family
+    );
+    Vec::new()
+}
+
+// ----- Units and colours ----------------------------------------------------
+
+fn pt_mm(points: f32) -> Mm {
+    Mm::from(Pt(points))
+}
+
+fn length_pt(value: &str, default: f32) -> f32 {
+    length_to_inches(value)
+        .map(|inches| inches as f32 * 72.0)
+        .unwrap_or(default)
+}
+
+/// A length in points that may also be given in `em` of `em` points.
+fn em_length_pt(value: &str, em: f32) -> f32 {
+    match value.trim().strip_suffix("em") {
+        Some(ems) => ems.trim().parse::<f32>().map_or(em, |ems| ems * em),
+        None => length_pt(value, em),
+    }
+}
+
+/// A CSS size in points: `%` is relative to `reference`, `vh` to the page's
+/// content height and `none` is unbounded.
+fn css_size(value: &str, reference: f32, content_height: f32) -> f32 {
+    let value = value.trim();
+    if value == "none" {
+        f32::INFINITY
+    } else if let Some(percent) = value.strip_suffix('%') {
+        percent
+            .parse::<f32>()
+            .map_or(reference, |p| reference * p / 100.0)
+    } else if let Some(vh) = value.strip_suffix("vh") {
+        vh.parse::<f32>()
+            .map_or(content_height, |v| content_height * v / 100.0)
+    } else {
+        length_pt(value, reference)
+    }
+}
+
+/// CSS margin shorthand: returns (top, right, bottom, left) in points.
+fn box_pt(value: &str) -> (f32, f32, f32, f32) {
+    let parts: Vec<f32> = value
+        .split_whitespace()
+        .map(|v| length_pt(v, 0.0))
+        .collect();
+    match parts.as_slice() {
+        [all] => (*all, *all, *all, *all),
+        [vertical, horizontal] => (*vertical, *horizontal, *vertical, *horizontal),
+        [top, horizontal, bottom] => (*top, *horizontal, *bottom, *horizontal),
+        [top, right, bottom, left, ..] => (*top, *right, *bottom, *left),
+        [] => (0.0, 0.0, 0.0, 0.0),
+    }
+}
+
+/// `number` in a CSS list style: `decimal`, `lower-alpha`, `upper-alpha`,
+/// `lower-roman` or `upper-roman`.
+fn list_number(number: u64, style: &str) -> String {
+    let number = number as usize;
+    match style {
+        "lower-alpha" | "lower-latin" => letters(number).to_lowercase(),
+        "upper-alpha" | "upper-latin" => letters(number),
+        "lower-roman" => roman(number).to_lowercase(),
+        "upper-roman" => roman(number),
+        _ => number.to_string(),
+    }
+}
+
+/// CSS border shorthand such as `1px solid #000`: the width in points and
+/// the colour, or `None` for no border.
+fn parse_border(value: &str) -> Option<(f32, (f32, f32, f32))> {
+    let mut width = 0.75;
+    let mut color = BLACK;
+    for token in value.split_whitespace() {
+        if matches!(token, "none" | "hidden") {
+            return None;
+        }
+        if let Some(inches) = length_to_inches(token) {
+            width = inches as f32 * 72.0;
+        } else if let Some(parsed) = parse_color(token) {
+            color = parsed;
+        }
+    }
+    (width > 0.0).then_some((width, color))
+}
+
+/// Parses `#rgb`, `#rrggbb`, `#rrggbbaa` and a few names. `None` means
+/// transparent (or unrecognised), i.e. paint nothing.
+fn parse_color(value: &str) -> Option<(f32, f32, f32)> {
+    let value = value
+        .trim()
+        .trim_end_matches("!important")
+        .trim()
+        .to_lowercase();
+    let hex = match value.as_str() {
+        "black" => "000000".to_string(),
+        "white" => "ffffff".to_string(),
+        "gray" | "grey" => "808080".to_string(),
+        "red" => "ff0000".to_string(),
+        "green" => "008000".to_string(),
+        "blue" => "0000ff".to_string(),
+        other => {
+            let digits = other.strip_prefix('#')?;
+            if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            match digits.len() {
+                3 | 4 => digits.chars().take(3).flat_map(|c| [c, c]).collect(),
+                6 | 8 => digits[..6].to_string(),
+                _ => return None,
+            }
+        }
+    };
+
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .ok()
+            .map(|v| v as f32 / 255.0)
+    };
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn rgb((r, g, b): (f32, f32, f32)) -> Color {
+    Color::Rgb(Rgb::new(r, g, b, None))
+}
+
+const BLACK: (f32, f32, f32) = (0.0, 0.0, 0.0);
+const LINK_BLUE: (f32, f32, f32) = (0.0, 0.0, 0.93);
+
+// ----- Layout ---------------------------------------------------------------
+
+/// Inherited block state: left/right insets, text colour and style, quote
+/// bars, and list nesting with the numbers of enclosing ordered items.
+#[derive(Clone, Default)]
+struct Context {
+    indent: f32,
+    color: Option<(f32, f32, f32)>,
+    italic: bool,
+    bars: Vec<f32>,
+    list_depth: usize,
+    numbers: Vec<u64>,
+}
+
+#[derive(Clone, Copy)]
+struct TextStyle {
+    size: f32,
+    bold: bool,
+    italic: bool,
+    color: (f32, f32, f32),
+}
+
+struct Fragment {
+    text: String,
+    style: Style,
+    size: f32,
+    color: (f32, f32, f32),
+    link: Option<String>,
+    width: f32,
+}
+
+#[derive(Default)]
+struct TextLine {
+    fragments: Vec<Fragment>,
+    width: f32,
+    size: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A table's `tables` settings, resolved once per table.
+struct TableStyle {
+    body: TextStyle,
+    header: TextStyle,
+    alignments: Vec<Align>,
+    /// Vertical and horizontal cell padding.
+    padding: (f32, f32),
+    border: Option<(f32, (f32, f32, f32))>,
+    header_background: Option<(f32, f32, f32)>,
+    zebra_background: Option<(f32, f32, f32)>,
+}
+
+#[derive(Clone, Copy)]
+enum Row {
+    Header,
+    Odd,
+    Even,
+}
+
+impl Align {
+    fn from_css(value: &str) -> Align {
+        match value {
+            "center" => Align::Center,
+            "right" => Align::Right,
+            _ => Align::Left,
+        }
+    }
+}
+
+struct Layout<'a> {
+    doc: &'a PdfDocumentReference,
+    config: &'a Config,
+    fonts: Fonts,
+    images: ImageResolver,
+    table_captions: Vec<Option<TableCaption>>,
+    tables_seen: usize,
+    /// The paper size; `page_width`/`page_height` are the current page's.
+    paper: (f32, f32),
+    page_width: f32,
+    page_height: f32,
+    margin: f32,
+    first_top_margin: f32,
+    line_height: f32,
+    body_size: f32,
+    pages: Vec<PdfPageIndex>,
+    /// Indexes into `pages` of the pages turned for wide tables.
+    landscape_pages: HashSet<usize>,
+    layer: PdfLayerReference,
+    /// Top edge of the next line box, measured up from the bottom of the page.
+    y: f32,
+    pending_space: f32,
+    pending_marker: Option<String>,
+    seen_h1: bool,
+    lead_paragraph: bool,
+    bookmarked: HashSet<usize>,
+    /// How many columns the text is set in, which one is being filled, where
+    /// they start on this page, and the lowest point any of them reached.
+    columns: ColumnSet,
+    column: usize,
+    column_top: f32,
+    column_bottom: f32,
+    /// Set while laying blocks out only to see whether they fit, drawing
+    /// nothing, and `overflowed` once they have not.
+    measuring: bool,
+    overflowed: bool,
+}
+
+/// Columns as laid out: how many, the gap between them in points and the
+/// rule drawn in it.
+#[derive(Clone, Copy)]
+struct ColumnSet {
+    count: usize,
+    gap: f32,
+    rule: Option<(f32, (f32, f32, f32))>,
+}
+
+impl ColumnSet {
+    const SINGLE: ColumnSet = ColumnSet {
+        count: 1,
+        gap: 0.0,
+        rule: None,
+    };
+}
+
+/// The layout state a measuring pass changes, to put back afterwards.
+struct Snapshot {
+    y: f32,
+    pending_space: f32,
+    pending_marker: Option<String>,
+    seen_h1: bool,
+    lead_paragraph: bool,
+    tables_seen: usize,
+    columns: ColumnSet,
+    column: usize,
+    column_top: f32,
+    column_bottom: f32,
+    measuring: bool,
+    overflowed: bool,
+}
+
+impl<'a> Layout<'a> {
+    fn new(
+        doc: &'a PdfDocumentReference,
+        config: &'a Config,
+        fonts: Fonts,
+        job: &RenderJob,
+        (page_width, page_height): (f32, f32),
+        (first_page, layer): (PdfPageIndex, PdfLayerReference),
+    ) -> Self {
+        let margin = job.options.margin as f32 * 72.0;
+        let first_top_margin = length_pt(&config.page.first_page_top_margin, margin);
+        let top = page_height - first_top_margin;
+
+        Layout {
+            doc,
+            config,
+            fonts,
+            images: job.images.clone(),
+            table_captions: job.document.table_captions.clone(),
+            tables_seen: 0,
+            paper: (page_width, page_height),
+            page_width,
+            page_height,
+            margin,
+            first_top_margin,
+            line_height: config.spacing.line_height.parse().unwrap_or(1.25),
+            body_size: length_pt(&config.fonts.body_size, 12.0),
+            pages: vec![first_page],
+            landscape_pages: HashSet::new(),
+            layer,
+            y: top,
+            pending_space: 0.0,
+            pending_marker: None,
+            seen_h1: false,
+            lead_paragraph: false,
+            bookmarked: HashSet::new(),
+            columns: ColumnSet::SINGLE,
+            column: 0,
+            column_top: top,
+            column_bottom: top,
+            measuring: false,
+            overflowed: false,
+        }
+    }
+
+    /// Width of the column being filled.
+    fn content_width(&self) -> f32 {
+        let ColumnSet { count, gap, .. } = self.columns;
+        (self.page_width - 2.0 * self.margin - gap * (count - 1) as f32) / count as f32
+    }
+
+    /// Left edge of the column being filled.
+    fn left(&self) -> f32 {
+        self.margin + self.column as f32 * (self.content_width() + self.columns.gap)
+    }
+
+    /// `page.columns` with a columns block's overrides.
+    fn column_set(&self, layout: &ColumnLayout) -> ColumnSet {
+        let defaults = &self.config.page.columns;
+        let gap = layout.gap.as_deref().unwrap_or(&defaults.gap);
+        let rule = layout.rule.as_deref().unwrap_or(&defaults.rule);
+        ColumnSet {
+            count: layout.count.max(1),
+            gap: em_length_pt(gap, self.body_size),
+            rule: parse_border(rule),
+        }
+    }
+
+    /// Draws the rules between the columns filled since `column_top`.
+    fn draw_column_rules(&self) {
+        let Some((thickness, color)) = self.columns.rule.filter(|_| !self.measuring) else {
+            return;
+        };
+        let bottom = self.column_bottom.min(self.y);
+        let width = self.content_width();
+        for column in 1..=self.column {
+            let x =
+                self.margin + column as f32 * (width + self.columns.gap) - self.columns.gap / 2.0;
+            self.layer.set_outline_color(rgb(color));
+            self.layer.set_outline_thickness(thickness);
+            self.layer.add_line(Line {
+                points: vec![
+                    (Point::new(pt_mm(x), pt_mm(self.column_top)), false),
+                    (Point::new(pt_mm(x), pt_mm(bottom)), false),
+                ],
+                is_closed: false,
+            });
+        }
+    }
+
+    fn page_top(&self) -> f32 {
+        if self.pages.len() == 1 {
+            self.page_height - self.first_top_margin
+        } else {
+            self.page_height - self.margin
+        }
+    }
+
+    fn at_page_top(&self) -> bool {
+        self.column == 0 && self.y >= self.page_top() - 0.01
+    }
+
+    fn at_column_top(&self) -> bool {
+        self.y >= self.column_top - 0.01
+    }
+
+    fn new_page(&mut self) {
+        self.draw_column_rules();
+        if self.measuring {
+            self.overflowed = true;
+        } else {
+            let (page, layer) =
+                self.doc
+                    .add_page(pt_mm(self.page_width), pt_mm(self.page_height), "Layer 1");
+            self.pages.push(page);
+            self.layer = self.doc.get_page(page).get_layer(layer);
+        }
+        self.y = self.page_height - self.margin;
+        self.pending_space = 0.0;
+        self.column = 0;
+        self.column_top = self.y;
+        self.column_bottom = self.y;
+    }
+
+    /// Moves to the top of the next column, or of a new page after the last.
+    fn next_column(&mut self) {
+        if self.column + 1 >= self.columns.count {
+            self.new_page();
+            return;
+        }
+        if self.measuring {
+            self.overflowed = true;
+        }
+        self.column_bottom = self.column_bottom.min(self.y);
+        self.column += 1;
+        self.y = self.column_top;
+        self.pending_space = 0.0;
+    }
+
+    /// Continues in `columns` below everything laid out so far.
+    fn set_columns(&mut self, columns: ColumnSet) {
+        self.draw_column_rules();
+        if !self.at_column_top() {
+            self.y -= self.pending_space;
+        }
+        self.pending_space = 0.0;
+        self.y = self.y.min(self.column_bottom);
+        self.column_top = self.y;
+        self.column_bottom = self.y;
+        self.column = 0;
+        self.columns = columns;
+    }
+
+    /// Whether `blocks` fit in what is left of the current column.
+    fn fits(&mut self, blocks: &[Block], ctx: &Context) -> bool {
+        let snapshot = Snapshot {
+            y: self.y,
+            pending_space: self.pending_space,
+            pending_marker: self.pending_marker.clone(),
+            seen_h1: self.seen_h1,
+            lead_paragraph: self.lead_paragraph,
+            tables_seen: self.tables_seen,
+            columns: self.columns,
+            column: self.column,
+            column_top: self.column_top,
+            column_bottom: self.column_bottom,
+            measuring: self.measuring,
+            overflowed: self.overflowed,
+        };
+
+        self.measuring = true;
+        self.overflowed = false;
+        for block in blocks {
+            self.block(block, ctx);
+        }
+        let fits = !self.overflowed;
+
+        self.y = snapshot.y;
+        self.pending_space = snapshot.pending_space;
+        self.pending_marker = snapshot.pending_marker;
+        self.seen_h1 = snapshot.seen_h1;
+        self.lead_paragraph = snapshot.lead_paragraph;
+        self.tables_seen = snapshot.tables_seen;
+        self.columns = snapshot.columns;
+        self.column = snapshot.column;
+        self.column_top = snapshot.column_top;
+        self.column_bottom = snapshot.column_bottom;
+        self.measuring = snapshot.measuring;
+        self.overflowed = snapshot.overflowed;
+        fits
+    }
+
+    /// Continues on a new page turned to landscape, or back to the paper's
+    /// own orientation.
+    fn turn_page(&mut self, landscape: bool) {
+        if self.measuring {
+            self.overflowed = true;
+            return;
+        }
+        (self.page_width, self.page_height) = self.page_size(landscape);
+        self.new_page();
+        if landscape {
+            self.landscape_pages.insert(self.pages.len() - 1);
+        }
+    }
+
+    fn page_size(&self, landscape: bool) -> (f32, f32) {
+        let (width, height) = self.paper;
+        if landscape {
+            (width.max(height), width.min(height))
+        } else {
+            (width, height)
+        }
+    }
+
+    fn space(&mut self, amount: f32) {
+        self.pending_space = self.pending_space.max(amount);
+    }
+
+    /// Applies collapsed vertical margins and makes sure `height` fits in the
+    /// current column, moving on to the next one if it doesn't.
+    fn reserve(&mut self, height: f32) {
+        if self.at_column_top() {
+            self.pending_space = 0.0;
+        } else {
+            self.y -= self.pending_space;
+            self.pending_space = 0.0;
+        }
+
+        if self.y - height < self.margin {
+            if !self.at_column_top() {
+                self.next_column();
+            } else if self.column_top < self.page_top() - 0.01 {
+                // Every column is as short as this one; only a page helps.
+                self.new_page();
+            }
+        }
+    }
+
+    // ----- Inline layout -----
+
+    fn break_lines(&self, spans: &[Span], base: TextStyle, width: f32) -> Vec<TextLine> {
+        let mut lines = vec![TextLine::default()];
+        let code_size = length_pt(&self.config.fonts.inline_code_size, base.size);
+        let link_color = parse_color(&self.config.links.color).unwrap_or(LINK_BLUE);
+
+        for span in spans {
+            let style = Style {
+                bold: span.style.bold || base.bold,
+                italic: span.style.italic || base.italic,
+                ..span.style
+            };
+            let size = if style.code { code_size } else { base.size };
+            let color = if span.link.is_some() {
+                link_color
+            } else {
+                base.color
+            };
+            let font = self.fonts.get(style);
+
+            for (index, piece) in span.text.split('\n').enumerate() {
+                if index > 0 {
+                    lines.push(TextLine::default());
+                }
+
+                for word in split_words(piece) {
+                    let mut word = word.to_string();
+                    let mut word_width = font.width(&word, size);
+                    let line = lines.last_mut().unwrap();
+
+                    if line.width + word_width > width && line.width > 0.0 {
+                        if word.trim().is_empty() {
+                            continue;
+                        }
+                        lines.push(TextLine::default());
+                    }
+
+                    // Words wider than the whole line are split by character.
+                    while word_width > width && word.chars().count() > 1 {
+                        let mut head = String::new();
+                        for c in word.chars() {
+                            if font.width(&format!("{}{}", head, c), size) > width
+                                && !head.is_empty()
+                            {
+                                break;
+                            }
+                            head.push(c);
+                        }
+                        word = word[head.len()..].to_string();
+                        let head_width = font.width(&head, size);
+                        push_fragment(
+                            lines.last_mut().unwrap(),
+                            &head,
+                            style,
+                            size,
+                            color,
+                            &span.link,
+                            head_width,
+                        );
+                        lines.push(TextLine::default());
+                        word_width = font.width(&word, size);
+                    }
+
+                    let line = lines.last_mut().unwrap();
+                    if line.width == 0.0 && word.trim().is_empty() {
+                        continue;
+                    }
+                    push_fragment(line, &word, style, size, color, &span.link, word_width);
+                }
+            }
+        }
+
+        for line in &mut lines {
+            if let Some(last) = line.fragments.last_mut() {
+                let trimmed = last.text.trim_end().to_string();
+                if trimmed.len() != last.text.len() {
+                    let font = self.fonts.get(last.style);
+                    line.width -= last.width;
+                    last.width = font.width(&trimmed, last.size);
+                    last.text = trimmed;
+                    line.width += last.width;
+                }
+            }
+            if line.size == 0.0 {
+                line.size = base.size;
+            }
+        }
+
+        if lines.len() > 1 && lines.last().is_some_and(|line| line.fragments.is_empty()) {
+            lines.pop();
+        }
+
+        lines
+    }
+
+    fn draw_line(&mut self, line: &TextLine, x: f32, width: f32, align: Align, ctx: &Context) {
+        let height = line.size * self.line_height;
+        // `x` moves along with the line if it continues in the next column.
+        let left = self.left();
+        self.reserve(height);
+        let x = x + self.left() - left;
+
+        let top = self.y;
+        let baseline = top - (height - line.size) / 2.0 - line.size * 0.8;
+        let mut cursor = match align {
+            Align::Left => x,
+            Align::Center => x + (width - line.width) / 2.0,
+            Align::Right => x + width - line.width,
+        };
+
+        self.draw_bars(ctx, top, height);
+
+        if let Some(marker) = self.pending_marker.take() {
+            let marker_width = self.fonts.regular.width(&marker, self.body_size);
+            let marker_x = x - marker_width - 6.0;
+            self.draw_text(
+                &marker,
+                Style::default(),
+                self.body_size,
+                ctx.color.unwrap_or(BLACK),
+                marker_x,
+                baseline,
+            );
+        }
+
+        for fragment in &line.fragments {
+            self.draw_text(
+                &fragment.text,
+                fragment.style,
+                fragment.size,
+                fragment.color,
+                cursor,
+                baseline,
+            );
+
+            if fragment.style.strike {
+                self.draw_rule(
+                    cursor,
+                    cursor + fragment.width,
+                    baseline + fragment.size * 0.3,
+                    0.6,
+                    fragment.color,
+                );
+            }
+
+            if let Some(url) = &fragment.link {
+                if self.config.links.underline {
+                    self.draw_rule(
+                        cursor,
+                        cursor + fragment.width,
+                        baseline - 1.5,
+                        0.5,
+                        fragment.color,
+                    );
+                }
+                if !url.starts_with('#') && !self.measuring {
+                    self.layer.add_link_annotation(LinkAnnotation::new(
+                        Rect::new(
+                            pt_mm(cursor),
+                            pt_mm(baseline - fragment.size * 0.25),
+                            pt_mm(cursor + fragment.width),
+                            pt_mm(baseline + fragment.size * 0.85),
+                        ),
+                        None,
+                        None,
+                        Actions::uri(url.clone()),
+                        None,
+                    ));
+                }
+            }
+
+            cursor += fragment.width;
+        }
+
+        self.y -= height;
+    }
+
+    fn draw_text(
+        &self,
+        text: &str,
+        style: Style,
+        size: f32,
+        color: (f32, f32, f32),
+        x: f32,
+        baseline: f32,
+    ) {
+        if text.is_empty() || self.measuring {
+            return;
+        }
+        let font = self.fonts.get(style);
+        self.layer.set_fill_color(rgb(color));
+        self.layer
+            .use_text(text, size, pt_mm(x), pt_mm(baseline), &font.pdf);
+    }
+
+    fn draw_rule(&self, x1: f32, x2: f32, y: f32, thickness: f32, color: (f32, f32, f32)) {
+        if self.measuring {
+            return;
+        }
+        self.layer.set_outline_color(rgb(color));
+        self.layer.set_outline_thickness(thickness);
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(pt_mm(x1), pt_mm(y)), false),
+                (Point::new(pt_mm(x2), pt_mm(y)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    fn draw_bars(&self, ctx: &Context, top: f32, height: f32) {
+        let quote = &self.config.blockquote;
+        let Some(color) = parse_color(&quote.border_color) else {
+            return;
+        };
+        if self.measuring {
+            return;
+        }
+        for offset in &ctx.bars {
+            let x = self.left() + offset;
+            self.layer.set_outline_color(rgb(color));
+            self.layer
+                .set_outline_thickness(length_pt(&quote.border_width, 2.25));
+            self.layer.add_line(Line {
+                points: vec![
+                    (Point::new(pt_mm(x), pt_mm(top)), false),
+                    (Point::new(pt_mm(x), pt_mm(top - height)), false),
+                ],
+                is_closed: false,
+            });
+        }
+    }
+
+    fn fill_rect(&self, x: f32, y: f32, width: f32, height: f32, color: (f32, f32, f32)) {
+        if self.measuring {
+            return;
+        }
+        self.layer.set_fill_color(rgb(color));
+        self.layer.add_rect(
+            Rect::new(pt_mm(x), pt_mm(y), pt_mm(x + width), pt_mm(y + height))
+                .with_mode(PaintMode::Fill),
+        );
+    }
+
+    fn stroke_rect(
+        &self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        thickness: f32,
+        color: (f32, f32, f32),
+    ) {
+        if self.measuring {
+            return;
+        }
+        self.layer.set_outline_color(rgb(color));
+        self.layer.set_outline_thickness(thickness);
+        self.layer.add_rect(
+            Rect::new(pt_mm(x), pt_mm(y), pt_mm(x + width), pt_mm(y + height))
+                .with_mode(PaintMode::Stroke),
+        );
+    }
+
+    fn paragraph(&mut self, spans: &[Span], style: TextStyle, align: Align, ctx: &Context) {
+        let width = self.content_width() - ctx.indent;
+
+        for line in self.break_lines(spans, style, width) {
+            let x = self.left() + ctx.indent;
+            self.draw_line(&line, x, width, align, ctx);
+        }
+    }
+
+    // ----- Blocks -----
+
+    fn block(&mut self, block: &Block, ctx: &Context) {
+        let lead = std::mem::take(&mut self.lead_paragraph);
+        let color = ctx.color.unwrap_or(BLACK);
+        let paragraph_margin = length_pt(&self.config.spacing.paragraph_margin, 12.0);
+
+        match block {
+            Block::Paragraph(spans) => {
+                let size = if lead {
+                    length_pt(&self.config.title_page.first_paragraph_size, self.body_size)
+                } else {
+                    self.body_size
+                };
+                self.space(paragraph_margin);
+                self.paragraph(
+                    spans,
+                    TextStyle {
+                        size,
+                        bold: false,
+                        italic: ctx.italic,
+                        color,
+                    },
+                    Align::Left,
+                    ctx,
+                );
+                self.space(paragraph_margin);
+            }
+            Block::Text(spans) => {
+                let style = TextStyle {
+                    size: self.body_size,
+                    bold: false,
+                    italic: ctx.italic,
+                    color,
+                };
+                self.paragraph(spans, style, Align::Left, ctx);
+            }
+            Block::Heading(level, spans) => self.heading(*level, spans, ctx),
+            Block::Code(info, code) => self.code_block(info, code, ctx),
+            Block::List(start, items) => self.list(*start, items, ctx),
+            Block::Quote(blocks) => {
+                let quote = &self.config.blockquote;
+                let bar_width = length_pt(&quote.border_width, 2.25);
+                let bar = ctx.indent + bar_width / 2.0;
+                let inner = Context {
+                    indent: ctx.indent + bar_width + length_pt(&quote.padding, 12.0),
+                    color: Some(parse_color(&quote.color).unwrap_or(color)),
+                    italic: quote.italic || ctx.italic,
+                    bars: ctx.bars.iter().copied().chain([bar]).collect(),
+                    ..ctx.clone()
+                };
+                self.space(12.0);
+                for block in blocks {
+                    self.block(block, &inner);
+                }
+                self.space(12.0);
+            }
+            Block::Table(alignments, head, rows) => self.table(alignments, head, rows, ctx),
+            Block::Image(picture) => self.image(picture, ctx),
+            Block::ImageRow(pictures) => self.image_row(pictures, ctx),
+            Block::Rule => {
+                self.space(12.0);
+                self.reserve(1.0);
+                let x = self.left() + ctx.indent;
+                self.draw_rule(
+                    x,
+                    self.left() + self.content_width(),
+                    self.y,
+                    0.75,
+                    (0.6, 0.6, 0.6),
+                );
+                self.y -= 1.0;
+                self.space(12.0);
+            }
+            Block::PageBreak => {
+                if !self.at_page_top() {
+                    self.new_page();
+                }
+            }
+            Block::KeepTogether(blocks) => {
+                if !self.at_column_top() && !self.fits(blocks, ctx) {
+                    self.next_column();
+                }
+                for block in blocks {
+                    self.block(block, ctx);
+                }
+            }
+            Block::Landscape(blocks) => {
+                self.turn_page(true);
+                for block in blocks {
+                    self.block(block, ctx);
+                }
+                self.turn_page(false);
+            }
+            Block::Columns(layout, blocks) => {
+                let outer = self.columns;
+                self.set_columns(self.column_set(layout));
+                for block in blocks {
+                    self.block(block, ctx);
+                }
+                self.set_columns(outer);
+            }
+        }
+    }
+
+    fn heading(&mut self, level: u8, spans: &[Span], ctx: &Context) {
+        let headings = &self.config.headings;
+        let spacing = &self.config.spacing;
+
+        let (size, margins) = match level {
+            1 => (
+                &headings.h1_size,
+                format!("0 0 {} 0", spacing.h1_bottom_margin),
+            ),
+            2 => (
+                &headings.h2_size,
+                format!("0 0 {} 0", spacing.h2_bottom_margin),
+            ),
+            3 => (&headings.h3_size, spacing.h3_margins.clone()),
+            4 => (&headings.h4_size, spacing.h4_margins.clone()),
+            5 => (&headings.h5_size, spacing.h5_margins.clone()),
+            _ => (&headings.h6_size, spacing.h6_margins.clone()),
+        };
+        let size = length_pt(size, self.body_size);
+        let (top, _, bottom, _) = box_pt(&margins);
+        let align = if level == 1 {
+            Align::from_css(&headings.h1_align)
+        } else {
+            Align::Left
+        };
+
+        // Mirrors `h1:first-of-type { page-break-before: avoid }`.
+        let first_h1 = level == 1 && !self.seen_h1;
+        if headings.page_break_before(level) && !first_h1 && !self.at_page_top() {
+            self.new_page();
+        }
+
+        // Keep the heading with at least two lines of what follows.
+        let following = if headings.keep_with_next(level) {
+            2.0 * self.body_size * self.line_height
+        } else {
+            0.0
+        };
+        let page_break_after = headings.page_break_after(level);
+        self.space(top);
+
+        // Mirrors `column-span: all`.
+        let columns = self.columns;
+        let span = columns.count > 1 && level <= self.config.page.columns.span_headings;
+        if span {
+            self.set_columns(ColumnSet::SINGLE);
+        }
+        self.reserve(size * self.line_height + following);
+
+        let page = self.pages.len() - 1;
+        if level <= 3 && !self.measuring && self.bookmarked.insert(page) {
+            self.doc.add_bookmark(plain_text(spans), self.pages[page]);
+        }
+
+        let style = TextStyle {
+            size,
+            bold: true,
+            italic: level == 6,
+            color: ctx.color.unwrap_or(BLACK),
+        };
+        self.paragraph(spans, style, align, ctx);
+        self.space(bottom);
+        if span {
+            self.set_columns(columns);
+        }
+        if page_break_after {
+            self.new_page();
+        }
+
+        if level == 1 {
+            self.lead_paragraph = first_h1;
+            self.seen_h1 = true;
+        }
+    }
+
+    fn code_block(&mut self, info: &str, code: &str, ctx: &Context) {
+        let blocks = &self.config.code_blocks;
+        let attrs = CodeAttrs::parse(info, blocks);
+        let size = length_pt(&self.config.fonts.block_code_size, 9.0);
+        let (margin_top, _, margin_bottom, _) = box_pt(&blocks.margin);
+        let (pad_top, pad_right, pad_bottom, pad_left) = box_pt(&blocks.padding);
+        let background = parse_color(&blocks.background_color);
+        let highlight = parse_color(&blocks.highlight_background);
+        let color = parse_color(&self.config.syntax_highlighting.text_color).unwrap_or(BLACK);
+        let number_color = parse_color(&blocks.line_number_color).unwrap_or(BLACK);
+        let line_height = size * self.line_height;
+        let style = Style {
+            code: true,
+            ..Style::default()
+        };
+
+        let sources: Vec<&str> = code.split('\n').collect();
+        let diffs = if attrs.diff {
+            DiffLine::classify(&sources)
+        } else {
+            vec![DiffLine::Unchanged; sources.len()]
+        };
+        let gutter = if attrs.line_numbers {
+            let digits = attrs.number(sources.len()).to_string();
+            self.fonts.mono.width(&digits, size) + size
+        } else {
+            0.0
+        };
+        let width = self.content_width() - ctx.indent - pad_left - pad_right - gutter;
+
+        // Each wrapped line with the block line it belongs to, numbered only
+        // where that line starts.
+        let mut lines: Vec<(usize, bool, String)> = Vec::new();
+        for (index, source) in sources.iter().enumerate() {
+            let number = index + 1;
+            let source = source.replace('\t', "    ");
+            if !blocks.word_wrap || self.fonts.mono.width(&source, size) <= width {
+                lines.push((number, true, source));
+                continue;
+            }
+            let mut current = String::new();
+            let mut first = true;
+            for c in source.chars() {
+                if self.fonts.mono.width(&format!("{}{}", current, c), size) > width
+                    && !current.is_empty()
+                {
+                    lines.push((number, first, std::mem::take(&mut current)));
+                    first = false;
+                }
+                current.push(c);
+            }
+            lines.push((number, first, current));
+        }
+
+        let title_height = if attrs.title.is_empty() {
+            0.0
+        } else {
+            line_height + 6.0
+        };
+
+        self.space(margin_top);
+        let total = title_height + lines.len() as f32 * line_height + pad_top + pad_bottom;
+        let available = self.page_height - 2.0 * self.margin;
+        if !blocks.page_break_inside && total <= available {
+            self.reserve(total);
+        } else {
+            self.reserve(title_height + line_height + pad_top);
+        }
+
+        let mut box_x = self.left() + ctx.indent;
+        let box_width = self.content_width() - ctx.indent;
+        if !attrs.title.is_empty() {
+            if let Some(title_background) = parse_color(&blocks.title_background) {
+                self.fill_rect(
+                    box_x,
+                    self.y - title_height,
+                    box_width,
+                    title_height,
+                    title_background,
+                );
+            }
+            let baseline = self.y - 3.0 - (line_height - size) / 2.0 - size * 0.8;
+            let title_color = parse_color(&blocks.title_color).unwrap_or(BLACK);
+            self.draw_text(
+                &attrs.title,
+                style,
+                size,
+                title_color,
+                box_x + 6.0,
+                baseline,
+            );
+            self.y -= title_height;
+        }
+        if let Some(background) = background {
+            self.fill_rect(box_x, self.y - pad_top, box_width, pad_top, background);
+        }
+        self.y -= pad_top;
+
+        for (number, first, line) in &lines {
+            if self.y - line_height < self.margin {
+                self.next_column();
+                box_x = self.left() + ctx.indent;
+            }
+            let diff = diffs[number - 1];
+            let fill = if attrs.is_highlighted(*number) {
+                highlight.or(background)
+            } else {
+                match diff {
+                    DiffLine::Added => parse_color(&blocks.diff_added_background),
+                    DiffLine::Removed => parse_color(&blocks.diff_removed_background),
+                    DiffLine::Header => parse_color(&blocks.diff_header_background),
+                    DiffLine::Unchanged => None,
+                }
+                .or(background)
+            };
+            if let Some(fill) = fill {
+                self.fill_rect(box_x, self.y - line_height, box_width, line_height, fill);
+            }
+            self.draw_bars(ctx, self.y, line_height);
+            let baseline = self.y - (line_height - size) / 2.0 - size * 0.8;
+            if attrs.line_numbers && *first {
+                let label = attrs.number(*number).to_string();
+                let label_x =
+                    box_x + pad_left + gutter - size - self.fonts.mono.width(&label, size);
+                self.draw_text(&label, style, size, number_color, label_x, baseline);
+            }
+            let text_color = if diff == DiffLine::Header {
+                number_color
+            } else {
+                color
+            };
+            self.draw_text(
+                line,
+                style,
+                size,
+                text_color,
+                box_x + pad_left + gutter,
+                baseline,
+            );
+            self.y -= line_height;
+        }
+
+        if let Some(background) = background {
+            self.fill_rect(
+                box_x,
+                self.y - pad_bottom,
+                box_width,
+                pad_bottom,
+                background,
+            );
+        }
+        self.y -= pad_bottom;
+        self.space(margin_bottom);
+    }
+
+    fn list(&mut self, start: Option<u64>, items: &[Vec<Block>], ctx: &Context) {
+        let lists = &self.config.lists;
+        let depth = ctx.list_depth;
+        let bullet = match lists.bullet(depth) {
+            _ if matches!(self.fonts.regular.metrics, Metrics::Builtin(_)) => "-",
+            "disc" => "•",
+            "circle" => "◦",
+            "square" => "▪",
+            "none" => "",
+            other => other,
+        }
+        .to_string();
+        let numbering = lists.numbering(depth).to_string();
+        let legal = lists.legal;
+        let item_spacing = length_pt(&lists.item_spacing, 6.0);
+        let indent = ctx.indent + length_pt(&lists.indent, 24.0);
+
+        self.space(12.0);
+        for (index, item) in items.iter().enumerate() {
+            let mut inner = Context {
+                indent,
+                list_depth: depth + 1,
+                ..ctx.clone()
+            };
+            let marker = match start {
+                Some(first) if legal => {
+                    inner.numbers.push(first + index as u64);
+                    let numbers: Vec<String> = inner.numbers.iter().map(u64::to_string).collect();
+                    format!("{}.", numbers.join("."))
+                }
+                Some(first) => format!("{}.", list_number(first + index as u64, &numbering)),
+                None => bullet.clone(),
+            };
+
+            self.space(item_spacing);
+            self.pending_marker = Some(marker);
+            for block in item {
+                self.block(block, &inner);
+            }
+            self.pending_marker = None;
+            self.space(item_spacing);
+        }
+        self.space(12.0);
+    }
+
+    fn table(
+        &mut self,
+        alignments: &[Alignment],
+        head: &[Vec<Span>],
+        rows: &[Vec<Vec<Span>>],
+        ctx: &Context,
+    ) {
+        let caption = self.table_captions.get(self.tables_seen).cloned().flatten();
+        self.tables_seen += 1;
+
+        let columns = head.len().max(rows.iter().map(Vec::len).max().unwrap_or(0));
+        if columns == 0 {
+            return;
+        }
+
+        let tables = &self.config.tables;
+        let wide = tables.is_wide(columns);
+        let landscape = wide && tables.wide == "landscape";
+        if landscape {
+            self.turn_page(true);
+        }
+
+        let style = self.table_style(alignments, columns, wide, ctx);
+        let (padding_y, padding_x) = style.padding;
+        let total_width = self.content_width() - ctx.indent;
+
+        // Like HTML auto layout: every column gets at least its longest word,
+        // and the remaining width is shared by how much more each one wants.
+        let mut minimum = vec![0.0f32; columns];
+        let mut natural = vec![0.0f32; columns];
+        for (row, text) in std::iter::once((head, style.header))
+            .chain(rows.iter().map(|r| (r.as_slice(), style.body)))
+        {
+            for (column, cell) in row.iter().enumerate() {
+                let single_line = self
+                    .break_lines(cell, text, f32::MAX)
+                    .iter()
+                    .map(|line| line.width)
+                    .fold(0.0, f32::max);
+                minimum[column] =
+                    minimum[column].max(self.longest_word(cell, text) + 2.0 * padding_x);
+                natural[column] = natural[column].max(single_line + 2.0 * padding_x);
+            }
+        }
+
+        let min_sum: f32 = minimum.iter().sum();
+        let natural_sum: f32 = natural.iter().sum();
+        let widths: Vec<f32> = if natural_sum <= total_width {
+            natural
+                .iter()
+                .map(|w| w + (total_width - natural_sum) / columns as f32)
+                .collect()
+        } else if min_sum < total_width {
+            let ratio = (total_width - min_sum) / (natural_sum - min_sum);
+            minimum
+                .iter()
+                .zip(&natural)
+                .map(|(min, max)| min + (max - min) * ratio)
+                .collect()
+        } else {
+            minimum.iter().map(|w| w / min_sum * total_width).collect()
+        };
+
+        let header_row =
+            (!head.is_empty()).then(|| self.table_row(head, style.header, &widths, style.padding));
+        let caption = caption.map_or_else(Vec::new, |caption| {
+            self.wrap_caption(&caption.text, total_width)
+        });
+        let caption_above = self.config.captions.table_caption_above();
+        let caption_align = Align::from_css(&self.config.images.caption_align);
+        let min_row = style.body.size * self.line_height + 2.0 * padding_y;
+
+        self.space(12.0);
+        if caption_above && !caption.is_empty() {
+            // Keep the caption with the first row of the table.
+            let first_row = header_row.as_ref().map_or(0.0, |(_, height)| *height);
+            self.reserve(self.lines_height(&caption) + 6.0 + first_row + min_row);
+            for line in &caption {
+                let x = self.left() + ctx.indent;
+                self.draw_line(line, x, total_width, caption_align, ctx);
+            }
+            self.y -= 6.0;
+        }
+        if let Some((lines, height)) = &header_row {
+            self.reserve(height + min_row);
+            let x = self.left() + ctx.indent;
+            self.draw_table_row(lines, *height, x, &widths, &style, Row::Header);
+        }
+
+        for (index, row) in rows.iter().enumerate() {
+            let (lines, height) = self.table_row(row, style.body, &widths, style.padding);
+            if self.y - self.pending_space - height < self.margin && !self.at_column_top() {
+                self.next_column();
+                // Repeat the header row at the top of every continuation page.
+                if let Some((header_lines, header_height)) = header_row
+                    .as_ref()
+                    .filter(|_| self.config.tables.repeat_header)
+                {
+                    let x = self.left() + ctx.indent;
+                    self.draw_table_row(
+                        header_lines,
+                        *header_height,
+                        x,
+                        &widths,
+                        &style,
+                        Row::Header,
+                    );
+                }
+            }
+            self.reserve(height);
+            let x = self.left() + ctx.indent;
+            let kind = if index % 2 == 1 { Row::Even } else { Row::Odd };
+            self.draw_table_row(&lines, height, x, &widths, &style, kind);
+        }
+
+        if !caption_above && !caption.is_empty() {
+            self.reserve(6.0 + self.lines_height(&caption));
+            self.y -= 6.0;
+            for line in &caption {
+                let x = self.left() + ctx.indent;
+                self.draw_line(line, x, total_width, caption_align, ctx);
+            }
+        }
+
+        if landscape {
+            self.turn_page(false);
+        } else {
+            self.space(12.0);
+        }
+    }
+
+    /// The `tables` settings for one table in points and colours.
+    fn table_style(
+        &self,
+        alignments: &[Alignment],
+        columns: usize,
+        wide: bool,
+        ctx: &Context,
+    ) -> TableStyle {
+        let tables = &self.config.tables;
+        let content_height = self.page_height - 2.0 * self.margin;
+        let mut size = css_size(&tables.font_size, self.body_size, content_height);
+        if wide && tables.wide == "shrink" {
+            size = css_size(&tables.shrink_font_size, size, content_height);
+        }
+
+        let body = TextStyle {
+            size,
+            bold: false,
+            italic: false,
+            color: ctx.color.unwrap_or(BLACK),
+        };
+        let header = TextStyle {
+            bold: true,
+            color: parse_color(&tables.header_color).unwrap_or(body.color),
+            ..body
+        };
+
+        let fallback = Align::from_css(&tables.align);
+        let alignments = (0..columns)
+            .map(|column| match alignments.get(column) {
+                Some(Alignment::Center) if tables.markdown_alignment => Align::Center,
+                Some(Alignment::Right) if tables.markdown_alignment => Align::Right,
+                Some(Alignment::Left) if tables.markdown_alignment => Align::Left,
+                _ => fallback,
+            })
+            .collect();
+        let (top, _, _, left) = box_pt(tables.cell_padding());
+
+        TableStyle {
+            body,
+            header,
+            alignments,
+            padding: (top, left),
+            border: parse_border(&tables.border),
+            header_background: parse_color(&tables.header_background),
+            zebra_background: tables
+                .zebra
+                .then(|| parse_color(&tables.zebra_background))
+                .flatten(),
+        }
+    }
+
+    fn longest_word(&self, spans: &[Span], base: TextStyle) -> f32 {
+        let code_size = length_pt(&self.config.fonts.inline_code_size, base.size);
+        spans
+            .iter()
+            .flat_map(|span| {
+                let style = Style {
+                    bold: span.style.bold || base.bold,
+                    italic: span.style.italic || base.italic,
+                    ..span.style
+                };
+                let size = if style.code { code_size } else { base.size };
+                let font = self.fonts.get(style);
+                split_words(&span.text)
+                    .into_iter()
+                    .map(move |word| font.width(word.trim_end(), size))
+            })
+            .fold(0.0, f32::max)
+    }
+
+    fn table_row(
+        &self,
+        cells: &[Vec<Span>],
+        style: TextStyle,
+        widths: &[f32],
+        (padding_y, padding_x): (f32, f32),
+    ) -> (Vec<Vec<TextLine>>, f32) {
+        let lines: Vec<Vec<TextLine>> = widths
+            .iter()
+            .enumerate()
+            .map(|(column, width)| {
+                let spans = cells.get(column).map(Vec::as_slice).unwrap_or(&[]);
+                self.break_lines(spans, style, width - 2.0 * padding_x)
+            })
+            .collect();
+        let height =
+            lines.iter().map(Vec::len).max().unwrap_or(1) as f32 * style.size * self.line_height
+                + 2.0 * padding_y;
+        (lines, height)
+    }
+
+    fn draw_table_row(
+        &mut self,
+        cells: &[Vec<TextLine>],
+        height: f32,
+        x: f32,
+        widths: &[f32],
+        style: &TableStyle,
+        kind: Row,
+    ) {
+        let (padding_y, padding_x) = style.padding;
+        let background = match kind {
+            Row::Header => style.header_background,
+            Row::Even => style.zebra_background,
+            Row::Odd => None,
+        };
+        let top = self.y;
+        let mut cell_x = x;
+
+        for (column, lines) in cells.iter().enumerate() {
+            if let Some(background) = background {
+                self.fill_rect(cell_x, top - height, widths[column], height, background);
+            }
+            if let Some((thickness, color)) = style.border {
+                self.stroke_rect(
+                    cell_x,
+                    top - height,
+                    widths[column],
+                    height,
+                    thickness,
+                    color,
+                );
+            }
+
+            let inner = widths[column] - 2.0 * padding_x;
+            self.y = top - padding_y;
+            for line in lines {
+                self.draw_line(
+                    line,
+                    cell_x + padding_x,
+                    inner,
+                    style.alignments[column],
+                    &Context::default(),
+                );
+            }
+            cell_x += widths[column];
+        }
+
+        self.y = top - height;
+    }
+
+    /// Loads a local image. Missing ones were already reported while
+    /// resolving, so only unreadable or remote images are warned about here.
+    fn load_image(&self, src: &str) -> Option<DynamicImage> {
+        match self.images.locate(src) {
+            Some(path) => printpdf::image_crate::open(&path)
+                .map_err(|e| eprintln!("Warning: could not load image {}: {}", path.display(), e))
+                .ok(),
+            None if is_external(src) => {
+                eprintln!(
+                    "Warning: remote and inline images are not supported ({})",
+                    src
+                );
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Printed size of an image in points: its natural size at 96 dpi, or the
+    /// size its attributes ask for, shrunk to `images.max_width`/`max_height`.
+    fn image_size(&self, image: &DynamicImage, attrs: &ImageAttrs, available: f32) -> (f32, f32) {
+        let (pixels_wide, pixels_high) = image.dimensions();
+        let natural_width = pixels_wide as f32 * 0.75;
+        let natural_height = pixels_high as f32 * 0.75;
+        let content_height = self.page_height - 2.0 * self.margin;
+
+        let width = attrs
+            .width
+            .as_deref()
+            .map(|w| css_size(w, available, content_height));
+        let height = attrs
+            .height
+            .as_deref()
+            .map(|h| css_size(h, content_height, content_height));
+        let (mut width, mut height) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, width * natural_height / natural_width),
+            (None, Some(height)) => (height * natural_width / natural_height, height),
+            (None, None) => (natural_width, natural_height),
+        };
+
+        let images = &self.config.images;
+        let max_width = css_size(&images.max_width, available, content_height).min(available);
+        let max_height = css_size(&images.max_height, content_height, content_height);
+        let scale = (max_width / width).min(max_height / height).min(1.0);
+        width *= scale;
+        height *= scale;
+
+        (width, height)
+    }
+
+    fn draw_image(&self, image: &DynamicImage, x: f32, top: f32, (width, height): (f32, f32)) {
+        if self.measuring {
+            return;
+        }
+        let (pixels_wide, pixels_high) = image.dimensions();
+        Image::from_dynamic_image(image).add_to_layer(
+            self.layer.clone(),
+            ImageTransform {
+                translate_x: Some(pt_mm(x)),
+                translate_y: Some(pt_mm(top - height)),
+                scale_x: Some(width / pixels_wide as f32),
+                scale_y: Some(height / pixels_high as f32),
+                dpi: Some(72.0),
+                ..ImageTransform::default()
+            },
+        );
+    }
+
+    fn caption_style(&self) -> TextStyle {
+        let images = &self.config.images;
+        TextStyle {
+            size: length_pt(&images.caption_size, 10.0),
+            bold: false,
+            italic: images.caption_style == "italic",
+            color: parse_color(&images.caption_color).unwrap_or(BLACK),
+        }
+    }
+
+    /// Caption lines for `alt` wrapped to `width`, or none if captions are off.
+    fn caption_lines(&self, alt: &str, width: f32) -> Vec<TextLine> {
+        if !self.config.images.show_captions || alt.is_empty() {
+            return Vec::new();
+        }
+        self.wrap_caption(alt, width)
+    }
+
+    fn wrap_caption(&self, text: &str, width: f32) -> Vec<TextLine> {
+        let spans = [Span {
+            text: text.to_string(),
+            style: Style::default(),
+            link: None,
+            image: None,
+        }];
+        self.break_lines(&spans, self.caption_style(), width)
+    }
+
+    fn lines_height(&self, lines: &[TextLine]) -> f32 {
+        lines.iter().map(|line| line.size * self.line_height).sum()
+    }
+
+    fn image_placeholder(&mut self, alt: &str, ctx: &Context) {
+        let spans = vec![Span {
+            text: format!("[{}]", alt),
+            style: Style::default(),
+            link: None,
+            image: None,
+        }];
+        let style = TextStyle {
+            size: self.body_size,
+            bold: false,
+            italic: true,
+            color: ctx.color.unwrap_or(BLACK),
+        };
+        self.paragraph(&spans, style, Align::Center, ctx);
+    }
+
+    /// Images aligned left or right are placed against that edge with their
+    /// caption beneath them; text does not flow around them.
+    fn image(&mut self, picture: &Picture, ctx: &Context) {
+        let Some(image) = self.load_image(&picture.src) else {
+            self.image_placeholder(&picture.alt, ctx);
+            return;
+        };
+
+        let available = self.content_width() - ctx.indent;
+        let (width, height) = self.image_size(&image, &picture.attrs, available);
+        let offset = match picture.attrs.align.as_deref() {
+            Some("left") => 0.0,
+            Some("right") => available - width,
+            _ => (available - width) / 2.0,
+        };
+
+        // Centred captions span the text column, like a block figure;
+        // aligned images keep their caption to the image's width.
+        let (caption_offset, caption_width) = if picture.attrs.align.is_some() {
+            (offset, width)
+        } else {
+            (0.0, available)
+        };
+        let caption = self.caption_lines(&picture.alt, caption_width);
+        let caption_height = if caption.is_empty() {
+            0.0
+        } else {
+            6.0 + self.lines_height(&caption)
+        };
+
+        self.space(12.0);
+        self.reserve(height + caption_height);
+
+        let x = self.left() + ctx.indent;
+        self.draw_image(&image, x + offset, self.y, (width, height));
+        self.y -= height;
+
+        if !caption.is_empty() {
+            self.y -= 6.0;
+            let align = Align::from_css(&self.config.images.caption_align);
+            for line in &caption {
+                let caption_x = self.left() + ctx.indent + caption_offset;
+                self.draw_line(line, caption_x, caption_width, align, ctx);
+            }
+        }
+
+        self.space(12.0);
+    }
+
+    /// Side-by-side images keep their natural (or requested) sizes and shrink
+    /// together when the row is wider than the text column.
+    fn image_row(&mut self, pictures: &[Picture], ctx: &Context) {
+        let loaded: Option<Vec<DynamicImage>> = pictures
+            .iter()
+            .map(|picture| self.load_image(&picture.src))
+            .collect();
+        let Some(loaded) = loaded else {
+            for picture in pictures {
+                self.image(picture, ctx);
+            }
+            return;
+        };
+
+        const GAP: f32 = 12.0;
+        let available = self.content_width() - ctx.indent;
+        let columns = available - GAP * (pictures.len() - 1) as f32;
+
+        let mut sizes: Vec<(f32, f32)> = pictures
+            .iter()
+            .zip(&loaded)
+            .map(|(picture, image)| self.image_size(image, &picture.attrs, columns))
+            .collect();
+        let total: f32 = sizes.iter().map(|(width, _)| width).sum();
+        if total > columns {
+            let scale = columns / total;
+            for (width, height) in &mut sizes {
+                *width *= scale;
+                *height *= scale;
+            }
+        }
+
+        let captions: Vec<Vec<TextLine>> = pictures
+            .iter()
+            .zip(&sizes)
+            .map(|(picture, (width, _))| self.caption_lines(&picture.alt, *width))
+            .collect();
+        let image_height = sizes.iter().map(|(_, height)| *height).fold(0.0, f32::max);
+        let caption_height = captions
+            .iter()
+            .filter(|lines| !lines.is_empty())
+            .map(|lines| 6.0 + self.lines_height(lines))
+            .fold(0.0, f32::max);
+
+        self.space(12.0);
+        self.reserve(image_height + caption_height);
+
+        let row_width =
+            sizes.iter().map(|(width, _)| width).sum::<f32>() + GAP * (pictures.len() - 1) as f32;
+        let top = self.y;
+        let mut x = self.left() + ctx.indent + (available - row_width) / 2.0;
+        let mut bottom = top - image_height;
+        let align = Align::from_css(&self.config.images.caption_align);
+
+        for ((image, size), caption) in loaded.iter().zip(&sizes).zip(&captions) {
+            self.draw_image(image, x, top, *size);
+
+            if !caption.is_empty() {
+                self.y = top - size.1 - 6.0;
+                for line in caption {
+                    self.draw_line(line, x, size.0, align, ctx);
+                }
+                bottom = bottom.min(self.y);
+            }
+            x += size.0 + GAP;
+        }
+
+        self.y = bottom;
+        self.space(12.0);
+    }
+
+    // ----- Page furniture -----
+
+    /// The cover fills the first page on its own, with the notice at the
+    /// foot of it, and the body starts on the next.
+    fn cover(&mut self, cover: &Cover) {
+        let options = &self.config.title_page.cover;
+        let align = Align::from_css(&options.align);
+        let logo_at_bottom = options.logo_position == "bottom";
+        let text = |text: &str| {
+            vec![Span {
+                text: text.to_string(),
+                style: Style::default(),
+                link: None,
+                image: None,
+            }]
+        };
+        let style = |size: f32, bold: bool, italic: bool| TextStyle {
+            size,
+            bold,
+            italic,
+            color: BLACK,
+        };
+        let ctx = Context::default();
+
+        self.y = self.page_height - self.margin;
+        if !logo_at_bottom {
+            self.cover_logo(&cover.logo, align);
+        }
+
+        if !cover.title.is_empty() {
+            let size = length_pt(&options.title_size, 32.0);
+            self.space(size);
+            self.paragraph(&text(&cover.title), style(size, true, false), align, &ctx);
+        }
+        if !cover.subtitle.is_empty() {
+            let size = length_pt(&options.subtitle_size, 18.0);
+            self.space(size / 2.0);
+            self.paragraph(
+                &text(&cover.subtitle),
+                style(size, false, false),
+                align,
+                &ctx,
+            );
+        }
+        self.space(2.0 * self.body_size);
+
+        for author in &cover.authors {
+            self.space(self.body_size / 2.0);
+            let body = style(self.body_size, false, false);
+            self.paragraph(&text(&author.name), body, align, &ctx);
+            if !author.affiliation.is_empty() {
+                let affiliation = style(self.body_size * 0.9, false, true);
+                self.paragraph(&text(&author.affiliation), affiliation, align, &ctx);
+            }
+        }
+        for line in [&cover.date, &cover.version] {
+            if !line.is_empty() {
+                self.space(self.body_size / 2.0);
+                let body = style(self.body_size, false, false);
+                self.paragraph(&text(line), body, align, &ctx);
+            }
+        }
+        if !cover.abstract_text.is_empty() {
+            self.space(3.0 * self.body_size);
+            let body = style(self.body_size, false, false);
+            self.paragraph(&text(&cover.abstract_text), body, Align::Left, &ctx);
+        }
+
+        if logo_at_bottom {
+            self.space(2.0 * self.body_size);
+            self.cover_logo(&cover.logo, align);
+        }
+
+        if !cover.notice.is_empty() {
+            let size = self.body_size * 0.8;
+            let lines = self.break_lines(
+                &text(&cover.notice),
+                style(size, false, true),
+                self.content_width(),
+            );
+            // Only move down to the foot if the notice still fits there.
+            let height = self.lines_height(&lines);
+            if self.y - self.pending_space - height >= self.margin {
+                self.y = self.margin + height;
+                self.pending_space = 0.0;
+            }
+            for line in &lines {
+                self.draw_line(line, self.margin, self.content_width(), align, &ctx);
+            }
+        }
+
+        self.new_page();
+    }
+
+    fn cover_logo(&mut self, src: &str, align: Align) {
+        if src.is_empty() {
+            return;
+        }
+        let Some(image) = self.load_image(src) else {
+            return;
+        };
+        let attrs = ImageAttrs {
+            width: Some(self.config.title_page.cover.logo_width.clone()),
+            ..ImageAttrs::default()
+        };
+        let available = self.content_width();
+        let (width, height) = self.image_size(&image, &attrs, available);
+
+        self.reserve(height);
+        let x = match align {
+            Align::Left => self.margin,
+            Align::Center => self.margin + (available - width) / 2.0,
+            Align::Right => self.margin + available - width,
+        };
+        self.draw_image(&image, x, self.y, (width, height));
+        self.y -= height;
+        self.space(2.0 * self.body_size);
+    }
+
+    /// The extracted header sits in the first page's top margin, like the
+    /// `@top-center` box in the HTML stylesheet.
+    fn draw_running_header(&self, text: &str) {
+        let size = self.body_size;
+        let width = self.fonts.regular.width(text, size);
+        let baseline = self.page_height - self.first_top_margin / 2.0;
+        self.draw_text(
+            text,
+            Style::default(),
+            size,
+            BLACK,
+            (self.page_width - width) / 2.0,
+            baseline,
+        );
+    }
+
+    /// Numbers every page but the first in the bottom-right corner.
+    fn draw_page_numbers(&self) {
+        let size = self.body_size;
+        for (index, page) in self.pages.iter().enumerate().skip(1) {
+            let layer = self.doc.get_page(*page).add_layer("Page number");
+            let number = (index + 1).to_string();
+            let width = self.fonts.regular.width(&number, size);
+            let (page_width, _) = self.page_size(self.landscape_pages.contains(&index));
+            layer.set_fill_color(rgb(BLACK));
+            layer.use_text(
+                number,
+                size,
+                pt_mm(page_width - self.margin - width),
+                pt_mm(self.margin / 2.0),
+                &self.fonts.regular.pdf,
+            );
+        }
+    }
+}
+
+fn push_fragment(
+    line: &mut TextLine,
+    text: &str,
+    style: Style,
+    size: f32,
+    color: (f32, f32, f32),
+    link: &Option<String>,
+    width: f32,
+) {
+    line.size = line.size.max(size);
+    line.width += width;
+
+    if let Some(last) = line.fragments.last_mut() {
+        if last.style == style && last.size == size && last.color == color && last.link == *link {
+            last.text.push_str(text);
+            last.width += width;
+            return;
+        }
+    }
+
+    line.fragments.push(Fragment {
+        text: text.to_string(),
+        style,
+        size,
+        color,
+        link: link.clone(),
+        width,
+    });
+}
+
+/// Splits text into words that keep their trailing whitespace.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = false;
+
+    for (index, c) in text.char_indices() {
+        if c.is_whitespace() {
+            in_space = true;
+        } else if in_space {
+            words.push(&text[start..index]);
+            start = index;
+            in_space = false;
+        }
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PrintOptions;
+    use crate::config::get_default_config;
+    use crate::directives::expand_directives;
+    use crate::markdown::parse_document;
+    use crate::staging::StagingDir;
+    use printpdf::lopdf;
+    use std::path::Path;
+
+    /// Renders `markdown` with the built-in fonts.
+    fn render(markdown: &str, cover: Option<&Cover>) -> lopdf::Document {
+        let config = get_default_config();
+        let markdown = expand_directives(markdown).unwrap();
+        let document = parse_document(&markdown, &config, None);
+        let images = ImageResolver::new(&config.images, Path::new("."));
+        let options = PrintOptions::from_config(&config);
+        let staging = StagingDir::create().unwrap();
+        let job = RenderJob {
+            html_path: &staging.path().join("document.html"),
+            document: &document,
+            header_text: "",
+            cover,
+            base_dir: Path::new("."),
+            images: &images,
+            work_dir: staging.path(),
+            options: &options,
+        };
+
+        let pdf = NativeBackend::new(config.clone())
+            .render_with(&job, Fonts::builtin)
+            .unwrap();
+        lopdf::Document::load_mem(&pdf).unwrap()
+    }
+
+    fn page_texts(pdf: &lopdf::Document) -> Vec<String> {
+        pdf.get_pages()
+            .keys()
+            .map(|&page| pdf.extract_text(&[page]).unwrap())
+            .collect()
+    }
+
+    /// Titles and page numbers, in page order.
+    fn bookmarks(pdf: &lopdf::Document) -> Vec<(String, usize)> {
+        let mut bookmarks: Vec<_> = pdf
+            .get_toc()
+            .unwrap()
+            .toc
+            .into_iter()
+            .map(|entry| (entry.title, entry.page))
+            .collect();
+        bookmarks.sort_by_key(|(_, page)| *page);
+        bookmarks
+    }
+
+    fn is_landscape(pdf: &lopdf::Document, page: u32) -> bool {
+        let page = pdf.get_pages()[&page];
+        let media_box = pdf
+            .get_dictionary(page)
+            .unwrap()
+            .get(b"MediaBox")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        let size = |i: usize| media_box[i].as_float().unwrap();
+        size(2) > size(3)
+    }
+
+    #[test]
+    fn lays_out_pages_and_bookmarks() {
+        let rows: String = (1..=80)
+            .map(|row| format!("| Row {} | {} |\n", row, row * 10))
+            .collect();
+        let markdown = format!(
+            "# Intro\n\nSome text.\n\n\\newpage\n\n# Data\n\n\
+             <!-- keep-together -->\nKept together.\n<!-- /keep-together -->\n\n\
+             | Name | Value |\n|------|-------|\n{}\n## Wide\n\n\
+             <!-- landscape -->\nTurned.\n<!-- /landscape -->\n\n\
+             <!-- columns: 2 -->\nIn columns.\n<!-- /columns -->\n\n\
+             ```rust\nlet long = \"{}\";\n```\n",
+            rows,
+            "x".repeat(300)
+        );
+        let pdf = render(&markdown, None);
+        let texts = page_texts(&pdf);
+
+        assert_eq!(texts.len(), 8);
+        assert!(texts[0].starts_with("Intro\nSome text.\n"));
+        assert!(texts[1].starts_with("Data\nKept together.\nName\nValue\nRow 1\n"));
+        // The table runs over four pages, its header on top of each.
+        for text in &texts[2..5] {
+            assert!(text.starts_with("Name\nValue\nRow "), "{}", text);
+        }
+        assert!(texts[4].contains("Row 80\n800\n"));
+        assert!(is_landscape(&pdf, 7));
+        assert!(!is_landscape(&pdf, 8));
+        // The long code line wraps rather than running off the page.
+        assert!(texts[7].starts_with("In columns.\nlet long"));
+        assert_eq!(texts[7].matches('x').count(), 300);
+        assert!(texts[7].lines().filter(|line| line.contains('x')).count() > 1);
+
+        assert_eq!(
+            bookmarks(&pdf),
+            [
+                ("Intro".to_string(), 1),
+                ("Data".to_string(), 2),
+                ("Wide".to_string(), 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn puts_the_cover_on_a_page_of_its_own() {
+        let cover = Cover {
+            title: "Handbook".to_string(),
+            subtitle: String::new(),
+            authors: Vec::new(),
+            date: String::new(),
+            version: String::new(),
+            logo: String::new(),
+            abstract_text: String::new(),
+            notice: String::new(),
+        };
+        let pdf = render("# Intro\n\nSome text.\n", Some(&cover));
+        let texts = page_texts(&pdf);
+
+        assert_eq!(texts.len(), 2);
+        assert!(texts[0].starts_with("Handbook\n"));
+        assert!(texts[1].starts_with("Intro\nSome text.\n"));
+        assert_eq!(bookmarks(&pdf), [("Intro".to_string(), 2)]);
+    }
+
+    #[test]
+    fn measures_with_the_advance_table() {
+        let metrics = Metrics::TrueType(HashMap::from([('a', 0.5), ('W', 1.0)]));
+        assert_eq!(metrics.width("aW", 10.0), 15.0);
+        // No glyph: half an em.
+        assert_eq!(metrics.width("\u{2603}", 10.0), 5.0);
+        assert_eq!(metrics.width("", 10.0), 0.0);
+    }
+
+    #[test]
+    fn measures_builtin_fonts_by_character_count() {
+        assert_eq!(Metrics::Builtin(0.6).width("abcd", 10.0), 24.0);
+    }
+
+    #[test]
+    fn rejects_data_that_is_not_a_font() {
+        assert!(Metrics::true_type(b"not a font").is_err());
+    }
+
+    #[test]
+    fn numbers_list_items_in_css_styles() {
+        assert_eq!(list_number(3, "decimal"), "3");
+        assert_eq!(list_number(28, "lower-alpha"), "ab");
+        assert_eq!(list_number(2, "upper-latin"), "B");
+        assert_eq!(list_number(9, "lower-roman"), "ix");
+        assert_eq!(list_number(4000, "upper-roman"), "4000");
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#f00"), Some((1.0, 0.0, 0.0)));
+        assert_eq!(parse_color("#0000FF80 !important"), Some((0.0, 0.0, 1.0)));
+        assert_eq!(parse_color("white"), Some((1.0, 1.0, 1.0)));
+        assert_eq!(parse_color("#aéééa"), None);
+        assert_eq!(parse_color("#12345g"), None);
+        assert_eq!(parse_color("#fff8"), Some((1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn parses_table_borders() {
+        assert_eq!(parse_border("1px solid #000"), Some((0.75, BLACK)));
+        assert_eq!(
+            parse_border("2pt dashed #ff0000"),
+            Some((2.0, (1.0, 0.0, 0.0)))
+        );
+        assert_eq!(parse_border("none"), None);
+        assert_eq!(parse_border("0 solid"), None);
+    }
+
+    #[test]
+    fn prefers_declared_font_files() {
+        let staging = StagingDir::create().unwrap();
+        let font = staging.path().join("Body.ttf");
+        fs::write(&font, b"font").unwrap();
+        let face = |path: &Path| FontFaceConfig {
+            family: "Body".to_string(),
+            path: path.display().to_string(),
+            weight: "normal".to_string(),
+            style: "normal".to_string(),
+        };
+        let files = [
+            face(&staging.path().join("Body.woff2")),
+            face(&font),
+            face(&staging.path().join("Missing.ttf")),
+        ];
+
+        let faces = resolve_family(&files, "body", &[]);
+        let paths: Vec<_> = faces.iter().map(|face| face.path.clone()).collect();
+        assert_eq!(paths, [font]);
+    }
+}
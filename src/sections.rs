@@ -0,0 +1,458 @@
+//! Section numbering, anchors and tables of contents for headings.
+//!
+//! Numbers are written into the heading text during the markdown pass, so
+//! they show up wherever the heading does: in the body, in PDF bookmarks, in
+//! the table of contents and in `@sec:label` cross-references. Every heading
+//! also gets an id, either its own `{#custom-id}` or a GitHub-style slug of
+//! its title, so `#fragment` links written for GitHub keep working in the PDF.
+
+use crate::config::Config;
+use pulldown_cmark::{Event, LinkType, Tag, TagEnd};
+use std::collections::{HashMap, HashSet};
+
+pub(crate) const TOC: &str = "[[toc]]";
+
+/// A heading that can be referred to, by its id.
+pub struct Section {
+    pub id: String,
+    pub level: usize,
+    /// "3.2.1" for numbered headings, otherwise empty.
+    pub number: String,
+    pub title: String,
+}
+
+/// Gives every heading without an explicit `{#id}` a GitHub-style slug of its
+/// title that is not in `used_ids` yet. Returns, for every heading, the id it
+/// would have had in a document of its own (which is what links inside that
+/// document use) mapped to the id it got.
+pub fn assign_heading_ids(
+    events: &mut [Event],
+    used_ids: &mut HashSet<String>,
+) -> HashMap<String, String> {
+    // Slugs must not take an id that a heading asks for explicitly.
+    let explicit: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+    used_ids.extend(explicit.iter().cloned());
+
+    let mut ids: HashMap<String, String> =
+        explicit.iter().map(|id| (id.clone(), id.clone())).collect();
+    let mut local_ids = explicit;
+    let mut index = 0;
+
+    while index < events.len() {
+        if !matches!(events[index], Event::Start(Tag::Heading { id: None, .. })) {
+            index += 1;
+            continue;
+        }
+        let end = events[index..]
+            .iter()
+            .position(|event| matches!(event, Event::End(TagEnd::Heading(_))))
+            .map_or(events.len(), |offset| index + offset);
+
+        let local = unique_id(
+            &slug(&heading_text(&events[index + 1..end])),
+            &mut local_ids,
+        );
+        let id = unique_id(&local, used_ids);
+        if let Event::Start(Tag::Heading { id: heading_id, .. }) = &mut events[index] {
+            *heading_id = Some(id.clone().into());
+        }
+        ids.insert(local, id);
+        index = end;
+    }
+
+    ids
+}
+
+/// Prefixes headings with their section number when numbering is enabled,
+/// and returns every heading that has an id.
+pub fn number_headings<'a>(
+    events: Vec<Event<'a>>,
+    config: &Config,
+) -> (Vec<Event<'a>>, Vec<Section>) {
+    let config = &config.headings.numbering;
+    let first_level = if config.h1_chapter { 1 } else { 2 };
+    let mut counters = [0usize; 6];
+    let mut output = Vec::with_capacity(events.len());
+    let mut sections = Vec::new();
+    // Id, level, number and title of the heading being read.
+    let mut current: Option<(Option<String>, usize, String, String)> = None;
+
+    for event in events {
+        match &event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                let level = *level as usize;
+                let numbered =
+                    config.enabled && level >= first_level && level <= config.max_level as usize;
+                let mut number = String::new();
+
+                if numbered {
+                    let depth = level - first_level;
+                    counters[depth] += 1;
+                    counters[depth + 1..].fill(0);
+                    number = format_number(&counters[..=depth], &config.format);
+                }
+
+                let id = id.as_ref().map(|id| id.to_string());
+                current = Some((id, level, number.clone(), String::new()));
+                output.push(event);
+                if !number.is_empty() {
+                    output.push(Event::Text(
+                        format!("{}{}", number, config.separator).into(),
+                    ));
+                }
+                continue;
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, _, title)) = &mut current {
+                    title.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((Some(id), level, number, title)) = current.take() {
+                    let title = title.trim().to_string();
+                    sections.push(Section {
+                        id,
+                        level,
+                        number,
+                        title,
+                    });
+                }
+            }
+            _ => {}
+        }
+        output.push(event);
+    }
+
+    (output, sections)
+}
+
+/// Replaces every paragraph reading `[[toc]]` with a nested list of links to
+/// the headings down to `toc.max_level`.
+pub fn insert_toc<'a>(
+    events: Vec<Event<'a>>,
+    sections: &[Section],
+    config: &Config,
+) -> Vec<Event<'a>> {
+    let mut output = Vec::with_capacity(events.len());
+    let mut index = 0;
+
+    while index < events.len() {
+        if events[index] == Event::Start(Tag::Paragraph) {
+            let end = events[index..]
+                .iter()
+                .position(|event| *event == Event::End(TagEnd::Paragraph))
+                .map_or(events.len(), |offset| index + offset);
+            if heading_text(&events[index + 1..end]).trim() == TOC {
+                output.extend(toc_events(sections, config));
+                index = end + 1;
+                continue;
+            }
+        }
+        output.push(events[index].clone());
+        index += 1;
+    }
+
+    output
+}
+
+fn toc_events<'a>(sections: &[Section], config: &Config) -> Vec<Event<'a>> {
+    let mut events = vec![Event::Html("<nav class=\"toc\">\n".into())];
+    if !config.toc.title.is_empty() {
+        events.extend([
+            Event::Start(Tag::Paragraph),
+            Event::Start(Tag::Strong),
+            Event::Text(config.toc.title.clone().into()),
+            Event::End(TagEnd::Strong),
+            Event::End(TagEnd::Paragraph),
+        ]);
+    }
+
+    // Level of every open list and whether its last item is still open.
+    let mut open: Vec<(usize, bool)> = Vec::new();
+    for section in sections
+        .iter()
+        .filter(|section| section.level <= config.toc.max_level as usize)
+    {
+        while let Some(&(level, item_open)) = open.last() {
+            if level <= section.level || open.len() == 1 {
+                break;
+            }
+            if item_open {
+                events.push(Event::End(TagEnd::Item));
+            }
+            events.push(Event::End(TagEnd::List(false)));
+            open.pop();
+        }
+
+        match open.last_mut() {
+            Some((level, item_open)) if *level >= section.level => {
+                if *item_open {
+                    events.push(Event::End(TagEnd::Item));
+                }
+                *item_open = true;
+            }
+            // A deeper level nests inside the item that is still open.
+            _ => {
+                events.push(Event::Start(Tag::List(None)));
+                open.push((section.level, true));
+            }
+        }
+
+        let text = if section.number.is_empty() {
+            section.title.clone()
+        } else {
+            format!(
+                "{}{}{}",
+                section.number, config.headings.numbering.separator, section.title
+            )
+        };
+        events.extend([
+            Event::Start(Tag::Item),
+            Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url: format!("#{}", section.id).into(),
+                title: "".into(),
+                id: "".into(),
+            }),
+            Event::Text(text.into()),
+            Event::End(TagEnd::Link),
+        ]);
+    }
+
+    while let Some((_, item_open)) = open.pop() {
+        if item_open {
+            events.push(Event::End(TagEnd::Item));
+        }
+        events.push(Event::End(TagEnd::List(false)));
+    }
+    events.push(Event::Html("</nav>\n".into()));
+
+    events
+}
+
+fn heading_text(events: &[Event]) -> String {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// GitHub's heading anchors: lowercase, with punctuation dropped and spaces
+/// turned into hyphens.
+fn slug(title: &str) -> String {
+    let slug: String = title
+        .trim()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect();
+
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+/// `id`, or `id-1`, `id-2`, ... if it is taken, as GitHub numbers repeats.
+fn unique_id(id: &str, used_ids: &mut HashSet<String>) -> String {
+    let mut candidate = id.to_string();
+    let mut repeat = 0;
+    while used_ids.contains(&candidate) {
+        repeat += 1;
+        candidate = format!("{}-{}", id, repeat);
+    }
+    used_ids.insert(candidate.clone());
+    candidate
+}
+
+/// Formats counters like `format`: each alphanumeric character picks the
+/// style of one level (`1`, `A`, `a`, `I` or `i`) and whatever sits between
+/// them is the delimiter. The last style and delimiter repeat for deeper
+/// levels, so `A.1` numbers the third level `B.2.1`.
+fn format_number(counters: &[usize], format: &str) -> String {
+    let mut styles = Vec::new();
+    let mut delimiters = Vec::new();
+    let mut delimiter = String::new();
+
+    for c in format.chars() {
+        if c.is_ascii_alphanumeric() {
+            if !styles.is_empty() {
+                delimiters.push(std::mem::take(&mut delimiter));
+            }
+            styles.push(c);
+        } else {
+            delimiter.push(c);
+        }
+    }
+    if styles.is_empty() {
+        styles.push('1');
+    }
+    if delimiters.is_empty() {
+        delimiters.push(".".to_string());
+    }
+
+    let mut number = String::new();
+    for (depth, &value) in counters.iter().enumerate() {
+        if depth > 0 {
+            number.push_str(&delimiters[(depth - 1).min(delimiters.len() - 1)]);
+        }
+        let style = styles[depth.min(styles.len() - 1)];
+        number.push_str(&format_counter(value, style));
+    }
+    // Trailing punctuation in the format ("1.") is kept.
+    number.push_str(&delimiter);
+    number
+}
+
+fn format_counter(value: usize, style: char) -> String {
+    match style {
+        'A' => letters(value),
+        'a' => letters(value).to_lowercase(),
+        'I' => roman(value),
+        'i' => roman(value).to_lowercase(),
+        _ => value.to_string(),
+    }
+}
+
+/// 1 -> A, 26 -> Z, 27 -> AA.
+pub(crate) fn letters(mut value: usize) -> String {
+    if value == 0 {
+        return "0".to_string();
+    }
+
+    let mut letters = Vec::new();
+    while value > 0 {
+        value -= 1;
+        letters.push((b'A' + (value % 26) as u8) as char);
+        value /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Roman numerals, falling back to decimal outside 1-3999 as CSS does.
+pub(crate) fn roman(mut value: usize) -> String {
+    if value == 0 || value > 3999 {
+        return value.to_string();
+    }
+
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    let mut roman = String::new();
+    for (amount, numeral) in NUMERALS {
+        while value >= amount {
+            roman.push_str(numeral);
+            value -= amount;
+        }
+    }
+    roman
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::parse_events;
+
+    fn heading_ids(markdown: &str, used_ids: &mut HashSet<String>) -> Vec<String> {
+        let mut events = parse_events(markdown);
+        assign_heading_ids(&mut events, used_ids);
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Start(Tag::Heading { id, .. }) => {
+                    Some(id.as_deref().unwrap_or("").to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slugs_titles_like_github() {
+        assert_eq!(slug("Getting Started"), "getting-started");
+        assert_eq!(slug("What's new in v2.0?"), "whats-new-in-v20");
+        assert_eq!(slug("snake_case and-dashes"), "snake_case-and-dashes");
+        assert_eq!(slug("Über uns"), "über-uns");
+        assert_eq!(slug("!!!"), "section");
+    }
+
+    #[test]
+    fn numbers_repeated_headings() {
+        let ids = heading_ids(
+            "# Setup\n## Setup\n## Setup\n# `code` *title*\n",
+            &mut HashSet::new(),
+        );
+        assert_eq!(ids, ["setup", "setup-1", "setup-2", "code-title"]);
+    }
+
+    #[test]
+    fn keeps_explicit_ids_and_avoids_them() {
+        let ids = heading_ids("# Intro\n# Other {#intro}\n", &mut HashSet::new());
+        assert_eq!(ids, ["intro-1", "intro"]);
+    }
+
+    #[test]
+    fn avoids_ids_used_by_earlier_documents() {
+        let mut used_ids = HashSet::new();
+        heading_ids("# Intro\n", &mut used_ids);
+
+        let mut events = parse_events("# Intro\n[back](#intro)\n");
+        let ids = assign_heading_ids(&mut events, &mut used_ids);
+        assert_eq!(ids["intro"], "intro-1");
+    }
+
+    #[test]
+    fn formats_letters() {
+        assert_eq!(letters(1), "A");
+        assert_eq!(letters(26), "Z");
+        assert_eq!(letters(27), "AA");
+        assert_eq!(letters(52), "AZ");
+        assert_eq!(letters(703), "AAA");
+        assert_eq!(letters(0), "0");
+    }
+
+    #[test]
+    fn formats_roman_numerals() {
+        assert_eq!(roman(1), "I");
+        assert_eq!(roman(4), "IV");
+        assert_eq!(roman(9), "IX");
+        assert_eq!(roman(14), "XIV");
+        assert_eq!(roman(1994), "MCMXCIV");
+        assert_eq!(roman(3999), "MMMCMXCIX");
+        assert_eq!(roman(4000), "4000");
+        assert_eq!(roman(0), "0");
+    }
+
+    #[test]
+    fn formats_section_numbers() {
+        assert_eq!(format_number(&[2, 1], "1.1"), "2.1");
+        assert_eq!(format_number(&[2, 1, 3], "A.1"), "B.1.3");
+        assert_eq!(format_number(&[3], "I."), "III.");
+        assert_eq!(format_number(&[1, 2], "i-a"), "i-b");
+        assert_eq!(format_number(&[1, 2], ""), "1.2");
+    }
+}
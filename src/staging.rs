@@ -0,0 +1,182 @@
+//! A private scratch directory for each conversion.
+//!
+//! Intermediate files (the generated HTML, the browser profile) live in a
+//! freshly created directory under the system temp dir, so concurrent runs
+//! never share state and nothing is written next to the user's documents.
+//! The directory is removed when the `StagingDir` is dropped, which also
+//! covers panics, and by the Ctrl-C handler for interrupted runs. The handler
+//! likewise kills running renderers and removes half-written PDFs.
+
+use crate::child;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Staging directories and partial output files that still exist, so the
+/// Ctrl-C handler can remove them.
+static LIVE_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+const CREATE_ATTEMPTS: u32 = 16;
+const REMOVE_ATTEMPTS: u32 = 5;
+const REMOVE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+pub struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    pub fn create() -> io::Result<StagingDir> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+
+        for attempt in 0..CREATE_ATTEMPTS {
+            let path =
+                env::temp_dir().join(format!("mandy-{}-{:x}-{}", process::id(), nanos, attempt));
+
+            // `create_dir` fails if the directory already exists, so a
+            // successful call means nobody else is using this path.
+            match fs::create_dir(&path) {
+                Ok(()) => {
+                    live_paths().push(path.clone());
+                    return Ok(StagingDir { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "could not find an unused temporary directory name",
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        remove_path(&self.path);
+        live_paths().retain(|path| path != &self.path);
+    }
+}
+
+/// Writes `contents` to `path` through a sibling `.partial` file that is
+/// renamed into place once complete, so an interrupted or failed write never
+/// leaves a truncated PDF behind or destroys the previous one.
+pub fn write_output(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    live_paths().push(partial.clone());
+    let result = fs::write(&partial, contents).and_then(|()| fs::rename(&partial, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    live_paths().retain(|live| live != &partial);
+
+    result
+}
+
+/// Kills running renderers, removes staging directories and partial output,
+/// and exits when the user presses Ctrl-C.
+pub fn install_interrupt_handler() {
+    let result = ctrlc::set_handler(|| {
+        eprintln!("\nInterrupted, cleaning up temporary files...");
+        child::kill_all();
+        let paths = live_paths().clone();
+        for path in paths {
+            remove_path(&path);
+        }
+        process::exit(130);
+    });
+
+    if let Err(e) = result {
+        eprintln!("Warning: could not install Ctrl-C handler: {}", e);
+    }
+}
+
+fn live_paths() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    // A panic while holding the lock must not stop later cleanup.
+    LIVE_PATHS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Browsers can keep profile files locked for a moment after exiting
+/// (notably on Windows), so removal is retried briefly before giving up.
+fn remove_path(path: &Path) {
+    for attempt in 1..=REMOVE_ATTEMPTS {
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+
+        match removed {
+            Ok(()) => return,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) if attempt == REMOVE_ATTEMPTS => {
+                eprintln!(
+                    "Warning: could not remove temporary file '{}': {}",
+                    path.display(),
+                    e
+                );
+            }
+            Err(_) => thread::sleep(REMOVE_RETRY_DELAY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_a_fresh_directory_per_run() {
+        let first = StagingDir::create().unwrap();
+        let second = StagingDir::create().unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().is_dir());
+        assert!(first.path().starts_with(env::temp_dir()));
+    }
+
+    #[test]
+    fn removes_the_directory_when_dropped() {
+        let staging = StagingDir::create().unwrap();
+        let path = staging.path().to_path_buf();
+        fs::create_dir(path.join("browser-profile")).unwrap();
+        fs::write(path.join("document.html"), "<p>hi</p>").unwrap();
+
+        drop(staging);
+        assert!(!path.exists());
+        assert!(!live_paths().contains(&path));
+    }
+
+    #[test]
+    fn replaces_output_without_leaving_partial_files() {
+        let staging = StagingDir::create().unwrap();
+        let output = staging.path().join("out.pdf");
+        fs::write(&output, "old").unwrap();
+
+        write_output(&output, b"%PDF-new").unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"%PDF-new");
+        assert!(!staging.path().join("out.pdf.partial").exists());
+    }
+
+    #[test]
+    fn fails_cleanly_when_the_directory_is_missing() {
+        let staging = StagingDir::create().unwrap();
+        let output = staging.path().join("missing-dir").join("out.pdf");
+        assert!(write_output(&output, b"%PDF").is_err());
+        assert!(!output.exists());
+    }
+}
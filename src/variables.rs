@@ -0,0 +1,255 @@
+//! `{{ name }}` substitution in markdown text.
+//!
+//! Values come from, lowest precedence first, `variables.values` in the
+//! config, the document's front matter and `--var name=value` on the command
+//! line; `{{ env.NAME }}` reads an environment variable. Code blocks and code
+//! spans are left alone unless `variables.in_code` is set, and a backslash in
+//! front (`\{{ name }}`) keeps a placeholder as written.
+
+use crate::markdown::parser_options;
+use pulldown_cmark::{Event, Parser, Tag};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ops::Range;
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+const FRONT_MATTER: &str = "---";
+const ENV_PREFIX: &str = "env.";
+
+/// Splits a leading `---` block of `name: value` lines off the markdown.
+/// Only flat scalar values become variables; nested entries and lists are
+/// skipped.
+pub fn split_front_matter(markdown: &str) -> (HashMap<String, String>, &str) {
+    let mut values = HashMap::new();
+
+    let Some(rest) = markdown.strip_prefix(FRONT_MATTER).and_then(|rest| {
+        rest.strip_prefix('\n')
+            .or_else(|| rest.strip_prefix("\r\n"))
+    }) else {
+        return (values, markdown);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == FRONT_MATTER || line == "..." {
+            return (values, &rest[offset..]);
+        }
+        if line.is_empty() || line.starts_with([' ', '\t', '-', '#']) {
+            continue;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            break;
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .or_else(|| {
+                value
+                    .strip_prefix('\'')
+                    .and_then(|value| value.strip_suffix('\''))
+            })
+            .unwrap_or(value);
+        values.insert(name.trim().to_string(), value.to_string());
+    }
+
+    // Without a closing line, or with lines that are not `name: value`, it
+    // was a thematic break rather than front matter.
+    (HashMap::new(), markdown)
+}
+
+/// Replaces every `{{ name }}` in `markdown` with its value. Unknown names
+/// are reported and left as written.
+pub fn substitute_variables(
+    markdown: &str,
+    values: &HashMap<String, String>,
+    in_code: bool,
+) -> String {
+    let mut substitution = Substitution {
+        values,
+        reported: HashSet::new(),
+    };
+    if in_code {
+        return substitution.apply(markdown);
+    }
+
+    let code: Vec<Range<usize>> = Parser::new_ext(markdown, parser_options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None,
+        })
+        .collect();
+
+    let mut output = String::with_capacity(markdown.len());
+    let mut copied = 0;
+    for range in code {
+        output.push_str(&substitution.apply(&markdown[copied..range.start]));
+        output.push_str(&markdown[range.clone()]);
+        copied = range.end;
+    }
+    output.push_str(&substitution.apply(&markdown[copied..]));
+
+    output
+}
+
+struct Substitution<'a> {
+    values: &'a HashMap<String, String>,
+    reported: HashSet<String>,
+}
+
+impl Substitution<'_> {
+    fn apply(&mut self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find(OPEN) {
+            let after = &rest[start + OPEN.len()..];
+            let Some(length) = after.find(CLOSE) else {
+                break;
+            };
+            let name = after[..length].trim();
+            let end = start + OPEN.len() + length + CLOSE.len();
+
+            if !is_name(name) {
+                output.push_str(&rest[..start + OPEN.len()]);
+                rest = after;
+                continue;
+            }
+
+            if let Some(before) = rest[..start].strip_suffix('\\') {
+                output.push_str(before);
+                output.push_str(&rest[start..end]);
+            } else {
+                output.push_str(&rest[..start]);
+                match self.value(name) {
+                    Some(value) => output.push_str(&value),
+                    None => output.push_str(&rest[start..end]),
+                }
+            }
+            rest = &rest[end..];
+        }
+
+        output.push_str(rest);
+        output
+    }
+
+    fn value(&mut self, name: &str) -> Option<String> {
+        let value = match name.strip_prefix(ENV_PREFIX) {
+            Some(variable) => env::var(variable).ok(),
+            None => self.values.get(name).cloned(),
+        };
+        if value.is_none() && self.reported.insert(name.to_string()) {
+            eprintln!("Warning: undefined variable '{}' left as written", name);
+        }
+        value
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_front_matter() {
+        let markdown = "---\ntitle: \"A: B\"\nauthor: 'Ann'\ntags:\n  - x\n# note\n---\n# Body\n";
+        let (front, body) = split_front_matter(markdown);
+        assert_eq!(
+            front,
+            values(&[("title", "A: B"), ("author", "Ann"), ("tags", "")])
+        );
+        assert_eq!(body, "# Body\n");
+
+        let (front, body) = split_front_matter("---\r\nversion: 2\r\n...\r\nText\r\n");
+        assert_eq!(front, values(&[("version", "2")]));
+        assert_eq!(body, "Text\r\n");
+    }
+
+    #[test]
+    fn leaves_thematic_breaks_alone() {
+        for markdown in [
+            "---\nNot front matter\n---\n",
+            "---\nkey: value\n",
+            "Text\n---\n",
+        ] {
+            let (front, body) = split_front_matter(markdown);
+            assert!(front.is_empty(), "{:?}", markdown);
+            assert_eq!(body, markdown);
+        }
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let values = values(&[("name", "Mandy"), ("v.major", "2")]);
+        assert_eq!(
+            substitute_variables(
+                "{{ name }} {{v.major}} {{ missing }} \\{{ name }}\n",
+                &values,
+                false
+            ),
+            "Mandy 2 {{ missing }} {{ name }}\n"
+        );
+        assert_eq!(
+            substitute_variables("{{ not a name }} {{ name\n", &values, false),
+            "{{ not a name }} {{ name\n"
+        );
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        env::set_var("MANDY_VARIABLES_TEST", "from env");
+        assert_eq!(
+            substitute_variables("{{ env.MANDY_VARIABLES_TEST }}", &HashMap::new(), false),
+            "from env"
+        );
+    }
+
+    #[test]
+    fn leaves_code_alone_unless_asked() {
+        let values = values(&[("name", "Mandy")]);
+        let markdown = "`{{ name }}` ``a ` {{ name }}`` {{ name }}\n\n```\n{{ name }}\n```\n";
+        assert_eq!(
+            substitute_variables(markdown, &values, false),
+            "`{{ name }}` ``a ` {{ name }}`` Mandy\n\n```\n{{ name }}\n```\n"
+        );
+        assert_eq!(
+            substitute_variables(markdown, &values, true),
+            "`Mandy` ``a ` Mandy`` Mandy\n\n```\nMandy\n```\n"
+        );
+    }
+
+    #[test]
+    fn leaves_indented_code_blocks_alone() {
+        let values = values(&[("name", "Mandy")]);
+        assert_eq!(
+            substitute_variables("{{ name }}\n\n    {{ name }}\n", &values, false),
+            "Mandy\n\n    {{ name }}\n"
+        );
+    }
+
+    #[test]
+    fn leaves_code_spans_across_lines_alone() {
+        let values = values(&[("name", "Mandy")]);
+        assert_eq!(
+            substitute_variables("{{ name }} `a\n{{ name }}` {{ name }}\n", &values, false),
+            "Mandy `a\n{{ name }}` Mandy\n"
+        );
+    }
+}